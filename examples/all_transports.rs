@@ -6,14 +6,17 @@ pub fn main() {
 
     let settings_http = HttpTransportSettings {
         address: "127.0.0.1:9001".into(),
+        ..Default::default()
     };
     let transport_http = HttpTransport::new(settings_http);
     let settings_tcp = TcpTransportSettings {
         address: "127.0.0.1:9002".into(),
+        ..Default::default()
     };
     let transport_tcp = TcpTransport::new(settings_tcp);
     let settings_ws = WsTransportSettings {
         address: "127.0.0.1:9003".into(),
+        ..Default::default()
     };
     let transport_ws = WsTransport::new(settings_ws);
 
@@ -31,6 +34,8 @@ pub fn main() {
         )))
     });
 
+    server.add_typed_method("add", |(a, b): (i64, i64)| futures::future::ok(a + b));
+
     server.start().unwrap();
 
     ctrlc::set_handler(|| std::process::exit(0)).unwrap();
@@ -0,0 +1,88 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+use witty_jsonrpc::{
+    jsonrpc_core::{BoxFuture, Params, Result},
+    jsonrpc_pubsub::{Sink, Subscriber, SubscriptionId},
+    prelude::*,
+};
+
+#[witty_jsonrpc::rpc]
+pub trait GreeterApi {
+    #[rpc(name = "say_hello")]
+    fn say_hello(&self, name: String, shout: Option<bool>) -> Result<String>;
+
+    #[rpc(name = "say_goodbye")]
+    fn say_goodbye(&self) -> BoxFuture<Result<&'static str>>;
+
+    #[rpc(subscription = "greetings", subscribe, name = "greetings_subscribe")]
+    fn subscribe(&self, subscriber: Subscriber);
+
+    #[rpc(
+        subscription = "greetings",
+        unsubscribe,
+        name = "greetings_unsubscribe"
+    )]
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<bool>;
+}
+
+#[derive(Default)]
+struct Greeter {
+    next_id: AtomicU64,
+    sinks: Mutex<Vec<(SubscriptionId, Sink)>>,
+}
+
+impl GreeterApi for Greeter {
+    fn say_hello(&self, name: String, shout: Option<bool>) -> Result<String> {
+        let greeting = format!("Hello, {}!", name);
+        for (_, sink) in self.sinks.lock().unwrap().iter() {
+            sink.notify(Params::Array(vec![Value::from(greeting.clone())]))
+                .ok();
+        }
+
+        Ok(if shout.unwrap_or_default() {
+            greeting.to_uppercase()
+        } else {
+            greeting
+        })
+    }
+
+    fn say_goodbye(&self) -> BoxFuture<Result<&'static str>> {
+        Box::pin(async { Ok("Goodbye, World!") })
+    }
+
+    fn subscribe(&self, subscriber: Subscriber) {
+        let id = SubscriptionId::Number(self.next_id.fetch_add(1, Ordering::Relaxed));
+        if let Ok(sink) = subscriber.assign_id(id.clone()) {
+            self.sinks.lock().unwrap().push((id, sink));
+        }
+    }
+
+    fn unsubscribe(&self, id: SubscriptionId) -> Result<bool> {
+        let mut sinks = self.sinks.lock().unwrap();
+        let count = sinks.len();
+        sinks.retain(|(sink_id, _)| *sink_id != id);
+
+        Ok(sinks.len() < count)
+    }
+}
+
+pub fn main() {
+    let settings = TcpTransportSettings {
+        address: "127.0.0.1:9001".into(),
+        ..Default::default()
+    };
+    let transport = TcpTransport::new(settings);
+    let mut server = WittyMonoServer::from_transport(transport);
+
+    Greeter::default().register_rpc(&mut server);
+
+    server.start().unwrap();
+
+    ctrlc::set_handler(|| std::process::exit(0)).unwrap();
+    loop {
+        std::thread::sleep(std::time::Duration::from_secs(10));
+    }
+}
@@ -6,10 +6,12 @@ pub fn main() {
 
     let settings_a = TcpTransportSettings {
         address: "127.0.0.1:9001".into(),
+        ..Default::default()
     };
     let transport_a = TcpTransport::new(settings_a);
     let settings_b = TcpTransportSettings {
         address: "127.0.0.1:9002".into(),
+        ..Default::default()
     };
     let transport_b = TcpTransport::new(settings_b);
 
@@ -5,10 +5,12 @@ pub fn main() {
 
     let settings_http = HttpTransportSettings {
         address: "127.0.0.1:9001".into(),
+        ..Default::default()
     };
     let transport_http = HttpTransport::new(settings_http);
     let settings_tcp = TcpTransportSettings {
         address: "127.0.0.1:9002".into(),
+        ..Default::default()
     };
     let transport_tcp = TcpTransport::new(settings_tcp);
 
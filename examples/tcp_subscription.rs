@@ -7,6 +7,7 @@ pub fn main() {
 
     let settings = TcpTransportSettings {
         address: "127.0.0.1:9001".into(),
+        ..Default::default()
     };
     let transport = TcpTransport::new(settings);
     let mut server = WittyMonoServer::from_transport(transport);
@@ -0,0 +1,340 @@
+use std::{
+    fmt,
+    io::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use futures::future::Either;
+use jsonrpc_core::{
+    middleware::{NoopCallFuture, NoopFuture},
+    serde_json::{self, json},
+    Call, Error, ErrorCode, Metadata, Middleware, Output, Params, Request, Response, Value,
+};
+
+use crate::handler::ConnectionMetadata;
+
+/// The `log` target that `LogSink` writes access log entries to, so that they can be filtered or
+/// routed separately from the rest of the logs.
+pub const ACCESS_LOG_TARGET: &str = "witty_jsonrpc::access";
+
+/// Whether a call succeeded or failed, as recorded in the access log.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AccessLogOutcome {
+    /// The call completed with a result.
+    Success,
+    /// The call completed with an error, including rejections by rate and concurrency limits.
+    Error {
+        /// The JSON-RPC error code.
+        code: i64,
+        /// The JSON-RPC error message.
+        message: String,
+    },
+}
+
+impl AccessLogOutcome {
+    /// Derive the outcome of a call from its result.
+    pub fn from_result<T>(result: &Result<T, Error>) -> Self {
+        match result {
+            Ok(_) => Self::Success,
+            Err(error) => Self::Error {
+                code: ErrorCode::code(&error.code),
+                message: error.message.clone(),
+            },
+        }
+    }
+}
+
+/// A record of a single JSON-RPC call.
+#[derive(Clone, Debug)]
+pub struct AccessLogEntry {
+    /// When the call was received.
+    pub timestamp: SystemTime,
+    /// The name of the transport that the call came through, if known.
+    pub transport: Option<&'static str>,
+    /// The address of the remote peer, if known.
+    pub peer_addr: Option<SocketAddr>,
+    /// The session that the call belongs to, if the transport is session based.
+    pub session_id: Option<u64>,
+    /// The name of the method that was called.
+    pub method: String,
+    /// The size in bytes of the serialized parameters.
+    pub params_size: usize,
+    /// How long it took to complete the call, including any time spent waiting in queues.
+    pub duration: Duration,
+    /// Whether the call succeeded or failed.
+    pub outcome: AccessLogOutcome,
+}
+
+impl AccessLogEntry {
+    /// Represent this entry as a flat JSON object.
+    pub fn to_json(&self) -> Value {
+        let timestamp_ms = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis())
+            .unwrap_or_default();
+        let (error_code, error_message) = match &self.outcome {
+            AccessLogOutcome::Success => (None, None),
+            AccessLogOutcome::Error { code, message } => (Some(*code), Some(message)),
+        };
+
+        json!({
+            "timestamp_ms": u64::try_from(timestamp_ms).unwrap_or(u64::MAX),
+            "transport": self.transport,
+            "peer": self.peer_addr.map(|addr| addr.to_string()),
+            "session_id": self.session_id,
+            "method": self.method,
+            "params_size": self.params_size,
+            "duration_us": u64::try_from(self.duration.as_micros()).unwrap_or(u64::MAX),
+            "outcome": if error_code.is_none() { "success" } else { "error" },
+            "error_code": error_code,
+            "error_message": error_message,
+        })
+    }
+}
+
+impl fmt::Display for AccessLogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} session={} method={} params_size={} duration={:?} ",
+            self.transport.unwrap_or("-"),
+            self.peer_addr
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|| String::from("-")),
+            self.session_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| String::from("-")),
+            self.method,
+            self.params_size,
+            self.duration,
+        )?;
+
+        match &self.outcome {
+            AccessLogOutcome::Success => write!(f, "outcome=success"),
+            AccessLogOutcome::Error { code, message } => {
+                write!(f, "outcome=error code={} message={:?}", code, message)
+            }
+        }
+    }
+}
+
+/// A destination for access log entries.
+///
+/// This is implemented for any `Fn(&AccessLogEntry)` closure, so simple sinks need no dedicated
+/// type.
+pub trait AccessLogSink: Send + Sync {
+    /// Record a single entry. This is called right after every call completes, so it should not
+    /// block for long.
+    fn record(&self, entry: &AccessLogEntry);
+}
+
+impl<F> AccessLogSink for F
+where
+    F: Fn(&AccessLogEntry) + Send + Sync,
+{
+    fn record(&self, entry: &AccessLogEntry) {
+        self(entry)
+    }
+}
+
+/// An access log sink that emits every entry through the `log` crate, under the
+/// `ACCESS_LOG_TARGET` target.
+#[derive(Clone, Debug)]
+pub struct LogSink {
+    level: log::Level,
+}
+
+impl LogSink {
+    /// Create a sink that logs entries at the provided level.
+    pub fn new(level: log::Level) -> Self {
+        Self { level }
+    }
+}
+
+impl Default for LogSink {
+    fn default() -> Self {
+        Self::new(log::Level::Info)
+    }
+}
+
+impl AccessLogSink for LogSink {
+    fn record(&self, entry: &AccessLogEntry) {
+        log::log!(target: ACCESS_LOG_TARGET, self.level, "{}", entry);
+    }
+}
+
+/// An access log sink that writes every entry as a line of JSON into any writer, e.g. a file.
+#[derive(Debug)]
+pub struct JsonLinesSink<W> {
+    writer: Mutex<W>,
+}
+
+impl<W> JsonLinesSink<W>
+where
+    W: Write + Send,
+{
+    /// Create a sink that writes into the provided writer.
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W> AccessLogSink for JsonLinesSink<W>
+where
+    W: Write + Send,
+{
+    fn record(&self, entry: &AccessLogEntry) {
+        let mut writer = self.writer.lock().unwrap();
+        let written = serde_json::to_writer(&mut *writer, &entry.to_json())
+            .map_err(std::io::Error::from)
+            .and_then(|_| writer.write_all(b"\n"))
+            .and_then(|_| writer.flush());
+
+        if let Err(error) = written {
+            log::warn!("Failed to write access log entry: {}", error);
+        }
+    }
+}
+
+/// The set of sinks that a server sends access log entries to.
+///
+/// Servers share this with their transports, which put it in front of everything else as a JSON-RPC
+/// middleware, so that every call gets an entry, including those to unknown methods and those that
+/// the limits of the transports reject.
+#[derive(Clone, Default)]
+pub struct AccessLog {
+    sinks: Arc<RwLock<Vec<Box<dyn AccessLogSink>>>>,
+}
+
+impl AccessLog {
+    /// Add a sink that will receive entries for every call from now on.
+    pub(crate) fn add_sink(&self, sink: Box<dyn AccessLogSink>) {
+        self.sinks.write().unwrap().push(sink);
+    }
+
+    /// Start recording a call to `method`.
+    ///
+    /// Returns `None` if there are no sinks, so that no work is wasted on measuring the size of
+    /// the parameters.
+    fn begin<M, P>(&self, method: &str, meta: Option<&M>, params_size: P) -> Option<PendingEntry>
+    where
+        M: ConnectionMetadata,
+        P: FnOnce() -> usize,
+    {
+        if self.sinks.read().unwrap().is_empty() {
+            return None;
+        }
+
+        Some(PendingEntry {
+            access_log: self.clone(),
+            started: Instant::now(),
+            entry: AccessLogEntry {
+                timestamp: SystemTime::now(),
+                transport: meta.and_then(ConnectionMetadata::transport),
+                peer_addr: meta.and_then(ConnectionMetadata::peer_addr),
+                session_id: meta.and_then(ConnectionMetadata::session_id),
+                method: String::from(method),
+                params_size: params_size(),
+                duration: Duration::ZERO,
+                outcome: AccessLogOutcome::Success,
+            },
+        })
+    }
+}
+
+/// An access log entry for a call that has not completed yet.
+struct PendingEntry {
+    access_log: AccessLog,
+    started: Instant,
+    entry: AccessLogEntry,
+}
+
+impl PendingEntry {
+    /// Complete the entry with the result of the call, and send it to every sink.
+    fn finish<T>(mut self, result: &Result<T, Error>) {
+        self.entry.duration = self.started.elapsed();
+        self.entry.outcome = AccessLogOutcome::from_result(result);
+
+        for sink in self.access_log.sinks.read().unwrap().iter() {
+            sink.record(&self.entry);
+        }
+    }
+}
+
+impl<M> Middleware<M> for AccessLog
+where
+    M: Metadata + ConnectionMetadata,
+{
+    type Future = NoopFuture;
+    type CallFuture = NoopCallFuture;
+
+    fn on_request<F, X>(&self, request: Request, meta: M, next: F) -> Either<Self::Future, X>
+    where
+        F: Fn(Request, M) -> X + Send + Sync,
+        X: futures::Future<Output = Option<Response>> + Send + 'static,
+    {
+        let calls = match &request {
+            Request::Single(call) => std::slice::from_ref(call),
+            Request::Batch(calls) => calls.as_slice(),
+        };
+        // Notifications have no id, as they get no response
+        let entries = calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::MethodCall(call) => self
+                    .begin(&call.method, Some(&meta), || params_size(&call.params))
+                    .map(|entry| (Some(call.id.clone()), entry)),
+                Call::Notification(notification) => self
+                    .begin(&notification.method, Some(&meta), || {
+                        params_size(&notification.params)
+                    })
+                    .map(|entry| (None, entry)),
+                Call::Invalid { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        if entries.is_empty() {
+            return Either::Right(next(request, meta));
+        }
+
+        let response = next(request, meta);
+
+        Either::Left(Box::pin(async move {
+            let response = response.await;
+            let mut outputs = match &response {
+                Some(Response::Single(output)) => vec![output],
+                Some(Response::Batch(outputs)) => outputs.iter().collect(),
+                None => Vec::new(),
+            };
+            for (id, entry) in entries {
+                let output = id.and_then(|id| {
+                    let position = outputs.iter().position(|output| output.id() == &id)?;
+
+                    Some(outputs.remove(position))
+                });
+                let result = match output {
+                    Some(Output::Failure(failure)) => Err(failure.error.clone()),
+                    _ => Ok(()),
+                };
+                entry.finish(&result);
+            }
+
+            response
+        }))
+    }
+}
+
+/// Calculate the size in bytes of the parameters of a call once serialized, which is zero if
+/// there are none.
+fn params_size(params: &Params) -> usize {
+    match params {
+        Params::None => 0,
+        params => serde_json::to_vec(params)
+            .map(|bytes| bytes.len())
+            .unwrap_or_default(),
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+};
+
+use futures::{
+    channel::oneshot,
+    future::{self, BoxFuture, Either},
+    FutureExt,
+};
+use jsonrpc_core::{Error, ErrorCode};
+
+/// The JSON-RPC error code used for rejecting calls when the server is too busy to take them.
+pub const SERVER_BUSY_ERROR_CODE: i64 = -32008;
+
+/// Settings for limiting how many calls can be executing at the same time.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConcurrencyLimit {
+    /// How many calls can be executing at the same time.
+    pub max_in_flight: NonZeroUsize,
+    /// How many calls can be waiting for others to complete before new ones start to be rejected.
+    pub max_queued: usize,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    in_flight: usize,
+    queue: VecDeque<oneshot::Sender<ConcurrencyPermit>>,
+}
+
+/// A semaphore with a bounded queue of waiters, used for shedding load when too many calls are
+/// executing at the same time.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimiter {
+    limit: ConcurrencyLimit,
+    state: Arc<Mutex<LimiterState>>,
+}
+
+impl ConcurrencyLimiter {
+    /// Create a new limiter that enforces the provided limit.
+    pub fn new(limit: ConcurrencyLimit) -> Self {
+        Self {
+            limit,
+            state: Default::default(),
+        }
+    }
+
+    /// Try to get permission to execute a call.
+    ///
+    /// If the limit has been reached, the returned future resolves once another call completes.
+    /// If the queue of waiting calls is also full, a "server busy" error is returned right away.
+    pub fn acquire(&self) -> Result<impl Future<Output = Result<ConcurrencyPermit, Error>>, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.in_flight < self.limit.max_in_flight.get() {
+            state.in_flight += 1;
+
+            return Ok(Either::Left(future::ready(Ok(ConcurrencyPermit {
+                limiter: Some(self.clone()),
+            }))));
+        }
+
+        state.queue.retain(|waiter| !waiter.is_canceled());
+        if state.queue.len() >= self.limit.max_queued {
+            return Err(server_busy_error());
+        }
+
+        let (sender, receiver) = oneshot::channel();
+        state.queue.push_back(sender);
+
+        Ok(Either::Right(
+            receiver.map(|permit| permit.map_err(|_| server_busy_error())),
+        ))
+    }
+
+    /// Hand the slot of a completed call over to the first live waiter, or free it if there is
+    /// none.
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        while let Some(waiter) = state.queue.pop_front() {
+            let permit = ConcurrencyPermit {
+                limiter: Some(self.clone()),
+            };
+
+            match waiter.send(permit) {
+                Ok(()) => return,
+                // The waiter is gone, so the permit must not release the slot again
+                Err(mut permit) => {
+                    permit.limiter.take();
+                }
+            }
+        }
+
+        state.in_flight = state.in_flight.saturating_sub(1);
+    }
+}
+
+/// Proof that a call was given permission to execute by a `ConcurrencyLimiter`.
+///
+/// The slot taken by the call is released once this is dropped.
+#[derive(Debug)]
+pub struct ConcurrencyPermit {
+    limiter: Option<ConcurrencyLimiter>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        if let Some(limiter) = self.limiter.take() {
+            limiter.release();
+        }
+    }
+}
+
+/// The global and per-method concurrency limiters of a server.
+#[derive(Debug, Default)]
+pub(crate) struct ConcurrencyLimiters {
+    pub(crate) global: Option<ConcurrencyLimiter>,
+    pub(crate) methods: HashMap<String, ConcurrencyLimiter>,
+}
+
+impl ConcurrencyLimiters {
+    /// Try to get permission to execute a call to `method` from both the per-method and the global
+    /// limiters.
+    ///
+    /// The global limiter is only asked once the per-method one grants permission, so that calls
+    /// waiting for a busy method do not take global slots away from calls to other methods.
+    pub(crate) fn acquire(
+        &self,
+        method: &str,
+    ) -> Result<BoxFuture<'static, Result<Vec<ConcurrencyPermit>, Error>>, Error> {
+        let method_permit = self.methods.get(method).map(|l| l.acquire()).transpose()?;
+        let global = self.global.clone();
+
+        Ok(Box::pin(async move {
+            let mut permits = Vec::with_capacity(2);
+            if let Some(permit) = method_permit {
+                permits.push(permit.await?);
+            }
+            if let Some(global) = global {
+                permits.push(global.acquire()?.await?);
+            }
+
+            Ok(permits)
+        }))
+    }
+}
+
+/// Build the JSON-RPC error that is returned when a call is rejected for lack of capacity.
+pub fn server_busy_error() -> Error {
+    log::debug!("Rejecting JSON-RPC call because the server is busy");
+
+    Error {
+        code: ErrorCode::ServerError(SERVER_BUSY_ERROR_CODE),
+        message: String::from("Server busy"),
+        data: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    fn limit(max_in_flight: usize, max_queued: usize) -> ConcurrencyLimit {
+        ConcurrencyLimit {
+            max_in_flight: NonZeroUsize::new(max_in_flight).unwrap(),
+            max_queued,
+        }
+    }
+
+    fn is_busy(error: &Error) -> bool {
+        error.code == ErrorCode::ServerError(SERVER_BUSY_ERROR_CODE)
+    }
+
+    #[test]
+    fn limiter_queues_calls_above_the_limit_and_rejects_the_rest() {
+        let limiter = ConcurrencyLimiter::new(limit(1, 1));
+
+        let permit = block_on(limiter.acquire().unwrap()).unwrap();
+        let mut queued = Box::pin(limiter.acquire().unwrap());
+        assert!(is_busy(&limiter.acquire().err().unwrap()));
+        assert!((&mut queued).now_or_never().is_none());
+
+        // The slot of a completed call goes to the first waiter
+        drop(permit);
+        let permit = (&mut queued).now_or_never().unwrap().unwrap();
+        assert!(limiter.acquire().is_ok());
+        drop(permit);
+    }
+
+    #[test]
+    fn limiter_skips_waiters_that_are_gone() {
+        let limiter = ConcurrencyLimiter::new(limit(1, 2));
+
+        let permit = block_on(limiter.acquire().unwrap()).unwrap();
+        drop(limiter.acquire().unwrap());
+        let mut queued = Box::pin(limiter.acquire().unwrap());
+
+        drop(permit);
+        let permit = (&mut queued).now_or_never().unwrap().unwrap();
+        drop(permit);
+
+        // Every slot is free again
+        let _first = block_on(limiter.acquire().unwrap()).unwrap();
+        assert!(limiter.acquire().unwrap().now_or_never().is_none());
+    }
+
+    #[test]
+    fn calls_waiting_for_a_method_do_not_take_global_slots() {
+        let limiters = ConcurrencyLimiters {
+            global: Some(ConcurrencyLimiter::new(limit(2, 0))),
+            methods: [(String::from("slow"), ConcurrencyLimiter::new(limit(1, 1)))]
+                .into_iter()
+                .collect(),
+        };
+
+        let slow = block_on(limiters.acquire("slow").unwrap()).unwrap();
+        let mut queued_slow = limiters.acquire("slow").unwrap();
+        assert!((&mut queued_slow).now_or_never().is_none());
+
+        let fast = block_on(limiters.acquire("fast").unwrap());
+        assert!(fast.is_ok());
+
+        drop(slow);
+        assert!(queued_slow.now_or_never().unwrap().is_ok());
+    }
+}
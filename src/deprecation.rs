@@ -0,0 +1,129 @@
+#[cfg(feature = "metrics")]
+use std::sync::{Arc, RwLock};
+
+use jsonrpc_core::{BoxFuture, Value};
+
+use crate::metadata::MethodMetadata;
+
+/// The `log` target that calls to deprecated method names are reported under, so that they can be
+/// filtered or routed separately from the rest of the logs.
+pub const DEPRECATION_LOG_TARGET: &str = "witty_jsonrpc::deprecation";
+
+/// The member that the deprecation note is added under in the results of calls to deprecated
+/// method names, if enabled.
+pub const DEPRECATION_MEMBER: &str = "deprecation";
+
+/// Settings for a deprecated alias of a method, i.e. an old name that still works but that
+/// clients should stop using.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Deprecation {
+    /// Anything else callers should know, e.g. when the old name will stop working.
+    pub note: Option<String>,
+    /// Whether to tell callers about the deprecation in the responses themselves, by adding a
+    /// `deprecation` member to the results that are JSON objects. Other results are left as is.
+    pub annotate_response: bool,
+}
+
+impl Deprecation {
+    /// The message that tells callers of a deprecated alias which name to use instead.
+    pub fn message(&self, alias: &str, target: &str) -> String {
+        match &self.note {
+            Some(note) => format!("{} is deprecated, use {} instead. {}", alias, target, note),
+            None => format!("{} is deprecated, use {} instead.", alias, target),
+        }
+    }
+
+    /// Add the deprecation message to the result of a call, if enabled and possible.
+    fn annotate(&self, alias: &str, target: &str, result: Value) -> Value {
+        match result {
+            Value::Object(mut object) if self.annotate_response => {
+                object.insert(
+                    String::from(DEPRECATION_MEMBER),
+                    Value::from(self.message(alias, target)),
+                );
+
+                Value::Object(object)
+            }
+            result => result,
+        }
+    }
+}
+
+/// Reports calls to deprecated method names through the `log` crate and, if enabled, Prometheus
+/// metrics.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DeprecationLog {
+    #[cfg(feature = "metrics")]
+    metrics: Arc<RwLock<Option<crate::metrics::Metrics>>>,
+}
+
+impl DeprecationLog {
+    /// Start counting calls to deprecated method names in some metrics.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn set_metrics(&self, metrics: crate::metrics::Metrics) {
+        *self.metrics.write().unwrap() = Some(metrics);
+    }
+
+    /// Report a call to a deprecated alias of a method.
+    pub(crate) fn record(&self, alias: &str, target: &str, deprecation: &Deprecation) {
+        log::warn!(
+            target: DEPRECATION_LOG_TARGET,
+            "Deprecated JSON-RPC method called: {}",
+            deprecation.message(alias, target)
+        );
+
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &*self.metrics.read().unwrap() {
+            metrics.record_deprecated_call(alias);
+        }
+    }
+}
+
+/// Describe an alias just like its target, if this is described already, but with a description
+/// that points at the target instead.
+pub(crate) fn alias_metadata(
+    alias: &str,
+    target: &str,
+    target_metadata: Option<&MethodMetadata>,
+    deprecation: Option<&Deprecation>,
+) -> MethodMetadata {
+    let mut metadata = target_metadata
+        .cloned()
+        .unwrap_or_else(|| MethodMetadata::new(target));
+    metadata.name = String::from(alias);
+    metadata.description = Some(match deprecation {
+        Some(deprecation) => deprecation.message(alias, target),
+        None => format!("Alias of {}.", target),
+    });
+    metadata.deprecated |= deprecation.is_some();
+    metadata.subscription = None;
+
+    metadata
+}
+
+/// Answer a call to an alias with the call to its target, which is answered with a method not found
+/// error if the target does not exist.
+pub(crate) fn call_alias(
+    alias: &str,
+    target: &str,
+    deprecation: Option<&Deprecation>,
+    deprecation_log: &DeprecationLog,
+    call: Option<BoxFuture<jsonrpc_core::Result<Value>>>,
+) -> BoxFuture<jsonrpc_core::Result<Value>> {
+    if let Some(deprecation) = deprecation {
+        deprecation_log.record(alias, target, deprecation);
+    }
+    let deprecation = deprecation.cloned();
+    let alias = String::from(alias);
+    let target = String::from(target);
+
+    Box::pin(async move {
+        let call = call.ok_or_else(jsonrpc_core::Error::method_not_found)?;
+        let result = call.await?;
+
+        Ok(match deprecation {
+            Some(deprecation) => deprecation.annotate(&alias, &target, result),
+            None => result,
+        })
+    })
+}
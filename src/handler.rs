@@ -1,29 +1,51 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    fmt::Debug,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use futures::channel::mpsc::UnboundedSender;
-use jsonrpc_core::{MetaIoHandler, Metadata, RpcMethodSimple};
+use jsonrpc_core::{MetaIoHandler, Metadata, RpcMethod, RpcMethodSimple};
 use jsonrpc_pubsub::{PubSubHandler, PubSubMetadata, SubscribeRpcMethod, UnsubscribeRpcMethod};
 
+use crate::trace_context::TraceContext;
+
 /// A wrapper around `jsonrpc_core`'s own `Session`, providing some convenience methods and
 /// `impl Default::default`.
 #[derive(Clone, Debug, Default)]
 pub struct Session {
     inner: Option<Arc<jsonrpc_pubsub::Session>>,
+    id: Option<u64>,
+    peer_addr: Option<SocketAddr>,
+    transport: Option<&'static str>,
+    trace_context: Option<TraceContext>,
+    api_version: Option<String>,
 }
 
 impl Session {
     /// A mocked session, i.e. created from an mpsc channel that is not wired to anything.
     pub fn mock() -> Session {
         let (sender, _) = futures::channel::mpsc::unbounded();
-        let inner = Some(Arc::from(jsonrpc_pubsub::Session::new(sender)));
 
-        Self { inner }
+        Self::from(Arc::from(jsonrpc_pubsub::Session::new(sender)))
     }
 }
 
 impl From<Arc<jsonrpc_pubsub::Session>> for Session {
     fn from(value: Arc<jsonrpc_pubsub::Session>) -> Self {
-        Session { inner: Some(value) }
+        static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(0);
+
+        Session {
+            inner: Some(value),
+            id: Some(NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed)),
+            peer_addr: None,
+            transport: None,
+            trace_context: None,
+            api_version: None,
+        }
     }
 }
 
@@ -35,16 +57,90 @@ impl PubSubMetadata for Session {
     }
 }
 
+impl ConnectionMetadata for Session {
+    fn session_id(&self) -> Option<u64> {
+        self.id
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    fn set_peer_addr(&mut self, peer_addr: SocketAddr) {
+        self.peer_addr = Some(peer_addr);
+    }
+
+    fn transport(&self) -> Option<&'static str> {
+        self.transport
+    }
+
+    fn set_transport(&mut self, transport: &'static str) {
+        self.transport = Some(transport);
+    }
+
+    fn trace_context(&self) -> Option<&TraceContext> {
+        self.trace_context.as_ref()
+    }
+
+    fn set_trace_context(&mut self, trace_context: TraceContext) {
+        self.trace_context = Some(trace_context);
+    }
+
+    fn api_version(&self) -> Option<&str> {
+        self.api_version.as_deref()
+    }
+
+    fn set_api_version(&mut self, version: &str) {
+        self.api_version = Some(String::from(version));
+    }
+}
+
+/// Trait for metadata types that can tell which session and remote peer a JSON-RPC message comes
+/// from.
+pub trait ConnectionMetadata {
+    /// A number that uniquely identifies the session, if the transport is session based.
+    fn session_id(&self) -> Option<u64>;
+
+    /// The address of the remote peer, if the transport knows about it.
+    fn peer_addr(&self) -> Option<SocketAddr>;
+
+    /// Attach the address of the remote peer.
+    fn set_peer_addr(&mut self, peer_addr: SocketAddr);
+
+    /// The name of the transport that the message came through, e.g. `"tcp"`.
+    fn transport(&self) -> Option<&'static str>;
+
+    /// Attach the name of the transport that the message came through.
+    fn set_transport(&mut self, transport: &'static str);
+
+    /// The W3C trace context that the message was sent with, if any.
+    fn trace_context(&self) -> Option<&TraceContext>;
+
+    /// Attach the W3C trace context that the message was sent with.
+    fn set_trace_context(&mut self, trace_context: TraceContext);
+
+    /// The version of the API that the message asks for, if any.
+    fn api_version(&self) -> Option<&str>;
+
+    /// Attach the version of the API that the message asks for.
+    fn set_api_version(&mut self, version: &str);
+}
+
 /// Trait that abstracts away different implementations of IO handlers.
 pub trait Handler {
     /// The type to use as the metadata for the IO handler. Especially relevant for PubSub.
-    type Metadata: PubSubMetadata + Unpin + Debug;
+    type Metadata: PubSubMetadata + ConnectionMetadata + Unpin + Debug;
 
     /// Add a JSON-RPC method.
     fn add_method<F>(&mut self, name: &str, method: F)
     where
         F: RpcMethodSimple;
 
+    /// Add a JSON-RPC method that also gets access to the metadata of every request.
+    fn add_method_with_meta<F>(&mut self, name: &str, method: F)
+    where
+        F: RpcMethod<Self::Metadata>;
+
     /// Add a JSON-RPC subscription.
     fn add_subscription<F, G>(
         &mut self,
@@ -74,7 +170,7 @@ pub trait Handler {
 
 impl<M> Handler for PubSubHandler<M>
 where
-    M: PubSubMetadata + Unpin + Debug + From<Arc<jsonrpc_pubsub::Session>>,
+    M: PubSubMetadata + ConnectionMetadata + Unpin + Debug + From<Arc<jsonrpc_pubsub::Session>>,
 {
     type Metadata = M;
 
@@ -85,6 +181,13 @@ where
         MetaIoHandler::add_method(self, name, method)
     }
 
+    fn add_method_with_meta<F>(&mut self, name: &str, method: F)
+    where
+        F: RpcMethod<M>,
+    {
+        MetaIoHandler::add_method_with_meta(self, name, method)
+    }
+
     fn add_subscription<F, G>(
         &mut self,
         notification: &str,
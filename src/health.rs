@@ -0,0 +1,53 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, RwLock,
+};
+
+use crate::panics::catch_panic_sync;
+
+/// A user supplied check that tells whether the application behind a server is ready to take
+/// requests, e.g. whether it is done syncing or has a working database connection.
+pub type ReadinessCheck = Box<dyn Fn() -> bool + Send + Sync>;
+
+/// Tracks whether a server is ready to take requests, i.e. whether all of its transports are
+/// running and its readiness check, if any, passes.
+///
+/// Servers share this with their transports, so that it can be reported by the readiness endpoint
+/// of the HTTP transport.
+#[derive(Clone, Default)]
+pub struct Readiness {
+    transports_running: Arc<AtomicBool>,
+    check: Arc<RwLock<Option<ReadinessCheck>>>,
+}
+
+impl Readiness {
+    /// Tell whether all the transports of the server are running.
+    pub fn transports_running(&self) -> bool {
+        self.transports_running.load(Ordering::Relaxed)
+    }
+
+    /// Run the readiness check, which passes if there is none.
+    ///
+    /// A check that panics is considered as failed.
+    pub fn check_passes(&self) -> bool {
+        match &*self.check.read().unwrap() {
+            Some(check) => catch_panic_sync("readiness check", check).unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Tell whether the server is ready to take requests.
+    pub fn is_ready(&self) -> bool {
+        self.transports_running() && self.check_passes()
+    }
+
+    /// Record whether all the transports of the server are running.
+    pub(crate) fn set_transports_running(&self, running: bool) {
+        self.transports_running.store(running, Ordering::Relaxed);
+    }
+
+    /// Replace the readiness check, or remove it if `None`.
+    pub(crate) fn set_check(&self, check: Option<ReadinessCheck>) {
+        *self.check.write().unwrap() = check;
+    }
+}
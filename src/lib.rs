@@ -15,12 +15,59 @@
 #![deny(unused_mut)]
 #![deny(missing_docs)]
 
+pub use jsonrpc_core;
+pub use jsonrpc_pubsub;
+#[cfg(feature = "derive")]
+pub use witty_jsonrpc_derive::rpc;
+
+/// Records of every JSON-RPC call, for answering who called what and how it went.
+pub mod access_log;
+/// Limits on how many JSON-RPC calls can be executing at the same time.
+pub mod concurrency;
+/// Aliases of JSON-RPC methods that are kept around for old clients.
+pub mod deprecation;
 /// Traits and implementations enabling compatibility with different IO handlers.
 pub mod handler;
+/// Liveness and readiness of servers, as reported by the health endpoints of the HTTP transport.
+pub mod health;
+/// Descriptions of JSON-RPC methods, their parameters and their results.
+pub mod metadata;
+/// Prometheus metrics about the calls, connections and subscriptions handled by a server.
+#[cfg(feature = "metrics")]
+pub mod metrics;
+/// Groups of JSON-RPC methods and subscriptions that can be mounted into servers under a prefix.
+pub mod module;
+/// Generation of OpenRPC documents that describe the API of a server.
+pub mod openrpc;
+/// Isolation of panics raised by JSON-RPC method handlers.
+mod panics;
+/// Token bucket rate limiting of JSON-RPC calls.
+pub mod rate_limit;
+/// Validation of the parameters of JSON-RPC calls against JSON Schemas.
+#[cfg(feature = "jsonschema")]
+pub mod schema;
 /// Traits and implementations of mono-transport and multi-transport servers.
 pub mod server;
+/// Detection and reporting of JSON-RPC method calls that take too long.
+pub mod slow_requests;
+/// Spans that tie everything logged through `tracing` to the JSON-RPC call that caused it.
+#[cfg(all(
+    feature = "tracing",
+    any(feature = "http", feature = "tcp", feature = "ws")
+))]
+mod spans;
+/// Deadlines for the execution of JSON-RPC methods.
+pub mod timeout;
+/// Topics that clients can subscribe to, and that notifications are published to server-side.
+pub mod topics;
+/// Propagation of W3C trace context from callers into the execution of JSON-RPC methods.
+pub mod trace_context;
 /// Traits and implementations of message transports (e.g. HTTP, TCP, WS, etc.)
 pub mod transports;
+/// JSON-RPC methods that take and return Rust types instead of raw JSON values.
+pub mod typed;
+/// Side-by-side versions of the API of a server, and how transports select among them.
+pub mod versioning;
 
 /// Make it easy for 3rd party projects to import all the right structures and traits to start using
 /// this library immediately.
@@ -28,6 +75,8 @@ pub mod prelude {
     pub use jsonrpc_core::Value;
     pub use jsonrpc_pubsub::PubSubHandler;
 
+    #[cfg(feature = "metrics")]
+    pub use crate::metrics::{Metrics, MetricsServer};
     #[cfg(feature = "http")]
     pub use crate::transports::http::{HttpTransport, HttpTransportSettings};
     #[cfg(feature = "tcp")]
@@ -35,10 +84,23 @@ pub mod prelude {
     #[cfg(feature = "ws")]
     pub use crate::transports::ws::{WsTransport, WsTransportSettings};
     pub use crate::{
+        access_log::{AccessLogEntry, AccessLogSink, JsonLinesSink, LogSink},
+        concurrency::ConcurrencyLimit,
+        deprecation::Deprecation,
         handler::Session,
+        metadata::{
+            ContentDescriptor, DescribeParams, DescribeType, MethodExample, MethodMetadata,
+        },
+        module::{Module, WittyModule},
+        openrpc::OpenRpcInfo,
+        rate_limit::{RateLimitKey, RateLimiter, RateLimiterError, RateLimiterSettings},
         server::{
             MultipleTransportsServer, Server, SingleTransportServer, WittyMonoServer,
             WittyMultiServer,
         },
+        slow_requests::{SlowRequest, SlowRequestSettings},
+        topics::{filter_fn, TopicFilter, Topics},
+        trace_context::TraceContext,
+        versioning::ApiVersionSelection,
     };
 }
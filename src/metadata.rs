@@ -0,0 +1,276 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use jsonrpc_core::{serde_json::json, Value};
+
+/// A description of a JSON-RPC method, for documenting the API of a server and generating client
+/// code from it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MethodMetadata {
+    /// The name of the method.
+    pub name: String,
+    /// A short summary of what the method does.
+    pub summary: Option<String>,
+    /// A verbose explanation of what the method does.
+    pub description: Option<String>,
+    /// The parameters of the method, in positional order.
+    pub params: Vec<ContentDescriptor>,
+    /// The result of the method, if described.
+    pub result: Option<ContentDescriptor>,
+    /// Whether the method is deprecated, i.e. whether clients should stop using it.
+    pub deprecated: bool,
+    /// Names for grouping related methods, e.g. in the help output of a client.
+    pub tags: Vec<String>,
+    /// Examples of calls to the method and their results.
+    pub examples: Vec<MethodExample>,
+    /// The subscription that the method belongs to, if it is a subscribe or unsubscribe method.
+    ///
+    /// This is filled in by the server when adding a subscription.
+    pub subscription: Option<SubscriptionMetadata>,
+}
+
+impl MethodMetadata {
+    /// Create the bare metadata of a method, i.e. with nothing more than its name.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: String::from(name),
+            ..Default::default()
+        }
+    }
+}
+
+/// A description of a parameter or the result of a JSON-RPC method.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentDescriptor {
+    /// The name of the parameter or result.
+    pub name: String,
+    /// A short summary of the content.
+    pub summary: Option<String>,
+    /// A verbose explanation of the content.
+    pub description: Option<String>,
+    /// Whether the parameter must be provided.
+    pub required: bool,
+    /// The JSON Schema that the content conforms to. The empty schema accepts anything.
+    pub schema: Value,
+}
+
+impl ContentDescriptor {
+    /// Create a descriptor for required content that conforms to `schema`.
+    pub fn new(name: &str, schema: Value) -> Self {
+        Self {
+            name: String::from(name),
+            schema,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for ContentDescriptor {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            summary: None,
+            description: None,
+            required: true,
+            schema: json!({}),
+        }
+    }
+}
+
+/// An example of a call to a JSON-RPC method and its result.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MethodExample {
+    /// The name of the example.
+    pub name: String,
+    /// A short summary of what the example shows.
+    pub summary: Option<String>,
+    /// The parameters of the call, either positional (an array) or named (an object).
+    pub params: Value,
+    /// The result of the call, if it has any worth showing.
+    pub result: Option<Value>,
+}
+
+/// The subscription that a subscribe or unsubscribe method belongs to.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SubscriptionMetadata {
+    /// The name that notifications are sent under.
+    pub notification: String,
+    /// The name of the method that creates subscriptions.
+    pub subscribe: String,
+    /// The name of the method that cancels subscriptions.
+    pub unsubscribe: String,
+}
+
+/// Rust types that can tell which JSON Schema the JSON values they are converted from and into
+/// conform to, for describing the parameters and results of typed methods.
+///
+/// This is implemented for primitives, strings, `Option`, `Vec`, maps with string keys,
+/// `serde_json::Value` and `SubscriptionId`. Other types can implement it with an empty `impl`
+/// block, which describes them with the empty schema, i.e. as anything, or else provide a schema
+/// of their own.
+pub trait DescribeType {
+    /// The JSON Schema that values of this type conform to.
+    fn schema() -> Value {
+        json!({})
+    }
+
+    /// Whether `null` stands for a value of this type, i.e. whether a parameter of this type can be
+    /// left out.
+    fn optional() -> bool {
+        false
+    }
+}
+
+/// Implement `DescribeType` for some types, all of them with the same schema.
+macro_rules! describe_types {
+    ($schema:tt => $($ty:ty),+) => {
+        $(
+            impl DescribeType for $ty {
+                fn schema() -> Value {
+                    json!($schema)
+                }
+            }
+        )+
+    };
+}
+
+describe_types!({ "type": "boolean" } => bool);
+describe_types!({ "type": "integer", "minimum": 0 } => u8, u16, u32, u64, u128, usize);
+describe_types!({ "type": "integer" } => i8, i16, i32, i64, i128, isize);
+describe_types!({ "type": "number" } => f32, f64);
+describe_types!({ "type": "string" } => char, str, String);
+describe_types!({ "type": "null" } => ());
+
+impl DescribeType for Value {}
+
+impl DescribeType for jsonrpc_pubsub::SubscriptionId {
+    fn schema() -> Value {
+        json!({ "anyOf": [{ "type": "integer", "minimum": 0 }, { "type": "string" }] })
+    }
+}
+
+impl<T> DescribeType for Option<T>
+where
+    T: DescribeType,
+{
+    fn schema() -> Value {
+        json!({ "anyOf": [T::schema(), { "type": "null" }] })
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+impl<T> DescribeType for Vec<T>
+where
+    T: DescribeType,
+{
+    fn schema() -> Value {
+        json!({ "type": "array", "items": T::schema() })
+    }
+}
+
+impl<T, S> DescribeType for HashMap<String, T, S>
+where
+    T: DescribeType,
+{
+    fn schema() -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema() })
+    }
+}
+
+impl<T> DescribeType for BTreeMap<String, T>
+where
+    T: DescribeType,
+{
+    fn schema() -> Value {
+        json!({ "type": "object", "additionalProperties": T::schema() })
+    }
+}
+
+impl<T> DescribeType for &T
+where
+    T: DescribeType + ?Sized,
+{
+    fn schema() -> Value {
+        T::schema()
+    }
+
+    fn optional() -> bool {
+        T::optional()
+    }
+}
+
+impl<T> DescribeType for Box<T>
+where
+    T: DescribeType + ?Sized,
+{
+    fn schema() -> Value {
+        T::schema()
+    }
+
+    fn optional() -> bool {
+        T::optional()
+    }
+}
+
+impl<T> DescribeType for Arc<T>
+where
+    T: DescribeType + ?Sized,
+{
+    fn schema() -> Value {
+        T::schema()
+    }
+
+    fn optional() -> bool {
+        T::optional()
+    }
+}
+
+/// Rust types that the parameters of typed methods are converted from, which can describe those
+/// parameters one by one.
+///
+/// This is implemented for `()` and for tuples of up to 8 elements that implement `DescribeType`,
+/// whose parameters are named after their position, e.g. `param0`. Other types can implement it
+/// with an empty `impl` block, which leaves their parameters undescribed, or else describe them.
+pub trait DescribeParams {
+    /// Describe the parameters, in positional order.
+    fn describe_params() -> Vec<ContentDescriptor> {
+        Vec::new()
+    }
+}
+
+impl DescribeParams for () {}
+
+/// Implement `DescribeParams` for tuples, given the positions and types of their elements.
+macro_rules! describe_tuples {
+    ($(($($position:tt $ty:ident),+))+) => {
+        $(
+            impl<$($ty),+> DescribeParams for ($($ty,)+)
+            where
+                $($ty: DescribeType),+
+            {
+                fn describe_params() -> Vec<ContentDescriptor> {
+                    vec![$(ContentDescriptor {
+                        required: !$ty::optional(),
+                        ..ContentDescriptor::new(concat!("param", $position), $ty::schema())
+                    }),+]
+                }
+            }
+        )+
+    };
+}
+
+describe_tuples! {
+    (0 A)
+    (0 A, 1 B)
+    (0 A, 1 B, 2 C)
+    (0 A, 1 B, 2 C, 3 D)
+    (0 A, 1 B, 2 C, 3 D, 4 E)
+    (0 A, 1 B, 2 C, 3 D, 4 E, 5 F)
+    (0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G)
+    (0 A, 1 B, 2 C, 3 D, 4 E, 5 F, 6 G, 7 H)
+}
@@ -0,0 +1,590 @@
+// Recording calls and subscriptions is only possible through the built-in transports
+#![cfg_attr(
+    not(any(feature = "http", feature = "tcp", feature = "ws")),
+    allow(dead_code, unused_imports)
+)]
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use futures::{future::Either, FutureExt};
+use jsonrpc_core::{
+    middleware::{NoopCallFuture, NoopFuture},
+    Call, ErrorCode, Middleware, Output, Value,
+};
+use jsonrpc_pubsub::PubSubMetadata;
+pub use prometheus::Registry;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, TextEncoder,
+};
+
+use crate::{handler::ConnectionMetadata, transports::TransportError};
+
+/// The content type of the Prometheus text exposition format.
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The maximum size in bytes of the requests that `MetricsServer` reads.
+const MAX_METRICS_REQUEST_SIZE: usize = 8 * 1024;
+
+/// How long `MetricsServer` waits for a whole request to arrive, and for its response to be sent.
+const METRICS_REQUEST_DEADLINE: Duration = Duration::from_secs(5);
+
+/// The label used in place of method names that the server does not know about, so that clients
+/// cannot blow up the number of time series by calling made up methods.
+const UNKNOWN_METHOD: &str = "<unknown>";
+
+/// The label used for calls that did not come through any transport.
+const UNKNOWN_TRANSPORT: &str = "<none>";
+
+/// The names of the methods used for subscribing and unsubscribing.
+#[derive(Debug, Default)]
+struct SubscriptionMethods {
+    subscribe: HashSet<String>,
+    unsubscribe: HashSet<String>,
+}
+
+/// Prometheus metrics about the calls, connections and subscriptions handled by a server.
+///
+/// This is cheap to clone, and all clones update the same metrics.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    errors: IntCounterVec,
+    deprecated_calls: IntCounterVec,
+    latency: HistogramVec,
+    #[cfg_attr(not(any(feature = "tcp", feature = "ws")), allow(dead_code))]
+    active_connections: IntGaugeVec,
+    active_subscriptions: IntGaugeVec,
+    subscription_methods: Arc<RwLock<SubscriptionMethods>>,
+    /// How many subscriptions every session has open, along with the transport it belongs to.
+    sessions: Arc<Mutex<HashMap<u64, (String, i64)>>>,
+}
+
+impl Metrics {
+    /// Create a new set of metrics, registered into a new registry.
+    pub fn new() -> Self {
+        Self::with_registry(Registry::new())
+            .expect("Metric names are unique within a fresh registry")
+    }
+
+    /// Create a new set of metrics, registered into an existing registry, e.g. one that is shared
+    /// with other parts of the application.
+    pub fn with_registry(registry: Registry) -> Result<Self, prometheus::Error> {
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "jsonrpc_requests_total",
+                "Number of JSON-RPC calls handled.",
+            ),
+            &["method", "transport"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "jsonrpc_errors_total",
+                "Number of JSON-RPC calls that completed with an error.",
+            ),
+            &["method", "transport", "code"],
+        )?;
+        let deprecated_calls = IntCounterVec::new(
+            Opts::new(
+                "jsonrpc_deprecated_calls_total",
+                "Number of JSON-RPC calls made through deprecated method names.",
+            ),
+            &["method"],
+        )?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "jsonrpc_request_duration_seconds",
+                "Time taken to complete JSON-RPC calls.",
+            ),
+            &["method"],
+        )?;
+        let active_connections = IntGaugeVec::new(
+            Opts::new(
+                "jsonrpc_active_connections",
+                "Number of connections currently open.",
+            ),
+            &["transport"],
+        )?;
+        let active_subscriptions = IntGaugeVec::new(
+            Opts::new(
+                "jsonrpc_active_subscriptions",
+                "Number of subscriptions currently open.",
+            ),
+            &["transport"],
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(deprecated_calls.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(active_connections.clone()))?;
+        registry.register(Box::new(active_subscriptions.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            errors,
+            deprecated_calls,
+            latency,
+            active_connections,
+            active_subscriptions,
+            subscription_methods: Default::default(),
+            sessions: Default::default(),
+        })
+    }
+
+    /// The registry that these metrics are registered into.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Render every metric in the registry in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(error) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            log::error!("Failed to encode metrics: {}", error);
+        }
+
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    /// Let the metrics know which methods open and close subscriptions, so that they can be
+    /// counted.
+    pub(crate) fn register_subscription(&self, subscribe: &str, unsubscribe: &str) {
+        let mut methods = self.subscription_methods.write().unwrap();
+        methods.subscribe.insert(String::from(subscribe));
+        methods.unsubscribe.insert(String::from(unsubscribe));
+    }
+
+    /// Count a call made through a deprecated method name.
+    pub(crate) fn record_deprecated_call(&self, method: &str) {
+        self.deprecated_calls.with_label_values(&[method]).inc();
+    }
+
+    /// Count a connection as open until the returned guard is dropped.
+    #[cfg(any(feature = "tcp", feature = "ws"))]
+    pub(crate) fn track_connection(&self, transport: &str) -> ActiveConnection {
+        let gauge = self.active_connections.with_label_values(&[transport]);
+        gauge.inc();
+
+        ActiveConnection { gauge }
+    }
+
+    /// Update the subscription counters after a successful subscription or unsubscription.
+    fn record_subscription<M>(&self, method: &str, transport: &str, output: &Output, meta: &M)
+    where
+        M: PubSubMetadata + ConnectionMetadata,
+    {
+        let delta = {
+            let methods = self.subscription_methods.read().unwrap();
+            match output {
+                Output::Success(_) if methods.subscribe.contains(method) => 1,
+                Output::Success(success)
+                    if methods.unsubscribe.contains(method)
+                        && success.result == Value::Bool(true) =>
+                {
+                    -1
+                }
+                _ => return,
+            }
+        };
+        let (Some(session_id), Some(session)) = (meta.session_id(), meta.session()) else {
+            return;
+        };
+
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get_mut(&session_id) {
+            // Unsubscribing from an unknown subscription id is answered with an error, so the
+            // count can only go below zero if the subscription was opened before metrics were
+            // enabled
+            Some((_, count)) if *count + delta < 0 => return,
+            Some((_, count)) => *count += delta,
+            None if delta < 0 => return,
+            None => {
+                sessions.insert(session_id, (String::from(transport), delta));
+
+                // Every subscription is dropped along with its session
+                let metrics = self.clone();
+                session.on_drop(move || {
+                    if let Some((transport, count)) =
+                        metrics.sessions.lock().unwrap().remove(&session_id)
+                    {
+                        metrics
+                            .active_subscriptions
+                            .with_label_values(&[&transport])
+                            .sub(count);
+                    }
+                });
+            }
+        }
+        self.active_subscriptions
+            .with_label_values(&[transport])
+            .add(delta);
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Proof that a connection is being counted as open.
+///
+/// The connection stops being counted once this is dropped.
+#[cfg(any(feature = "tcp", feature = "ws"))]
+#[derive(Debug)]
+pub(crate) struct ActiveConnection {
+    gauge: prometheus::IntGauge,
+}
+
+#[cfg(any(feature = "tcp", feature = "ws"))]
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+/// A JSON-RPC middleware that records the calls going through a transport into `Metrics`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RequestMetrics(pub(crate) Option<Metrics>);
+
+impl<M> Middleware<M> for RequestMetrics
+where
+    M: PubSubMetadata + ConnectionMetadata,
+{
+    type Future = NoopFuture;
+    type CallFuture = NoopCallFuture;
+
+    fn on_call<F, X>(&self, call: Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: Fn(Call, M) -> X + Send + Sync,
+        X: futures::Future<Output = Option<Output>> + Send + 'static,
+    {
+        let metrics = match &self.0 {
+            Some(metrics) => metrics.clone(),
+            None => return Either::Right(next(call, meta)),
+        };
+        let method = match &call {
+            Call::MethodCall(call) => call.method.clone(),
+            Call::Notification(notification) => notification.method.clone(),
+            Call::Invalid { .. } => return Either::Right(next(call, meta)),
+        };
+        let transport = meta.transport().unwrap_or(UNKNOWN_TRANSPORT);
+        let started = Instant::now();
+
+        let response = next(call, meta.clone()).map(move |output| {
+            let error_code = match &output {
+                Some(Output::Failure(failure)) => Some(failure.error.code.clone()),
+                _ => None,
+            };
+            let method = match error_code {
+                Some(ErrorCode::MethodNotFound) => UNKNOWN_METHOD,
+                _ => method.as_str(),
+            };
+
+            metrics
+                .requests
+                .with_label_values(&[method, transport])
+                .inc();
+            metrics
+                .latency
+                .with_label_values(&[method])
+                .observe(started.elapsed().as_secs_f64());
+            if let Some(code) = error_code {
+                metrics
+                    .errors
+                    .with_label_values(&[method, transport, &code.code().to_string()])
+                    .inc();
+            }
+            if let Some(output) = &output {
+                metrics.record_subscription(method, transport, output, &meta);
+            }
+
+            output
+        });
+
+        Either::Left(Box::pin(response))
+    }
+}
+
+/// A minimal HTTP listener that serves `Metrics` on `/metrics`, for exposing them without going
+/// through any JSON-RPC transport.
+pub struct MetricsServer {
+    address: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Start listening on `address`, e.g. `"127.0.0.1:9090"`.
+    pub fn start(address: &str, metrics: Metrics) -> Result<Self, TransportError> {
+        let listener = TcpListener::bind(address.parse::<SocketAddr>()?)?;
+        let address = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+
+        let thread = std::thread::Builder::new()
+            .name(format!("witty-jsonrpc-metrics-{}", address))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if thread_stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    match stream {
+                        Ok(stream) => serve_metrics(stream, &metrics),
+                        Err(error) => log::warn!("Error accepting metrics connection: {}", error),
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            address,
+            stopped,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address that the listener is bound to.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stop listening.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stopped.store(true, Ordering::SeqCst);
+            // Wake up the listener so that it notices it has been stopped
+            TcpStream::connect(self.address).ok();
+            thread.join().ok();
+        }
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Answer a single HTTP request with the rendered metrics, or with a 404 if it asks for any other
+/// path.
+///
+/// Requests are served one at a time, so those that are too large or too slow to arrive are
+/// answered with a 413 or a 408 right away, for them not to hold up the listener.
+fn serve_metrics(stream: TcpStream, metrics: &Metrics) {
+    stream
+        .set_write_timeout(Some(METRICS_REQUEST_DEADLINE))
+        .ok();
+
+    let (status, content_type, body) = match read_request_head(&stream) {
+        Ok(head) if head.split_whitespace().nth(1) == Some("/metrics") => {
+            ("200 OK", METRICS_CONTENT_TYPE, metrics.render())
+        }
+        Ok(_) => (
+            "404 Not Found",
+            "text/plain; charset=utf-8",
+            String::from("Not found.\n"),
+        ),
+        Err(HeadError::TooLarge) => {
+            log::debug!("Rejecting metrics request that is too large");
+
+            (
+                "413 Payload Too Large",
+                "text/plain; charset=utf-8",
+                String::from("Request too large.\n"),
+            )
+        }
+        Err(HeadError::TooSlow) => {
+            log::debug!("Rejecting metrics request that is too slow");
+
+            (
+                "408 Request Timeout",
+                "text/plain; charset=utf-8",
+                String::from("Request timed out.\n"),
+            )
+        }
+        Err(HeadError::Broken) => return,
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    (&stream).write_all(response.as_bytes()).ok();
+}
+
+/// The reasons why the head of a request to `MetricsServer` cannot be read.
+enum HeadError {
+    /// The head does not fit in `MAX_METRICS_REQUEST_SIZE`.
+    TooLarge,
+    /// The head did not arrive before `METRICS_REQUEST_DEADLINE`.
+    TooSlow,
+    /// The connection was closed, or the head is not valid UTF-8.
+    Broken,
+}
+
+/// Read the request line and the headers of an HTTP request, as long as they fit in
+/// `MAX_METRICS_REQUEST_SIZE` and arrive before `METRICS_REQUEST_DEADLINE`.
+fn read_request_head(mut stream: &TcpStream) -> Result<String, HeadError> {
+    let deadline = Instant::now() + METRICS_REQUEST_DEADLINE;
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+
+    while !(head.windows(4).any(|end| end == b"\r\n\r\n")
+        || head.windows(2).any(|end| end == b"\n\n"))
+    {
+        if head.len() >= MAX_METRICS_REQUEST_SIZE {
+            return Err(HeadError::TooLarge);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(HeadError::TooSlow);
+        }
+        stream
+            .set_read_timeout(Some(remaining))
+            .map_err(|_| HeadError::Broken)?;
+        let read = match stream.read(&mut buffer) {
+            Ok(0) => return Err(HeadError::Broken),
+            Ok(read) => read,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(HeadError::TooSlow)
+            }
+            Err(_) => return Err(HeadError::Broken),
+        };
+        head.extend_from_slice(&buffer[..read]);
+    }
+
+    String::from_utf8(head).map_err(|_| HeadError::Broken)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send the start of a request to a `MetricsServer`, returning the status line of the
+    /// response.
+    fn send(server: &MetricsServer, request: &[u8]) -> String {
+        let mut stream = TcpStream::connect(server.address()).unwrap();
+        // The server may answer and close the connection before the whole request is written
+        stream.write_all(request).ok();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).ok();
+
+        String::from(response.lines().next().unwrap_or_default())
+    }
+
+    #[cfg(feature = "tcp")]
+    #[test]
+    fn calls_through_transports_are_scraped() {
+        use std::io::BufRead;
+
+        use jsonrpc_core::serde_json::json;
+
+        use crate::{
+            deprecation::Deprecation,
+            server::Server,
+            transports::{
+                echo_server, free_address,
+                tcp::{TcpTransport, TcpTransportSettings},
+            },
+        };
+
+        let metrics = Metrics::new();
+        let address = free_address();
+        let mut server = echo_server(TcpTransport::new(TcpTransportSettings {
+            address: address.clone(),
+            ..Default::default()
+        }));
+        server.set_metrics(metrics.clone());
+        server.add_method("fail", |_| {
+            futures::future::err::<Value, _>(jsonrpc_core::Error::invalid_params("Nope."))
+        });
+        server.add_alias(
+            "old_echo",
+            "echo",
+            Some(Deprecation {
+                note: None,
+                annotate_response: false,
+            }),
+        );
+
+        let mut stream = TcpStream::connect(&address).unwrap();
+        for (id, method) in ["echo", "fail", "old_echo"].into_iter().enumerate() {
+            let call = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": [] });
+            writeln!(stream, "{}", call).unwrap();
+        }
+        let mut lines = std::io::BufReader::new(stream).lines();
+        for _ in 0..3 {
+            lines.next().unwrap().unwrap();
+        }
+        server.stop().unwrap();
+
+        let scrape = metrics.render();
+        for sample in [
+            r#"jsonrpc_requests_total{method="echo",transport="tcp"} 1"#,
+            r#"jsonrpc_requests_total{method="fail",transport="tcp"} 1"#,
+            r#"jsonrpc_requests_total{method="old_echo",transport="tcp"} 1"#,
+            r#"jsonrpc_errors_total{code="-32602",method="fail",transport="tcp"} 1"#,
+            r#"jsonrpc_deprecated_calls_total{method="old_echo"} 1"#,
+            r#"jsonrpc_request_duration_seconds_bucket{method="echo",le="+Inf"} 1"#,
+            r#"jsonrpc_request_duration_seconds_count{method="fail"} 1"#,
+        ] {
+            assert!(scrape.contains(sample), "{} is not in:\n{}", sample, scrape);
+        }
+    }
+
+    #[test]
+    fn metrics_are_served_on_their_path() {
+        let server = MetricsServer::start("127.0.0.1:0", Metrics::new()).unwrap();
+
+        let request = b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert!(send(&server, request).contains("200 OK"));
+        let request = b"GET /other HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert!(send(&server, request).contains("404 Not Found"));
+
+        server.stop();
+    }
+
+    #[test]
+    fn requests_past_the_size_and_time_bounds_are_rejected() {
+        let server = MetricsServer::start("127.0.0.1:0", Metrics::new()).unwrap();
+
+        let mut request = b"GET /metrics HTTP/1.1\r\n".to_vec();
+        request.extend(
+            b"X-Padding: "
+                .iter()
+                .chain(&[b'x'; MAX_METRICS_REQUEST_SIZE]),
+        );
+        assert!(send(&server, &request).contains("413 Payload Too Large"));
+
+        // The head of the request never ends
+        let started = Instant::now();
+        assert!(send(&server, b"GET /metrics HTTP/1.1\r\n").contains("408 Request Timeout"));
+        assert!(started.elapsed() >= METRICS_REQUEST_DEADLINE);
+
+        server.stop();
+    }
+}
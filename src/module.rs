@@ -0,0 +1,323 @@
+use std::{collections::BTreeSet, fmt, sync::Arc};
+
+use jsonrpc_core::{BoxFuture, Params, RpcMethodSimple, Value};
+use jsonrpc_pubsub::{
+    PubSubHandler, SubscribeRpcMethod, Subscriber, SubscriptionId, UnsubscribeRpcMethod,
+};
+
+use crate::{
+    deprecation::Deprecation,
+    handler::{Handler, Session},
+    metadata::MethodMetadata,
+    server::{BoxedMethod, Server},
+};
+
+/// A convenient type alias for a module that can be mounted into the servers that support PubSub.
+pub type WittyModule = Module<PubSubHandler<Session>>;
+
+/// A type-erased JSON-RPC subscribe method.
+type BoxedSubscribe<M> = Arc<dyn Fn(Params, M, Subscriber) + Send + Sync>;
+/// A type-erased JSON-RPC unsubscribe method.
+type BoxedUnsubscribe<M> =
+    Arc<dyn Fn(SubscriptionId, Option<M>) -> BoxFuture<jsonrpc_core::Result<Value>> + Send + Sync>;
+
+/// Enumerates all the different errors that mounting a `Module` can get into.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ModuleError {
+    /// The module would add a method whose name is already taken, either by another method of the
+    /// server or by another method of the same module.
+    DuplicateName(String),
+    /// The module has a subscription, with this notification name, but is being added as a
+    /// version of the API, which can only have methods.
+    VersionedSubscription(String),
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateName(name) => write!(f, "the method name {} is already taken", name),
+            Self::VersionedSubscription(notification) => write!(
+                f,
+                "the subscription {} cannot be part of a version of the API",
+                notification
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModuleError {}
+
+/// A subscription of a module, with the names of its methods not prefixed yet.
+struct ModuleSubscription<M> {
+    notification: String,
+    subscribe: (String, BoxedSubscribe<M>),
+    unsubscribe: (String, BoxedUnsubscribe<M>),
+}
+
+/// A group of JSON-RPC methods and subscriptions that share a common prefix in their names (e.g.
+/// `wallet_` or `chain_`), and can be mounted into any `Server` in one go.
+///
+/// Modules implement `Server` themselves, so methods can be added to them in all the same ways as
+/// to servers, including `add_typed_method` and the `register_rpc` method generated by the `rpc`
+/// attribute macro. As they do not listen anywhere by themselves, starting and stopping them does
+/// nothing.
+///
+/// The prefix applies to the names of methods, subscribe and unsubscribe methods, and
+/// notifications alike.
+pub struct Module<H>
+where
+    H: Handler,
+{
+    prefix: String,
+    methods: Vec<(String, BoxedMethod)>,
+    subscriptions: Vec<ModuleSubscription<H::Metadata>>,
+    /// The aliases of the methods, along with the methods they stand for.
+    aliases: Vec<(String, String, Option<Deprecation>)>,
+    metadata: Vec<MethodMetadata>,
+}
+
+impl<H> Module<H>
+where
+    H: Handler,
+{
+    /// Create an empty module whose methods will be mounted with `prefix` prepended to their names.
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: String::from(prefix),
+            methods: Vec::new(),
+            subscriptions: Vec::new(),
+            aliases: Vec::new(),
+            metadata: Vec::new(),
+        }
+    }
+
+    /// Add all the methods and subscriptions of this module to a server, with their names
+    /// prefixed.
+    ///
+    /// Nothing is added if any of the prefixed names is already taken in the server, or is used
+    /// more than once within the module.
+    pub fn mount<S>(self, server: &mut S) -> Result<(), ModuleError>
+    where
+        S: Server<H>,
+    {
+        let mut names = server.describe_api().into_iter().collect::<BTreeSet<_>>();
+        for name in self.names() {
+            if !names.insert(name.clone()) {
+                return Err(ModuleError::DuplicateName(name));
+            }
+        }
+
+        let prefix = self.prefix;
+        let prefixed = |name: &str| format!("{}{}", prefix, name);
+
+        for (name, method) in self.methods {
+            server.add_method(&prefixed(&name), move |params| method(params));
+        }
+        for subscription in self.subscriptions {
+            let (subscribe_name, subscribe) = subscription.subscribe;
+            let (unsubscribe_name, unsubscribe) = subscription.unsubscribe;
+
+            server.add_subscription(
+                &prefixed(&subscription.notification),
+                (
+                    &prefixed(&subscribe_name),
+                    move |params, meta, subscriber| subscribe(params, meta, subscriber),
+                ),
+                (&prefixed(&unsubscribe_name), move |id, meta| {
+                    unsubscribe(id, meta)
+                }),
+            );
+        }
+        for mut metadata in self.metadata {
+            metadata.name = prefixed(&metadata.name);
+            // Adding the subscriptions to the server already filled this in with prefixed names
+            metadata.subscription = None;
+            server.set_method_metadata(metadata);
+        }
+        // Aliases go last, so that they are described just like their targets
+        for (alias, target, deprecation) in self.aliases {
+            server.add_alias(&prefixed(&alias), &prefixed(&target), deprecation);
+        }
+
+        Ok(())
+    }
+
+    /// The prefixed notification names of all the subscriptions of this module.
+    pub(crate) fn notifications(&self) -> Vec<String> {
+        self.subscriptions
+            .iter()
+            .map(|subscription| format!("{}{}", self.prefix, subscription.notification))
+            .collect()
+    }
+
+    /// The prefixed names of all the methods of this module, including duplicates.
+    fn names(&self) -> Vec<String> {
+        let methods = self.methods.iter().map(|(name, _)| name);
+        let subscriptions = self
+            .subscriptions
+            .iter()
+            .flat_map(|subscription| [&subscription.subscribe.0, &subscription.unsubscribe.0]);
+
+        let aliases = self.aliases.iter().map(|(alias, _, _)| alias);
+
+        methods
+            .chain(subscriptions)
+            .chain(aliases)
+            .map(|name| format!("{}{}", self.prefix, name))
+            .collect()
+    }
+}
+
+impl<H> Server<H> for Module<H>
+where
+    H: Handler,
+{
+    type Error = ModuleError;
+
+    fn start(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn add_method<F>(&mut self, name: &str, method: F)
+    where
+        F: RpcMethodSimple,
+    {
+        let method = Arc::new(method);
+
+        self.methods.push((
+            String::from(name),
+            Arc::new(move |params| {
+                let method = method.clone();
+
+                Box::pin(async move { method.call(params).await })
+            }),
+        ));
+    }
+
+    fn add_subscription<F, G>(
+        &mut self,
+        notification: &str,
+        subscribe: (&str, F),
+        unsubscribe: (&str, G),
+    ) where
+        F: SubscribeRpcMethod<H::Metadata>,
+        G: UnsubscribeRpcMethod<H::Metadata>,
+    {
+        let (subscribe_name, subscribe_method) = subscribe;
+        let (unsubscribe_name, unsubscribe_method) = unsubscribe;
+        let unsubscribe_method = Arc::new(unsubscribe_method);
+
+        self.subscriptions.push(ModuleSubscription {
+            notification: String::from(notification),
+            subscribe: (
+                String::from(subscribe_name),
+                Arc::new(move |params, meta, subscriber| {
+                    subscribe_method.call(params, meta, subscriber)
+                }),
+            ),
+            unsubscribe: (
+                String::from(unsubscribe_name),
+                Arc::new(move |id, meta| {
+                    let method = unsubscribe_method.clone();
+
+                    Box::pin(async move { method.call(id, meta).await })
+                }),
+            ),
+        });
+    }
+
+    fn describe_api(&self) -> Vec<String> {
+        self.names()
+    }
+
+    fn describe_api_detailed(&self) -> Vec<MethodMetadata> {
+        self.metadata
+            .iter()
+            .cloned()
+            .map(|mut metadata| {
+                metadata.name = format!("{}{}", self.prefix, metadata.name);
+
+                metadata
+            })
+            .collect()
+    }
+
+    fn add_alias(&mut self, alias: &str, target: &str, deprecation: Option<Deprecation>) {
+        self.aliases
+            .push((String::from(alias), String::from(target), deprecation));
+    }
+
+    fn set_method_metadata(&mut self, metadata: MethodMetadata) {
+        self.metadata
+            .retain(|existing| existing.name != metadata.name);
+        self.metadata.push(metadata);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future;
+
+    use super::*;
+    use crate::server::WittyMultiServer;
+
+    /// A module whose methods are prefixed with `math_`, with a plain method, a subscription and an
+    /// alias.
+    fn math() -> WittyModule {
+        let mut module = Module::new("math_");
+        module.add_method("add", |_| future::ready(Ok(Value::from(3))));
+        module.add_subscription(
+            "sums",
+            ("subscribe", |_, _, _| {}),
+            ("unsubscribe", |_, _| future::ready(Ok(Value::from(true)))),
+        );
+        module.add_alias("sum", "add", None);
+
+        module
+    }
+
+    /// Mount the math module into a server, checking that it is rejected because of `name` and that
+    /// nothing gets added to the server.
+    fn assert_rejected(mut server: WittyMultiServer, name: &str) {
+        let mut before = server.describe_api();
+        before.sort();
+
+        assert_eq!(
+            math().mount(&mut server),
+            Err(ModuleError::DuplicateName(String::from(name)))
+        );
+
+        let mut after = server.describe_api();
+        after.sort();
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn names_of_existing_methods_are_rejected() {
+        let mut server = WittyMultiServer::new();
+        server.add_method("math_add", |_| future::ready(Ok(Value::from(4))));
+
+        assert_rejected(server, "math_add");
+    }
+
+    #[test]
+    fn names_of_existing_subscriptions_are_rejected() {
+        let mut server = WittyMultiServer::new();
+        server.add_topic("news", "math_subscribe", "math_unsubscribe");
+
+        assert_rejected(server, "math_subscribe");
+    }
+
+    #[test]
+    fn names_of_existing_aliases_are_rejected() {
+        let mut server = WittyMultiServer::new();
+        server.add_method("total", |_| future::ready(Ok(Value::from(4))));
+        server.add_alias("math_sum", "total", None);
+
+        assert_rejected(server, "math_sum");
+    }
+}
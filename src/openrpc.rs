@@ -0,0 +1,172 @@
+use jsonrpc_core::{
+    serde_json::{json, Map},
+    Value,
+};
+
+use crate::metadata::{ContentDescriptor, MethodExample, MethodMetadata};
+
+/// The version of the OpenRPC specification that generated documents conform to.
+pub const OPENRPC_VERSION: &str = "1.2.6";
+
+/// The name of the method that returns the OpenRPC document of a server, as set forth by the
+/// OpenRPC specification.
+pub const DISCOVER_METHOD: &str = "rpc.discover";
+
+/// General information about an API, which goes into the `info` section of its OpenRPC document.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OpenRpcInfo {
+    /// The title of the API.
+    pub title: String,
+    /// The version of the API, not to be confused with the version of the OpenRPC specification.
+    pub version: String,
+    /// A verbose explanation of the API.
+    pub description: Option<String>,
+}
+
+/// Build an OpenRPC document out of the metadata of some methods.
+///
+/// Subscribe and unsubscribe methods are annotated with an `x-subscription` extension field that
+/// names the notification and both methods of their subscription, as OpenRPC has no other way to
+/// describe subscriptions. The `rpc.discover` method itself is left out, as the specification
+/// requires.
+pub fn document<'a, I>(info: &OpenRpcInfo, methods: I) -> Value
+where
+    I: IntoIterator<Item = &'a MethodMetadata>,
+{
+    let mut info_object = json!({
+        "title": info.title,
+        "version": info.version,
+    });
+    if let Some(description) = &info.description {
+        info_object["description"] = Value::from(description.as_str());
+    }
+
+    let methods = methods
+        .into_iter()
+        .filter(|method| method.name != DISCOVER_METHOD)
+        .map(method_object)
+        .collect::<Vec<_>>();
+
+    json!({
+        "openrpc": OPENRPC_VERSION,
+        "info": info_object,
+        "methods": methods,
+    })
+}
+
+/// Build the OpenRPC method object that describes a method.
+fn method_object(method: &MethodMetadata) -> Value {
+    let mut object = Map::new();
+    object.insert(String::from("name"), Value::from(method.name.as_str()));
+    insert_text(&mut object, "summary", &method.summary);
+    insert_text(&mut object, "description", &method.description);
+    object.insert(
+        String::from("params"),
+        method.params.iter().map(content_descriptor).collect(),
+    );
+    // The result is mandatory in OpenRPC 1.2, so undescribed results are taken as anything
+    let result = method
+        .result
+        .clone()
+        .unwrap_or_else(|| ContentDescriptor::new("result", json!({})));
+    object.insert(String::from("result"), content_descriptor(&result));
+    object.insert(String::from("paramStructure"), Value::from("either"));
+    if method.deprecated {
+        object.insert(String::from("deprecated"), Value::from(true));
+    }
+    if !method.tags.is_empty() {
+        object.insert(
+            String::from("tags"),
+            method
+                .tags
+                .iter()
+                .map(|tag| json!({ "name": tag }))
+                .collect(),
+        );
+    }
+    if !method.examples.is_empty() {
+        object.insert(
+            String::from("examples"),
+            method
+                .examples
+                .iter()
+                .map(|example| example_pairing(method, example))
+                .collect(),
+        );
+    }
+    if let Some(subscription) = &method.subscription {
+        object.insert(
+            String::from("x-subscription"),
+            json!({
+                "notification": subscription.notification,
+                "subscribe": subscription.subscribe,
+                "unsubscribe": subscription.unsubscribe,
+            }),
+        );
+    }
+
+    Value::Object(object)
+}
+
+/// Build the OpenRPC content descriptor object that describes a parameter or result.
+fn content_descriptor(descriptor: &ContentDescriptor) -> Value {
+    let mut object = Map::new();
+    object.insert(String::from("name"), Value::from(descriptor.name.as_str()));
+    insert_text(&mut object, "summary", &descriptor.summary);
+    insert_text(&mut object, "description", &descriptor.description);
+    object.insert(String::from("required"), Value::from(descriptor.required));
+    object.insert(String::from("schema"), descriptor.schema.clone());
+
+    Value::Object(object)
+}
+
+/// Build the OpenRPC example pairing object that describes an example of a call to a method.
+///
+/// Positional parameters are named after the parameters of the method they correspond to, if
+/// described.
+fn example_pairing(method: &MethodMetadata, example: &MethodExample) -> Value {
+    let params = match &example.params {
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .map(|(position, value)| {
+                let name = method
+                    .params
+                    .get(position)
+                    .map(|param| param.name.clone())
+                    .unwrap_or_else(|| format!("param{}", position));
+
+                json!({ "name": name, "value": value })
+            })
+            .collect(),
+        Value::Object(values) => values
+            .iter()
+            .map(|(name, value)| json!({ "name": name, "value": value }))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let mut object = Map::new();
+    object.insert(String::from("name"), Value::from(example.name.as_str()));
+    insert_text(&mut object, "summary", &example.summary);
+    object.insert(String::from("params"), Value::from(params));
+    if let Some(result) = &example.result {
+        let name = method
+            .result
+            .as_ref()
+            .map_or("result", |result| result.name.as_str());
+        object.insert(
+            String::from("result"),
+            json!({ "name": name, "value": result }),
+        );
+    }
+
+    Value::Object(object)
+}
+
+/// Insert an optional text field into an object, leaving it out if missing.
+fn insert_text(object: &mut Map<String, Value>, key: &str, text: &Option<String>) {
+    if let Some(text) = text {
+        object.insert(String::from(key), Value::from(text.as_str()));
+    }
+}
@@ -0,0 +1,304 @@
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::{Cell, RefCell},
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::Once,
+    task::{Context, Poll},
+};
+
+use jsonrpc_core::Error;
+
+thread_local! {
+    /// How many nested `catch_panic_sync` calls are active on this thread.
+    static CATCHING: Cell<usize> = const { Cell::new(0) };
+    /// Where the last panic caught on this thread happened, and the backtrace leading to it.
+    static LAST_PANIC: RefCell<Option<(String, Backtrace)>> = const { RefCell::new(None) };
+}
+
+/// A panic hook, as taken from and set into the standard library.
+type PanicHook = Box<dyn Fn(&panic::PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+/// Install a panic hook that captures the location and backtrace of panics raised within
+/// `catch_panic_sync`, and defers to the previous hook for any other panic.
+fn install_hook() {
+    static INSTALL: Once = Once::new();
+
+    INSTALL.call_once(|| panic::set_hook(capturing_hook(panic::take_hook())));
+}
+
+/// Build the panic hook that `install_hook` installs, deferring to `previous`.
+fn capturing_hook(previous: PanicHook) -> PanicHook {
+    Box::new(move |info| {
+        if CATCHING.with(Cell::get) > 0 {
+            let location = info
+                .location()
+                .map(ToString::to_string)
+                .unwrap_or_else(|| String::from("<unknown>"));
+            LAST_PANIC
+                .with(|last| *last.borrow_mut() = Some((location, Backtrace::force_capture())));
+        } else {
+            previous(info)
+        }
+    })
+}
+
+/// Extract a human readable message from the payload of a panic.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("<non-string panic payload>")
+}
+
+/// Run a closure, turning any panic raised inside it into a JSON-RPC internal error.
+///
+/// The panic is logged along with its location and backtrace. `context` describes what was being
+/// executed, e.g. the name of a method.
+pub(crate) fn catch_panic_sync<R, F>(context: &str, f: F) -> Result<R, Error>
+where
+    F: FnOnce() -> R,
+{
+    install_hook();
+
+    CATCHING.with(|catching| catching.set(catching.get() + 1));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING.with(|catching| catching.set(catching.get() - 1));
+
+    result.map_err(|payload| {
+        let (location, backtrace) = LAST_PANIC
+            .with(|last| last.borrow_mut().take())
+            .map(|(location, backtrace)| (location, backtrace.to_string()))
+            .unwrap_or_default();
+        log::error!(
+            "Panic in JSON-RPC {} at {}: {}\n{}",
+            context,
+            location,
+            panic_message(&*payload),
+            backtrace
+        );
+
+        Error::internal_error()
+    })
+}
+
+/// A future that turns any panic raised while polling the future it wraps into a JSON-RPC
+/// internal error.
+pub(crate) struct CatchPanic<F> {
+    context: String,
+    inner: Option<Pin<Box<F>>>,
+}
+
+impl<F> Future for CatchPanic<F>
+where
+    F: Future,
+{
+    type Output = Result<F::Output, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let inner = match this.inner.as_mut() {
+            Some(inner) => inner,
+            None => return Poll::Ready(Err(Error::internal_error())),
+        };
+
+        match catch_panic_sync(&this.context, || inner.as_mut().poll(cx)) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Err(error) => {
+                // A future that panicked must never be polled again
+                this.inner = None;
+
+                Poll::Ready(Err(error))
+            }
+        }
+    }
+}
+
+/// Wrap a future so that panics raised while polling it become JSON-RPC internal errors.
+pub(crate) fn catch_panic<F>(context: &str, future: F) -> CatchPanic<F>
+where
+    F: Future,
+{
+    CatchPanic {
+        context: String::from(context),
+        inner: Some(Box::pin(future)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use futures::future;
+    use jsonrpc_core::{serde_json, ErrorCode, Params, Value};
+    use jsonrpc_pubsub::{Subscriber, SubscriptionId};
+
+    use super::*;
+    #[cfg(feature = "with_actix")]
+    use crate::server::ActixServer;
+    use crate::{
+        handler::Session,
+        server::{Server, WittyMultiServer},
+    };
+
+    /// Call a method of a server, returning the whole response.
+    fn call(server: &WittyMultiServer, method: &str) -> Value {
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": 1, "method": method });
+        let response = server
+            .handle_request_sync(&request.to_string(), Session::mock())
+            .unwrap();
+
+        serde_json::from_str(&response).unwrap()
+    }
+
+    /// Build a server with a `hello` method, for checking that it keeps answering after a panic.
+    fn server() -> WittyMultiServer {
+        let mut server = WittyMultiServer::new();
+        server.add_method("hello", |_| future::ok(Value::from("world")));
+
+        server
+    }
+
+    /// Check that calling a method is answered with an error code, and that the server keeps
+    /// answering other calls afterwards.
+    fn assert_panic_is_isolated(server: &WittyMultiServer, method: &str, code: ErrorCode) {
+        let response = call(server, method);
+        assert_eq!(response["error"]["code"], serde_json::json!(code.code()));
+
+        let response = call(server, "hello");
+        assert_eq!(response["result"], serde_json::json!("world"));
+    }
+
+    /// Start an actix system on its own thread.
+    #[cfg(feature = "with_actix")]
+    fn actix_system() -> actix::System {
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let system = actix::System::new();
+            tx.send(actix::System::current()).unwrap();
+            system.run().unwrap();
+        });
+
+        rx.recv().unwrap()
+    }
+
+    #[test]
+    fn panicking_methods_are_answered_with_an_internal_error() {
+        let mut server = server();
+        server.add_method(
+            "panics",
+            |_| -> future::Ready<jsonrpc_core::Result<Value>> { panic!("method panicked") },
+        );
+
+        assert_panic_is_isolated(&server, "panics", ErrorCode::InternalError);
+    }
+
+    #[test]
+    fn panicking_subscriptions_are_answered_with_an_internal_error() {
+        let mut server = server();
+        server.add_subscription(
+            "panics",
+            ("subscribePanics", |_: Params, _: Session, _: Subscriber| {
+                panic!("subscription panicked")
+            }),
+            (
+                "unsubscribePanics",
+                |_: SubscriptionId, _: Option<Session>| future::ok(Value::Bool(true)),
+            ),
+        );
+
+        assert_panic_is_isolated(&server, "subscribePanics", ErrorCode::InternalError);
+    }
+
+    #[cfg(feature = "with_actix")]
+    #[test]
+    fn panicking_actix_methods_are_answered_with_an_internal_error() {
+        let system = actix_system();
+        for system in [None, Some(system.clone())] {
+            let mut server = server();
+            server.add_actix_method(
+                &system,
+                "panics",
+                |_| -> future::Ready<jsonrpc_core::Result<Value>> { panic!("method panicked") },
+            );
+
+            assert_panic_is_isolated(&server, "panics", ErrorCode::InternalError);
+        }
+        system.stop();
+    }
+
+    #[cfg(feature = "with_actix")]
+    #[test]
+    fn panicking_actix_subscriptions_are_rejected() {
+        let system = actix_system();
+        for system in [None, Some(system.clone())] {
+            let mut server = server();
+            server.add_actix_subscription(
+                &system,
+                "panics",
+                (
+                    "subscribePanics",
+                    Arc::new(|_: Params, _: Session, _: Subscriber| panic!("subscription panicked")),
+                ),
+                (
+                    "unsubscribePanics",
+                    Arc::new(|_: SubscriptionId, _: Option<Session>| -> jsonrpc_core::BoxFuture<
+                        jsonrpc_core::Result<Value>,
+                    > { Box::pin(future::ok(Value::Bool(true))) }),
+                ),
+            );
+
+            // The subscriber is dropped by the panic, whether or not the method was spawned
+            assert_panic_is_isolated(&server, "subscribePanics", ErrorCode::ServerError(-32091));
+        }
+        system.stop();
+    }
+
+    #[test]
+    fn panics_outside_catch_panic_sync_reach_the_previous_hook() {
+        install_hook();
+        let original = Arc::new(panic::take_hook());
+        let forwarded = Arc::new(AtomicUsize::new(0));
+        let previous: PanicHook = {
+            let original = original.clone();
+            let forwarded = forwarded.clone();
+
+            // Panics of other tests running meanwhile go to the original hook
+            Box::new(move |info| {
+                if thread::current().name() == Some("panics-test") {
+                    forwarded.fetch_add(1, Ordering::SeqCst);
+                } else {
+                    original(info)
+                }
+            })
+        };
+        panic::set_hook(capturing_hook(previous));
+
+        let spawn = |f: fn()| {
+            thread::Builder::new()
+                .name(String::from("panics-test"))
+                .spawn(f)
+                .unwrap()
+                .join()
+        };
+        let caught = spawn(|| {
+            catch_panic_sync("test", || panic!("caught")).ok();
+        });
+        let uncaught = spawn(|| panic!("uncaught"));
+        panic::set_hook(Box::new(move |info| original(info)));
+
+        assert!(caught.is_ok());
+        assert!(uncaught.is_err());
+        assert_eq!(forwarded.load(Ordering::SeqCst), 1);
+    }
+}
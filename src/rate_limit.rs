@@ -0,0 +1,275 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use jsonrpc_core::{serde_json::json, Error, ErrorCode};
+
+use crate::handler::ConnectionMetadata;
+
+/// The JSON-RPC error code used for rejecting requests that exceed a rate limit.
+pub const RATE_LIMITED_ERROR_CODE: i64 = -32005;
+
+/// How often buckets that have been refilled up to their capacity are dropped from memory.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Enumerates the different criteria that requests can be grouped by for rate limiting purposes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RateLimitKey {
+    /// One bucket per remote IP address. Requests coming from transports that do not know the
+    /// address of the remote peer are not limited. Over HTTP, the address is only known through
+    /// the `X-Forwarded-For` header of the trusted proxies in the `ConnectionLimits` of the
+    /// transport.
+    Peer,
+    /// One bucket per session. Requests coming from transports that are not session based are not
+    /// limited.
+    Session,
+    /// One bucket per JSON-RPC method name, shared by all clients.
+    Method,
+}
+
+/// Settings needed for constructing a `RateLimiter`.
+#[derive(Clone, Debug)]
+pub struct RateLimiterSettings {
+    /// The criteria used for assigning requests to buckets.
+    pub key: RateLimitKey,
+    /// How many tokens a bucket can hold, i.e. the largest burst of requests that is allowed.
+    pub capacity: u32,
+    /// How many tokens are put back into every bucket per second, which must be a positive and
+    /// finite number.
+    pub refill_per_second: f64,
+}
+
+/// Enumerates the reasons why the settings of a `RateLimiter` can be rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateLimiterError {
+    /// The refill rate is zero, negative or not a finite number, so that buckets would never be
+    /// refilled, or never run out of tokens.
+    InvalidRefillRate(f64),
+}
+
+/// A single token bucket.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// A token bucket rate limiter that keeps one bucket per key.
+#[derive(Debug)]
+pub struct RateLimiter {
+    settings: RateLimiterSettings,
+    buckets: Mutex<(HashMap<String, Bucket>, Instant)>,
+}
+
+impl RateLimiter {
+    /// Create a new instance of a rate limiter.
+    ///
+    /// Fails if the refill rate is not a positive and finite number.
+    pub fn new(settings: RateLimiterSettings) -> Result<Self, RateLimiterError> {
+        let refill_per_second = settings.refill_per_second;
+        if !(refill_per_second.is_finite() && refill_per_second > 0.0) {
+            return Err(RateLimiterError::InvalidRefillRate(refill_per_second));
+        }
+
+        Ok(Self {
+            settings,
+            buckets: Mutex::new((HashMap::new(), Instant::now())),
+        })
+    }
+
+    /// Derive the bucket key that a call to `method` with metadata `meta` belongs to.
+    ///
+    /// Returns `None` if the call cannot be classified, in which case it should not be limited.
+    pub fn key_for<M>(&self, method: &str, meta: &M) -> Option<String>
+    where
+        M: ConnectionMetadata,
+    {
+        match self.settings.key {
+            RateLimitKey::Peer => meta.peer_addr().map(|addr| addr.ip().to_string()),
+            RateLimitKey::Session => meta.session_id().map(|id| id.to_string()),
+            RateLimitKey::Method => Some(String::from(method)),
+        }
+    }
+
+    /// Try to take one token out of the bucket for `key`.
+    ///
+    /// If the bucket is empty, returns how long the caller would need to wait before a token
+    /// becomes available.
+    pub fn try_acquire(&self, key: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let capacity = f64::from(self.settings.capacity);
+        let refill_per_second = self.settings.refill_per_second;
+        let (buckets, last_pruned) = &mut *self.buckets.lock().unwrap();
+
+        if now.duration_since(*last_pruned) >= PRUNE_INTERVAL {
+            buckets.retain(|_, bucket| {
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens + elapsed * refill_per_second < capacity
+            });
+            *last_pruned = now;
+        }
+
+        let bucket = buckets.entry(String::from(key)).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_second).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / refill_per_second;
+
+            Err(Duration::try_from_secs_f64(wait).unwrap_or(Duration::MAX))
+        }
+    }
+
+    /// Check whether a call to `method` with metadata `meta` is allowed, consuming a token if so.
+    pub fn check<M>(&self, method: &str, meta: &M) -> Result<(), Error>
+    where
+        M: ConnectionMetadata,
+    {
+        match self.key_for(method, meta) {
+            None => Ok(()),
+            Some(key) => self.try_acquire(&key).map_err(rate_limited_error),
+        }
+    }
+}
+
+/// Check a call against a list of rate limiters, stopping at the first one that rejects it.
+pub(crate) fn check_rate_limits<M>(
+    limiters: &[RateLimiter],
+    method: &str,
+    meta: &M,
+) -> Result<(), Error>
+where
+    M: ConnectionMetadata,
+{
+    limiters
+        .iter()
+        .try_for_each(|limiter| limiter.check(method, meta))
+}
+
+/// Build the JSON-RPC error that is returned when a request exceeds a rate limit.
+pub fn rate_limited_error(retry_after: Duration) -> Error {
+    let retry_after_ms = u64::try_from(retry_after.as_millis()).unwrap_or(u64::MAX);
+
+    Error {
+        code: ErrorCode::ServerError(RATE_LIMITED_ERROR_CODE),
+        message: String::from("Rate limit exceeded"),
+        data: Some(json!({ "retry_after_ms": retry_after_ms })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+    use crate::handler::Session;
+
+    fn limiter(key: RateLimitKey, capacity: u32, refill_per_second: f64) -> RateLimiter {
+        RateLimiter::new(RateLimiterSettings {
+            key,
+            capacity,
+            refill_per_second,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn bucket_allows_bursts_up_to_its_capacity() {
+        let limiter = limiter(RateLimitKey::Method, 3, 1.0);
+
+        for _ in 0..3 {
+            assert_eq!(limiter.try_acquire("a"), Ok(()));
+        }
+        let wait = limiter.try_acquire("a").unwrap_err();
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+
+        // Other keys have buckets of their own
+        assert_eq!(limiter.try_acquire("b"), Ok(()));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let limiter = limiter(RateLimitKey::Method, 1, 100.0);
+
+        assert_eq!(limiter.try_acquire("a"), Ok(()));
+        assert!(limiter.try_acquire("a").is_err());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(limiter.try_acquire("a"), Ok(()));
+    }
+
+    #[test]
+    fn refill_rates_that_are_not_positive_and_finite_are_rejected() {
+        for refill_per_second in [0.0, -1.0, f64::INFINITY] {
+            let settings = RateLimiterSettings {
+                key: RateLimitKey::Method,
+                capacity: 1,
+                refill_per_second,
+            };
+
+            assert_eq!(
+                RateLimiter::new(settings).unwrap_err(),
+                RateLimiterError::InvalidRefillRate(refill_per_second)
+            );
+        }
+
+        let settings = RateLimiterSettings {
+            key: RateLimitKey::Method,
+            capacity: 1,
+            refill_per_second: f64::NAN,
+        };
+        assert!(RateLimiter::new(settings).is_err());
+    }
+
+    #[test]
+    fn calls_are_keyed_by_peer_session_or_method() {
+        let mut meta = Session::mock();
+        meta.set_peer_addr(SocketAddr::from(([10, 0, 0, 1], 1234)));
+
+        assert_eq!(
+            limiter(RateLimitKey::Peer, 1, 1.0).key_for("a", &meta),
+            Some(String::from("10.0.0.1"))
+        );
+        assert_eq!(
+            limiter(RateLimitKey::Session, 1, 1.0).key_for("a", &meta),
+            meta.session_id().map(|id| id.to_string())
+        );
+        assert_eq!(
+            limiter(RateLimitKey::Method, 1, 1.0).key_for("a", &meta),
+            Some(String::from("a"))
+        );
+    }
+
+    #[test]
+    fn calls_without_a_key_are_not_limited() {
+        let limiter = limiter(RateLimitKey::Peer, 1, 1.0);
+        let meta = Session::default();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check("a", &meta), Ok(()));
+        }
+    }
+
+    #[test]
+    fn first_limiter_that_rejects_a_call_wins() {
+        let limiters = [
+            limiter(RateLimitKey::Method, 2, 1.0),
+            limiter(RateLimitKey::Method, 1, 1.0),
+        ];
+        let meta = Session::default();
+
+        assert_eq!(check_rate_limits(&limiters, "a", &meta), Ok(()));
+        let error = check_rate_limits(&limiters, "a", &meta).unwrap_err();
+        assert_eq!(error.code, ErrorCode::ServerError(RATE_LIMITED_ERROR_CODE));
+        assert!(error.data.unwrap()["retry_after_ms"].as_u64().unwrap() <= 1000);
+    }
+}
@@ -0,0 +1,240 @@
+use futures::future::{self, Either};
+use jsonrpc_core::{serde_json::json, Error, ErrorCode, Params, RpcMethodSimple, Value};
+
+use crate::metadata::ContentDescriptor;
+
+/// How many validation errors are reported back at most, so that the responses to calls with
+/// wildly wrong parameters stay small.
+const MAX_REPORTED_ERRORS: usize = 10;
+
+/// The error returned when a JSON Schema is itself invalid.
+pub type SchemaError = jsonschema::ValidationError<'static>;
+
+/// Wrap a JSON-RPC method so that the parameters of every call are validated against a JSON
+/// Schema before calling it.
+///
+/// Positional parameters are validated as an array, named parameters as an object, and calls
+/// without parameters as `null`. Calls with parameters that do not conform to the schema are
+/// answered with an invalid params error (`-32602`) that points at the failing fields, without
+/// calling the method.
+///
+/// Fails if `schema` is not a valid JSON Schema.
+pub fn with_schema<F>(schema: &Value, method: F) -> Result<impl RpcMethodSimple, SchemaError>
+where
+    F: RpcMethodSimple,
+{
+    let validator = jsonschema::validator_for(schema)?;
+
+    Ok(move |params: Params| {
+        let instance = match &params {
+            Params::None => Value::Null,
+            Params::Array(values) => Value::Array(values.clone()),
+            Params::Map(map) => Value::Object(map.clone()),
+        };
+        let errors = validator
+            .iter_errors(&instance)
+            .take(MAX_REPORTED_ERRORS)
+            .map(|error| {
+                (
+                    error.instance_path().as_str().to_string(),
+                    error.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            Either::Right(method.call(params))
+        } else {
+            Either::Left(future::err(invalid_params_error(&errors)))
+        }
+    })
+}
+
+/// Describe the parameters of a method out of the JSON Schema that they are validated against.
+///
+/// Object schemas are described by their properties, and array schemas by their `prefixItems`, or
+/// by their `items` if these are a list as in older drafts. Any other schema is described as a
+/// single parameter named `params` that conforms to the whole schema.
+pub(crate) fn params_metadata(schema: &Value) -> Vec<ContentDescriptor> {
+    let required = |name: &str| {
+        schema["required"]
+            .as_array()
+            .is_some_and(|required| required.iter().any(|value| value == name))
+    };
+    let min_items = schema["minItems"].as_u64().unwrap_or_default();
+
+    if let Some(properties) = schema["properties"].as_object() {
+        properties
+            .iter()
+            .map(|(name, schema)| ContentDescriptor {
+                required: required(name),
+                ..ContentDescriptor::new(name, schema.clone())
+            })
+            .collect()
+    } else if let Some(items) = schema["prefixItems"]
+        .as_array()
+        .or_else(|| schema["items"].as_array())
+    {
+        items
+            .iter()
+            .enumerate()
+            .map(|(position, schema)| ContentDescriptor {
+                required: (position as u64) < min_items,
+                ..ContentDescriptor::new(&format!("param{}", position), schema.clone())
+            })
+            .collect()
+    } else {
+        vec![ContentDescriptor::new("params", schema.clone())]
+    }
+}
+
+/// Build the JSON-RPC error that is returned when the parameters of a call do not conform to the
+/// schema of the method, out of the JSON Pointers to the failing fields and what is wrong with
+/// them.
+///
+/// The message tells about the first error, while the data lists all of them.
+pub fn invalid_params_error(errors: &[(String, String)]) -> Error {
+    let message = match errors.first() {
+        Some((path, message)) if path.is_empty() => format!("Invalid params: {}.", message),
+        Some((path, message)) => format!("Invalid params: {} at {}.", message, path),
+        None => String::from("Invalid params."),
+    };
+    let errors = errors
+        .iter()
+        .map(|(path, message)| json!({ "path": path, "message": message }))
+        .collect::<Vec<_>>();
+
+    Error {
+        code: ErrorCode::InvalidParams,
+        message,
+        data: Some(json!({ "errors": errors })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use jsonrpc_core::serde_json;
+
+    use super::*;
+
+    fn params(value: Value) -> Params {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Wrap a method that counts its calls and returns its parameters, validating them against a
+    /// schema.
+    fn counted_echo(schema: Value) -> (impl RpcMethodSimple, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let method = with_schema(&schema, move |params: Params| {
+            counter.fetch_add(1, Ordering::SeqCst);
+
+            future::ready(params.parse::<Value>())
+        })
+        .unwrap();
+
+        (method, calls)
+    }
+
+    fn call<M>(method: &M, params: Params) -> Result<Value, Error>
+    where
+        M: RpcMethodSimple,
+    {
+        futures::executor::block_on(method.call(params))
+    }
+
+    #[test]
+    fn valid_params_reach_the_method() {
+        let (method, calls) = counted_echo(json!({
+            "type": "array",
+            "prefixItems": [{ "type": "string" }, { "type": "integer" }],
+            "minItems": 1,
+        }));
+
+        assert_eq!(call(&method, params(json!(["a", 1]))), Ok(json!(["a", 1])));
+        assert_eq!(call(&method, params(json!(["a"]))), Ok(json!(["a"])));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn invalid_params_are_rejected_with_the_failing_fields() {
+        let (method, calls) = counted_echo(json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" }, "age": { "type": "integer" } },
+            "required": ["name"],
+        }));
+
+        let error = call(&method, params(json!({ "name": "a", "age": "old" }))).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert!(error.message.ends_with(" at /age."));
+        let errors = &error.data.unwrap()["errors"];
+        assert_eq!(errors.as_array().unwrap().len(), 1);
+        assert_eq!(errors[0]["path"], json!("/age"));
+
+        // Calls without parameters are validated as `null`
+        let error = call(&method, Params::None).unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert_eq!(error.data.unwrap()["errors"][0]["path"], json!(""));
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn reported_errors_are_bounded() {
+        let (method, _) = counted_echo(json!({ "type": "array", "items": { "type": "string" } }));
+
+        let error = call(&method, params(json!(vec![0; 50]))).unwrap_err();
+
+        let errors = error.data.unwrap()["errors"].as_array().unwrap().len();
+        assert_eq!(errors, MAX_REPORTED_ERRORS);
+    }
+
+    #[test]
+    fn invalid_schemas_are_rejected() {
+        let schema = json!({ "type": "not a type" });
+
+        assert!(with_schema(&schema, |_: Params| future::ok(Value::Null)).is_err());
+    }
+
+    #[test]
+    fn params_are_described_out_of_the_schema() {
+        let described = params_metadata(&json!({
+            "type": "object",
+            "properties": { "name": { "type": "string" }, "age": { "type": "integer" } },
+            "required": ["name"],
+        }));
+        let names = described
+            .iter()
+            .map(|param| (param.name.as_str(), param.required))
+            .collect::<Vec<_>>();
+        assert!(names.contains(&("name", true)));
+        assert!(names.contains(&("age", false)));
+
+        let described = params_metadata(&json!({
+            "type": "array",
+            "prefixItems": [{ "type": "string" }, { "type": "integer" }],
+            "minItems": 1,
+        }));
+        assert_eq!(
+            described,
+            vec![
+                ContentDescriptor::new("param0", json!({ "type": "string" })),
+                ContentDescriptor {
+                    required: false,
+                    ..ContentDescriptor::new("param1", json!({ "type": "integer" }))
+                },
+            ]
+        );
+
+        let schema = json!({ "type": "string" });
+        assert_eq!(
+            params_metadata(&schema),
+            vec![ContentDescriptor::new("params", schema)]
+        );
+    }
+}
@@ -1,15 +1,47 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
 
 #[cfg(feature = "with_actix")]
 use actix::System;
-use jsonrpc_core::{Metadata, RpcMethodSimple};
-use jsonrpc_pubsub::{PubSubHandler, SubscribeRpcMethod, UnsubscribeRpcMethod};
+use futures::FutureExt;
+use jsonrpc_core::{BoxFuture, MetaIoHandler, Metadata, Params, RpcMethod, RpcMethodSimple, Value};
+use jsonrpc_pubsub::{
+    new_subscription, PubSubHandler, SubscribeRpcMethod, Subscriber, SubscriptionId,
+    UnsubscribeRpcMethod,
+};
 
 use crate::{
-    handler::{Handler, Session},
+    access_log::{AccessLog, AccessLogSink},
+    concurrency::{ConcurrencyLimit, ConcurrencyLimiter, ConcurrencyLimiters},
+    deprecation::{alias_metadata, call_alias, Deprecation, DeprecationLog},
+    handler::{ConnectionMetadata, Handler, Session},
+    health::Readiness,
+    metadata::{
+        ContentDescriptor, DescribeParams, DescribeType, MethodMetadata, SubscriptionMetadata,
+    },
+    module::{Module, ModuleError},
+    openrpc::{OpenRpcInfo, DISCOVER_METHOD},
+    panics::{catch_panic, catch_panic_sync},
+    rate_limit::{check_rate_limits, RateLimiter},
+    slow_requests::{SlowRequest, SlowRequestLog, SlowRequestSettings},
+    timeout::with_timeout,
+    topics::{filter_parser, FilterParser, TopicFilter, Topics},
     transports::{Transport, TransportError},
+    typed::typed_method,
+    versioning::{ApiVersion, ApiVersions},
 };
 
+/// A type-erased JSON-RPC method.
+pub(crate) type BoxedMethod =
+    Arc<dyn Fn(Params) -> BoxFuture<jsonrpc_core::Result<Value>> + Send + Sync>;
+/// A type-erased JSON-RPC method that also gets access to the metadata of every call.
+type BoxedMetaMethod<M> =
+    Arc<dyn Fn(Params, M) -> BoxFuture<jsonrpc_core::Result<Value>> + Send + Sync>;
+
 /// A convenient type alias for a single transport server that supports PubSub.
 pub type WittyMonoServer = SingleTransportServer<PubSubHandler<Session>>;
 /// A convenient type alias for a multiple transports server that supports PubSub.
@@ -51,6 +83,83 @@ where
     where
         F: RpcMethodSimple;
 
+    /// Add a JSON-RPC method to the server, which will be aborted and answered with a timeout
+    /// error if it takes longer than `timeout` to complete.
+    fn add_method_with_timeout<F>(&mut self, name: &str, timeout: Duration, method: F)
+    where
+        F: RpcMethodSimple,
+    {
+        self.add_method(name, with_timeout(name, timeout, method))
+    }
+
+    /// Add a JSON-RPC method to the server, whose parameters are validated against a JSON Schema
+    /// before every call.
+    ///
+    /// Calls with parameters that do not conform to the schema are answered with an invalid params
+    /// error that points at the failing fields. Fails without adding the method if `schema` is not
+    /// a valid JSON Schema.
+    ///
+    /// The parameters in the metadata of the method are described out of the schema, keeping the
+    /// rest of its metadata if already set.
+    #[cfg(feature = "jsonschema")]
+    fn add_method_with_schema<F>(
+        &mut self,
+        name: &str,
+        schema: &Value,
+        method: F,
+    ) -> Result<(), crate::schema::SchemaError>
+    where
+        F: RpcMethodSimple,
+    {
+        self.add_method(name, crate::schema::with_schema(schema, method)?);
+
+        let mut metadata = self
+            .describe_api_detailed()
+            .into_iter()
+            .find(|metadata| metadata.name == name)
+            .unwrap_or_else(|| MethodMetadata::new(name));
+        metadata.params = crate::schema::params_metadata(schema);
+        self.set_method_metadata(metadata);
+
+        Ok(())
+    }
+
+    /// Add a JSON-RPC method that takes parameters of type `P` and returns a result of type `R`,
+    /// which are converted from and into JSON automatically.
+    ///
+    /// Calls with parameters that cannot be converted into `P` are answered with an invalid params
+    /// error. See `typed::typed_method` for how parameters are converted.
+    fn add_typed_method<P, R, F, X>(&mut self, name: &str, method: F)
+    where
+        P: serde::de::DeserializeOwned + Send + 'static,
+        R: serde::Serialize + Send + 'static,
+        F: Fn(P) -> X + Send + Sync + 'static,
+        X: Future<Output = jsonrpc_core::Result<R>> + Send + 'static,
+    {
+        self.add_method(name, typed_method(method))
+    }
+
+    /// Same as `add_typed_method`, but also describes the parameters and the result in the
+    /// metadata of the method out of `P` and `R`, keeping the rest of its metadata if already set.
+    fn add_typed_method_described<P, R, F, X>(&mut self, name: &str, method: F)
+    where
+        P: serde::de::DeserializeOwned + DescribeParams + Send + 'static,
+        R: serde::Serialize + DescribeType + Send + 'static,
+        F: Fn(P) -> X + Send + Sync + 'static,
+        X: Future<Output = jsonrpc_core::Result<R>> + Send + 'static,
+    {
+        self.add_typed_method(name, method);
+
+        let mut metadata = self
+            .describe_api_detailed()
+            .into_iter()
+            .find(|metadata| metadata.name == name)
+            .unwrap_or_else(|| MethodMetadata::new(name));
+        metadata.params = P::describe_params();
+        metadata.result = Some(ContentDescriptor::new("result", R::schema()));
+        self.set_method_metadata(metadata);
+    }
+
     /// Add a JSON-RPC subscription so the server.
     fn add_subscription<F, G>(
         &mut self,
@@ -63,6 +172,31 @@ where
 
     /// Get a list of all the supported JSON-RPC methods.
     fn describe_api(&self) -> Vec<String>;
+
+    /// Get the metadata of all the supported JSON-RPC methods, sorted by name.
+    ///
+    /// Methods added without metadata are only described by their name.
+    fn describe_api_detailed(&self) -> Vec<MethodMetadata>;
+
+    /// Add an alias of a JSON-RPC method, i.e. another name that it can also be called by.
+    ///
+    /// Aliases are resolved on every call, so they can be added before the method itself. They can
+    /// stand for any method, including subscribe and unsubscribe methods, `rpc.discover` and other
+    /// aliases, in which case calls go straight to the method at the end of the chain. Calls to
+    /// aliases of methods that do not exist, or to chains of aliases that loop, are answered with a
+    /// method not found error. Calls to aliases count towards the rate and concurrency limits of
+    /// their targets.
+    ///
+    /// If the alias is deprecated, every call to it is reported as a warning under the
+    /// `DEPRECATION_LOG_TARGET` log target and counted in the metrics of the server, if any.
+    fn add_alias(&mut self, alias: &str, target: &str, deprecation: Option<Deprecation>);
+
+    /// Set the metadata that describes a JSON-RPC method in `describe_api_detailed` and in the
+    /// OpenRPC document of the server.
+    ///
+    /// The metadata can be set before or after adding the method itself. The subscription that a
+    /// method belongs to is filled in by `add_subscription`, so it does not need to be set here.
+    fn set_method_metadata(&mut self, metadata: MethodMetadata);
 }
 
 /// A little extension of `Server` that allows seamless compatibility with the Actix framework.
@@ -80,8 +214,30 @@ where
     where
         F: RpcMethodSimple;
 
+    /// Add a JSON-RPC method that when executed will be spawned into an Actix `arbiter` if a
+    /// `system` is provided, and aborted if it takes longer than `timeout` to complete.
+    ///
+    /// The timeout covers the whole call, including the time spent waiting for the arbiter.
+    fn add_actix_method_with_timeout<F>(
+        &mut self,
+        system: &Option<actix::System>,
+        name: &str,
+        timeout: Duration,
+        method: F,
+    ) where
+        F: RpcMethodSimple,
+    {
+        self.add_method(
+            name,
+            with_timeout(name, timeout, actix_method(system, name, method)),
+        )
+    }
+
     /// Add a JSON-RPC subscription that when executed will be spawned into an Actix `arbiter` if a
     /// `system` is provided.
+    ///
+    /// If the subscribe method panics, its subscriber is dropped and the subscription is reported
+    /// as rejected.
     fn add_actix_subscription<F, G>(
         &mut self,
         system: &Option<actix::System>,
@@ -99,7 +255,6 @@ where
 /// A JSON-RPC server that supports using multiple transports at once.
 ///
 /// All the transports share the same underlying IO handler.
-#[derive(Default)]
 pub struct MultipleTransportsServer<H>
 where
     H: Handler,
@@ -107,6 +262,30 @@ where
     transports: Vec<Box<dyn Transport<H>>>,
     // TODO: Change Mutex for RwLock
     io_handler: Arc<Mutex<H>>,
+    rate_limiters: Arc<RwLock<Vec<RateLimiter>>>,
+    concurrency_limiters: Arc<RwLock<ConcurrencyLimiters>>,
+    access_log: AccessLog,
+    /// A snapshot of the IO handler behind the access log, for `handle_request_sync`, which is
+    /// taken again every time that the methods of the server change.
+    logged_io_handler: MetaIoHandler<H::Metadata, AccessLog>,
+    slow_request_log: SlowRequestLog,
+    readiness: Readiness,
+    /// The metadata of every method, including bare entries for those added without any.
+    api_metadata: Arc<RwLock<BTreeMap<String, MethodMetadata>>>,
+    /// Every method that the IO handler dispatches to, including subscribe and unsubscribe methods,
+    /// as originally added, for calling it through its aliases.
+    methods: Arc<RwLock<HashMap<String, BoxedMetaMethod<H::Metadata>>>>,
+    /// The target of every alias, for following chains of aliases.
+    aliases: Arc<RwLock<HashMap<String, String>>>,
+    deprecation_log: DeprecationLog,
+    /// The methods of every version of the API, for dispatching calls to versioned methods.
+    api_versions: Arc<RwLock<ApiVersions>>,
+    topics: Topics,
+    #[cfg(feature = "metrics")]
+    metrics: Option<crate::metrics::Metrics>,
+    /// The names of the subscribe and unsubscribe methods of every subscription.
+    #[cfg(feature = "metrics")]
+    subscriptions: Vec<(String, String)>,
 }
 
 impl<H> MultipleTransportsServer<H>
@@ -118,17 +297,332 @@ where
     where
         T: Transport<H> + 'static,
     {
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            transport.set_metrics(metrics.clone());
+        }
+        transport.set_readiness(self.readiness.clone());
+        transport.set_access_log(self.access_log.clone());
         transport.set_handler(self.io_handler.clone()).ok();
         self.transports.push(Box::new(transport));
+        self.update_readiness();
+    }
+
+    /// Add a rate limiter that every method call needs to get past before being dispatched.
+    ///
+    /// Rate limiters apply to all methods, including those that were added before the limiter.
+    pub fn add_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        self.rate_limiters.write().unwrap().push(rate_limiter);
+    }
+
+    /// Set how many method calls can be executing at the same time across the whole server, and
+    /// how many more can be queued before new ones are rejected with a "server busy" error.
+    ///
+    /// Passing `None` removes the limit.
+    pub fn set_concurrency_limit(&mut self, limit: Option<ConcurrencyLimit>) {
+        self.concurrency_limiters.write().unwrap().global = limit.map(ConcurrencyLimiter::new);
+    }
+
+    /// Set how many calls to a specific method can be executing at the same time, and how many more
+    /// can be queued before new ones are rejected with a "server busy" error.
+    ///
+    /// Passing `None` removes the limit.
+    pub fn set_method_concurrency_limit(&mut self, name: &str, limit: Option<ConcurrencyLimit>) {
+        let methods = &mut self.concurrency_limiters.write().unwrap().methods;
+        match limit {
+            Some(limit) => methods.insert(String::from(name), ConcurrencyLimiter::new(limit)),
+            None => methods.remove(name),
+        };
+    }
+
+    /// Add a sink that will receive an access log entry for every call handled by the server from
+    /// now on, including subscriptions and unsubscriptions, calls to unknown methods and calls that
+    /// the limits of the transports reject.
+    pub fn add_access_log_sink<S>(&mut self, sink: S)
+    where
+        S: AccessLogSink + 'static,
+    {
+        self.access_log.add_sink(Box::new(sink));
+    }
+
+    /// Report every method call that takes longer than a threshold as a warning under the
+    /// `SLOW_REQUEST_LOG_TARGET` log target, and keep the most recent ones for `slowest_requests`.
+    ///
+    /// Passing `None` disables the detection of slow calls.
+    pub fn set_slow_request_settings(&mut self, settings: Option<SlowRequestSettings>) {
+        self.slow_request_log.set_settings(settings);
+    }
+
+    /// Get the `n` slowest method calls among the most recent ones that took longer than the slow
+    /// request threshold, from slowest to fastest.
+    pub fn slowest_requests(&self, n: usize) -> Vec<SlowRequest> {
+        self.slow_request_log.slowest(n)
+    }
+
+    /// Set a check that needs to pass, along with all transports running, for the server to be
+    /// reported as ready by the readiness endpoint of the HTTP transport.
+    pub fn set_readiness_check<F>(&mut self, check: F)
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.readiness.set_check(Some(Box::new(check)));
+    }
+
+    /// Tell whether all the transports are running and the readiness check, if any, passes.
+    pub fn is_ready(&self) -> bool {
+        self.readiness.is_ready()
+    }
+
+    /// Build an OpenRPC document that describes all the methods and subscriptions of the server.
+    ///
+    /// Methods added without metadata are only described by their name.
+    pub fn openrpc_document(&self, info: &OpenRpcInfo) -> Value {
+        crate::openrpc::document(info, self.api_metadata.read().unwrap().values())
+    }
+
+    /// Add the `rpc.discover` method, which returns the OpenRPC document of the server.
+    ///
+    /// The document is built anew on every call, so it also covers methods added later on. If the
+    /// server has versions of its API, the document describes the version that the call asks for.
+    pub fn enable_discovery(&mut self, info: OpenRpcInfo) {
+        let api_metadata = self.api_metadata.clone();
+        let api_versions = self.api_versions.clone();
+
+        self.register_method(DISCOVER_METHOD, move |_params, meta| {
+            let methods = api_versions
+                .read()
+                .unwrap()
+                .describe(&api_metadata.read().unwrap(), meta.api_version());
+
+            Box::pin(futures::future::ok(crate::openrpc::document(
+                &info, &methods,
+            )))
+        });
+        self.set_method_metadata(MethodMetadata {
+            summary: Some(String::from(
+                "Get the OpenRPC document that describes the API of the server.",
+            )),
+            ..MethodMetadata::new(DISCOVER_METHOD)
+        });
+    }
+
+    /// Add a version of the API, i.e. a set of methods that take precedence over the rest of the
+    /// methods of the server for the calls that ask for `version`.
+    ///
+    /// Versions can share method names, e.g. for changing the semantics of a method in `v2`
+    /// without breaking the clients of `v1`. Calls to versioned methods are dispatched to the
+    /// version that they ask for through `ApiVersionSelection` in the settings of the transports,
+    /// or else to the default version. Methods added directly to the server are shared by all
+    /// versions.
+    ///
+    /// Fails without adding anything if the module has subscriptions, which cannot be versioned,
+    /// or if any of its method names is already taken by an unversioned method of the server.
+    /// Adding a version that already exists replaces it.
+    pub fn add_api_version(&mut self, version: &str, module: Module<H>) -> Result<(), ModuleError> {
+        if let Some(notification) = module.notifications().into_iter().next() {
+            return Err(ModuleError::VersionedSubscription(notification));
+        }
+
+        let mut methods = ApiVersion::new(self.deprecation_log.clone());
+        module.mount(&mut methods)?;
+        let mut metadata = Server::<H>::describe_api_detailed(&methods)
+            .into_iter()
+            .map(|metadata| (metadata.name.clone(), metadata))
+            .collect::<BTreeMap<_, _>>();
+
+        let new_names = {
+            let api_versions = self.api_versions.read().unwrap();
+            let existing = self.describe_api();
+            let mut new_names = Vec::new();
+            for name in Server::<H>::describe_api(&methods) {
+                if api_versions.is_versioned(&name) {
+                    continue;
+                }
+                if existing.contains(&name) {
+                    return Err(ModuleError::DuplicateName(name));
+                }
+                new_names.push(name);
+            }
+
+            new_names
+        };
+
+        if self
+            .api_versions
+            .write()
+            .unwrap()
+            .insert(version, methods)
+            .is_some()
+        {
+            log::warn!("Replacing version {} of the JSON-RPC API", version);
+        }
+
+        // Every versioned name is served by a single method that dispatches to the right version,
+        // which is described just like in the first version that has it
+        for name in new_names {
+            let api_versions = self.api_versions.clone();
+            let method_name = name.clone();
+            let admission_versions = self.api_versions.clone();
+            let admission_name = name.clone();
+
+            // Versioned aliases share the limits of their targets in the version that is called
+            let admitted_as = move |meta: &H::Metadata| {
+                admission_versions
+                    .read()
+                    .unwrap()
+                    .alias_target(&admission_name, meta.api_version())
+                    .unwrap_or_else(|| admission_name.clone())
+            };
+            self.register_method_admitted_as(&name, admitted_as, move |params, meta| {
+                let method = api_versions
+                    .read()
+                    .unwrap()
+                    .method(&method_name, meta.api_version());
+
+                Box::pin(async move { method?(params).await })
+            });
+            if let Some(metadata) = metadata.remove(&name) {
+                self.set_method_metadata(metadata);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Set the version of the API that calls to versioned methods are dispatched to if they do not
+    /// ask for any, e.g. the oldest one for the sake of clients that predate versioning.
+    ///
+    /// If `None`, such calls are answered with an unsupported API version error.
+    pub fn set_default_api_version(&mut self, version: Option<&str>) {
+        self.api_versions.write().unwrap().set_default(version);
+    }
+
+    /// Get the metadata of all the methods that calls asking for `version` can reach, i.e. those
+    /// shared by all versions plus those of that version, sorted by name.
+    pub fn describe_api_version(&self, version: &str) -> Vec<MethodMetadata> {
+        self.api_versions
+            .read()
+            .unwrap()
+            .describe(&self.api_metadata.read().unwrap(), Some(version))
+    }
+
+    /// Add a topic that clients can subscribe to through the `subscribe` method and unsubscribe
+    /// from through the `unsubscribe` method, without any parameters.
+    ///
+    /// The server keeps track of the subscribers by itself, so that notifications can be pushed to
+    /// all of them at once through `publish`. Notifications are sent under the name of the topic.
+    pub fn add_topic(&mut self, topic: &str, subscribe: &str, unsubscribe: &str) {
+        self.add_topic_with_filter(topic, subscribe, unsubscribe, None)
+    }
+
+    /// Add a topic just like `add_topic`, but whose subscribers can send parameters to `filter`
+    /// for getting only the notifications that match them.
+    ///
+    /// Subscribers that send no parameters get every notification, while those that send invalid
+    /// ones are rejected.
+    pub fn add_filtered_topic<F>(
+        &mut self,
+        topic: &str,
+        subscribe: &str,
+        unsubscribe: &str,
+        filter: F,
+    ) where
+        F: TopicFilter,
+    {
+        self.add_topic_with_filter(topic, subscribe, unsubscribe, Some(filter_parser(filter)))
+    }
+
+    /// Add a topic whose subscribers can be filtered or not.
+    fn add_topic_with_filter(
+        &mut self,
+        topic: &str,
+        subscribe: &str,
+        unsubscribe: &str,
+        filter: Option<FilterParser>,
+    ) {
+        let subscribe_topics = self.topics.clone();
+        let unsubscribe_topics = self.topics.clone();
+        let subscribe_topic = String::from(topic);
+        let unsubscribe_topic = String::from(topic);
+
+        self.add_subscription(
+            topic,
+            (
+                subscribe,
+                move |params, meta: H::Metadata, subscriber: Subscriber| {
+                    subscribe_topics.subscribe(
+                        &subscribe_topic,
+                        params,
+                        &meta,
+                        subscriber,
+                        filter.as_ref(),
+                    )
+                },
+            ),
+            (
+                unsubscribe,
+                move |id: SubscriptionId, meta: Option<H::Metadata>| {
+                    futures::future::ready(unsubscribe_topics.unsubscribe(
+                        &unsubscribe_topic,
+                        &id,
+                        meta.as_ref(),
+                    ))
+                },
+            ),
+        );
+        self.set_method_metadata(MethodMetadata {
+            summary: Some(format!("Subscribe to {} notifications.", topic)),
+            ..MethodMetadata::new(subscribe)
+        });
+        self.set_method_metadata(MethodMetadata {
+            summary: Some(format!("Unsubscribe from {} notifications.", topic)),
+            ..MethodMetadata::new(unsubscribe)
+        });
+    }
+
+    /// Notify every subscriber to a topic added through `add_topic`, across all transports,
+    /// returning how many of them were notified.
+    pub fn publish<T>(&self, topic: &str, value: &T) -> usize
+    where
+        T: serde::Serialize,
+    {
+        self.topics.publish(topic, value)
+    }
+
+    /// Get a handle to the subscribers to the topics of the server, for publishing notifications
+    /// from elsewhere, e.g. a background thread.
+    pub fn topics(&self) -> Topics {
+        self.topics.clone()
+    }
+
+    /// Start recording Prometheus metrics about the calls, connections and subscriptions handled
+    /// by every transport of the server.
+    ///
+    /// The metrics can be exposed through `HttpTransportSettings::metrics_path` or a
+    /// `MetricsServer`.
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics(&mut self, metrics: crate::metrics::Metrics) {
+        for (subscribe, unsubscribe) in &self.subscriptions {
+            metrics.register_subscription(subscribe, unsubscribe);
+        }
+        self.deprecation_log.set_metrics(metrics.clone());
+        self.on_every_transport(|transport| {
+            transport.set_metrics(metrics.clone());
+
+            Ok(())
+        })
+        .ok();
+        self.metrics = Some(metrics);
+        self.reset_all_transports().ok();
     }
 
     /// Programmatically trigger the handling of a JSON-RPC message inside the IO handler that the
     /// server wraps.
+    ///
+    /// Messages handled this way are also recorded in the access log, just like those that come
+    /// through the transports.
     pub fn handle_request_sync(&self, request: &str, meta: H::Metadata) -> Option<String> {
-        self.io_handler
-            .lock()
-            .unwrap()
-            .handle_request_sync(request, meta)
+        self.logged_io_handler.handle_request_sync(request, meta)
     }
 
     /// Apply the same closure on every single transport added to this server.
@@ -142,11 +636,119 @@ where
             .collect::<Result<Vec<_>, _>>()
     }
 
+    /// Add a JSON-RPC method that also gets access to the metadata of every call, wrapped in all
+    /// the rate limiting, concurrency limiting, logging and panic isolation that every method of the
+    /// server goes through.
+    fn register_method<F>(&mut self, name: &str, method: F)
+    where
+        F: Fn(Params, H::Metadata) -> BoxFuture<jsonrpc_core::Result<Value>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let method_name = String::from(name);
+
+        self.register_method_admitted_as(name, move |_meta| method_name.clone(), method)
+    }
+
+    /// Same as `register_method`, but with every call subject to the rate and concurrency limits of
+    /// the method that `admitted_as` tells, e.g. the target of an alias.
+    fn register_method_admitted_as<A, F>(&mut self, name: &str, admitted_as: A, method: F)
+    where
+        A: Fn(&H::Metadata) -> String + Send + Sync + 'static,
+        F: Fn(Params, H::Metadata) -> BoxFuture<jsonrpc_core::Result<Value>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let rate_limiters = self.rate_limiters.clone();
+        let concurrency_limiters = self.concurrency_limiters.clone();
+        let slow_request_log = self.slow_request_log.clone();
+        let method_name = String::from(name);
+        let method = Arc::new(method);
+
+        // Use a `Module` to have duplicates rejected instead
+        if self.describe_api().iter().any(|existing| existing == name) {
+            log::warn!(
+                "Replacing JSON-RPC method {}, which had already been added",
+                name
+            );
+        }
+        self.api_metadata
+            .write()
+            .unwrap()
+            .entry(method_name.clone())
+            .or_insert_with(|| MethodMetadata::new(name));
+        self.methods
+            .write()
+            .unwrap()
+            .insert(method_name.clone(), method.clone());
+        self.aliases.write().unwrap().remove(name);
+
+        (*self.io_handler.lock().unwrap()).add_method_with_meta(
+            name,
+            move |params, meta: H::Metadata| {
+                let method = method.clone();
+                let context = format!("method {}", method_name);
+                let timing = slow_request_log.begin(&method_name, &meta, &params);
+                let admission_name = admitted_as(&meta);
+                let admission =
+                    check_rate_limits(&rate_limiters.read().unwrap(), &admission_name, &meta)
+                        .and_then(|_| {
+                            concurrency_limiters
+                                .read()
+                                .unwrap()
+                                .acquire(&admission_name)
+                        });
+
+                Box::pin(async move {
+                    let execution = async move {
+                        // The permits are held until the method completes
+                        let _permits = admission?.await?;
+
+                        catch_panic(&context, async move { method(params, meta).await }).await?
+                    };
+                    let response = execution.await;
+                    if let Some(timing) = timing {
+                        timing.finish();
+                    }
+
+                    response
+                })
+            },
+        );
+        self.reset_all_transports().ok();
+    }
+
+    /// Record whether all the transports are running, for the readiness endpoint to report.
+    fn update_readiness(&self) {
+        let running = self.transports.iter().all(|transport| transport.running());
+        self.readiness.set_transports_running(running);
+    }
+
     /// Create a new server with everything set to its defaults.
     pub fn new() -> Self {
+        let access_log = AccessLog::default();
+
         Self {
             transports: vec![],
             io_handler: Arc::new(Mutex::new(H::new())),
+            rate_limiters: Default::default(),
+            concurrency_limiters: Default::default(),
+            logged_io_handler: MetaIoHandler::with_middleware(access_log.clone()),
+            access_log,
+            slow_request_log: Default::default(),
+            readiness: Default::default(),
+            api_metadata: Default::default(),
+            methods: Default::default(),
+            aliases: Default::default(),
+            deprecation_log: Default::default(),
+            api_versions: Default::default(),
+            topics: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            #[cfg(feature = "metrics")]
+            subscriptions: Vec::new(),
         }
     }
 
@@ -156,8 +758,11 @@ where
     /// cannot benefit from the `Arc` around the IO handler.
     fn reset_all_transports(&mut self) -> Result<(), TransportError> {
         let handler = self.io_handler.clone();
+        let mut logged_io_handler = MetaIoHandler::with_middleware(self.access_log.clone());
+        logged_io_handler.extend_with(handler.lock().unwrap().as_meta_io_handler());
+        self.logged_io_handler = logged_io_handler;
 
-        self.on_every_transport(|transport| {
+        let result = self.on_every_transport(|transport| {
             if transport.requires_reset() {
                 let running = transport.running();
                 if running {
@@ -169,12 +774,23 @@ where
                 }
             }
             Ok(())
-        })?;
+        });
+        self.update_readiness();
+        result?;
 
         Ok(())
     }
 }
 
+impl<H> Default for MultipleTransportsServer<H>
+where
+    H: Handler,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<H> Server<H> for MultipleTransportsServer<H>
 where
     H: Handler,
@@ -183,13 +799,17 @@ where
     type Error = ServerError;
 
     fn start(&mut self) -> Result<(), Self::Error> {
-        let _ = &self.on_every_transport(|transport| transport.start())?;
+        let result = self.on_every_transport(|transport| transport.start());
+        self.update_readiness();
+        result?;
 
         Ok(())
     }
 
     fn stop(&mut self) -> Result<(), Self::Error> {
-        let _ = &self.on_every_transport(Transport::stop)?;
+        let result = self.on_every_transport(Transport::stop);
+        self.update_readiness();
+        result?;
 
         Ok(())
     }
@@ -198,8 +818,13 @@ where
     where
         F: RpcMethodSimple,
     {
-        (*self.io_handler.lock().unwrap()).add_method(name, method);
-        self.reset_all_transports().ok();
+        let method = Arc::new(method);
+
+        self.register_method(name, move |params, _meta| {
+            let method = method.clone();
+
+            Box::pin(async move { method.call(params).await })
+        });
     }
 
     fn add_subscription<F, G>(
@@ -211,13 +836,179 @@ where
         F: SubscribeRpcMethod<H::Metadata>,
         G: UnsubscribeRpcMethod<H::Metadata>,
     {
-        (*self.io_handler.lock().unwrap()).add_subscription(notification, subscribe, unsubscribe);
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(metrics) = &self.metrics {
+                metrics.register_subscription(subscribe.0, unsubscribe.0);
+            }
+            self.subscriptions
+                .push((String::from(subscribe.0), String::from(unsubscribe.0)));
+        }
+        let subscription = SubscriptionMetadata {
+            notification: String::from(notification),
+            subscribe: String::from(subscribe.0),
+            unsubscribe: String::from(unsubscribe.0),
+        };
+        for name in [subscribe.0, unsubscribe.0] {
+            self.api_metadata
+                .write()
+                .unwrap()
+                .entry(String::from(name))
+                .or_insert_with(|| MethodMetadata::new(name))
+                .subscription = Some(subscription.clone());
+        }
+
+        let rate_limiters = self.rate_limiters.clone();
+        let (subscribe_name, subscribe_method) = subscribe;
+        let (unsubscribe_name, unsubscribe_method) = unsubscribe;
+        let subscribe_method = Arc::new(subscribe_method);
+        let unsubscribe_method = Arc::new(unsubscribe_method);
+        let subscribe_context = format!("subscription method {}", subscribe_name);
+        let unsubscribe_context = format!("unsubscription method {}", unsubscribe_name);
+        let method_name = String::from(subscribe_name);
+
+        // Aliases call the subscription without going through the limits and logging below, as
+        // they go through their own
+        let (raw_subscribe, raw_unsubscribe) = {
+            let subscribe_method = subscribe_method.clone();
+            let unsubscribe_method = unsubscribe_method.clone();
+
+            new_subscription(
+                notification,
+                move |params, meta: H::Metadata, subscriber: Subscriber| {
+                    subscribe_method.call(params, meta, subscriber)
+                },
+                move |id: SubscriptionId, meta: Option<H::Metadata>| {
+                    unsubscribe_method.call(id, meta)
+                },
+            )
+        };
+        let raw_subscribe = Arc::new(raw_subscribe);
+        let raw_unsubscribe = Arc::new(raw_unsubscribe);
+        let mut methods = self.methods.write().unwrap();
+        methods.insert(
+            String::from(subscribe_name),
+            Arc::new(move |params, meta| raw_subscribe.call(params, meta)),
+        );
+        methods.insert(
+            String::from(unsubscribe_name),
+            Arc::new(move |params, meta| raw_unsubscribe.call(params, meta)),
+        );
+        drop(methods);
+        let mut aliases = self.aliases.write().unwrap();
+        aliases.remove(subscribe_name);
+        aliases.remove(unsubscribe_name);
+        drop(aliases);
+
+        let (subscribe, unsubscribe) = new_subscription(
+            notification,
+            move |params, meta: H::Metadata, subscriber: Subscriber| {
+                let allowed =
+                    check_rate_limits(&rate_limiters.read().unwrap(), &method_name, &meta);
+
+                match allowed {
+                    Ok(()) => subscribe_method.call(params, meta, subscriber),
+                    Err(error) => {
+                        subscriber.reject(error).ok();
+                    }
+                }
+            },
+            move |id: SubscriptionId, meta: Option<H::Metadata>| {
+                let method = unsubscribe_method.clone();
+                let execution = async move { method.call(id, meta).await };
+
+                catch_panic(&unsubscribe_context, execution)
+                    .map(|response| response.and_then(|response| response))
+            },
+        );
+        let mut io_handler = self.io_handler.lock().unwrap();
+        io_handler.add_method_with_meta(subscribe_name, move |params, meta: H::Metadata| {
+            // The subscriber of a method that panics is dropped, which would otherwise be reported
+            // as a rejection of the subscription
+            catch_panic_sync(&subscribe_context, || subscribe.call(params, meta))
+                .unwrap_or_else(|error| Box::pin(futures::future::err(error)))
+        });
+        io_handler.add_method_with_meta(unsubscribe_name, unsubscribe);
+        drop(io_handler);
         self.reset_all_transports().ok();
     }
 
     fn describe_api(&self) -> Vec<String> {
         self.io_handler.lock().unwrap().describe_api()
     }
+
+    fn add_alias(&mut self, alias: &str, target: &str, deprecation: Option<Deprecation>) {
+        let methods = self.methods.clone();
+        let aliases = self.aliases.clone();
+        let admission_aliases = self.aliases.clone();
+        let deprecation_log = self.deprecation_log.clone();
+        let alias_name = String::from(alias);
+        let target_name = String::from(target);
+        let admission_name = String::from(target);
+
+        // Aliases are described just like their targets, unless these are added later on
+        let metadata = alias_metadata(
+            alias,
+            target,
+            self.api_metadata.read().unwrap().get(target),
+            deprecation.as_ref(),
+        );
+
+        // Aliases can stand for any method that the IO handler dispatches to, including versioned
+        // methods in whatever version the call asks for, and share the rate and concurrency limits
+        // of the method at the end of their chain
+        let admitted_as = move |_meta: &H::Metadata| {
+            resolve_alias(&admission_aliases.read().unwrap(), &admission_name)
+                .unwrap_or_else(|| admission_name.clone())
+        };
+        self.register_method_admitted_as(alias, admitted_as, move |params, meta| {
+            let method = resolve_alias(&aliases.read().unwrap(), &target_name)
+                .and_then(|name| methods.read().unwrap().get(&name).cloned());
+
+            call_alias(
+                &alias_name,
+                &target_name,
+                deprecation.as_ref(),
+                &deprecation_log,
+                method.map(|method| method(params, meta)),
+            )
+        });
+        self.aliases
+            .write()
+            .unwrap()
+            .insert(String::from(alias), String::from(target));
+        self.set_method_metadata(metadata);
+    }
+
+    fn describe_api_detailed(&self) -> Vec<MethodMetadata> {
+        self.api_metadata
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    fn set_method_metadata(&mut self, mut metadata: MethodMetadata) {
+        let mut api_metadata = self.api_metadata.write().unwrap();
+        if let Some(existing) = api_metadata.get_mut(&metadata.name) {
+            metadata.subscription = metadata.subscription.or(existing.subscription.take());
+        }
+        api_metadata.insert(metadata.name.clone(), metadata);
+    }
+}
+
+/// Follow a chain of aliases from `target` to the method that it ends at, if it does not loop.
+fn resolve_alias(aliases: &HashMap<String, String>, target: &str) -> Option<String> {
+    let mut name = target;
+    for _ in 0..=aliases.len() {
+        match aliases.get(name) {
+            Some(next) => name = next,
+            None => return Some(String::from(name)),
+        }
+    }
+
+    None
 }
 
 #[cfg(feature = "with_actix")]
@@ -229,32 +1020,7 @@ where
     where
         F: RpcMethodSimple,
     {
-        let system = system.clone();
-
-        self.add_method(name, move |params| {
-            let system = system.clone();
-            let execution = method.call(params);
-            let (tx, rx) = futures::channel::oneshot::channel();
-
-            Box::pin(async move {
-                // The future that will actually execute the method
-                let fut = async move {
-                    let response = execution.await;
-                    tx.send(response)
-                        .expect("Should be able to send result back to spawner");
-                };
-
-                // If an actix system is available, spawn there, otherwise simply wait on the future
-                if let Some(system) = system.clone() {
-                    system.arbiter().spawn(fut);
-                } else {
-                    fut.await;
-                }
-
-                rx.await
-                    .expect("Should be able to await the oneshot channel")
-            })
-        })
+        self.add_method(name, actix_method(system, name, method))
     }
 
     fn add_actix_subscription<F, G>(
@@ -274,23 +1040,35 @@ where
         let unsubscribe_system = system.clone();
         let (subscribe_name, subscribe_method) = subscribe;
         let (unsubscribe_name, unsubscribe_method) = unsubscribe;
+        let subscribe_context = format!("subscription method {}", subscribe_name);
+        let unsubscribe_context = format!("unsubscription method {}", unsubscribe_name);
 
         self.add_subscription(
             notification,
             (subscribe_name, move |params, meta, subscriber| {
                 let method = subscribe_method.clone();
+                let context = subscribe_context.clone();
 
                 // If an actix system is available, spawn there, otherwise simply wait on the future
                 if let Some(system) = subscribe_system.clone() {
-                    system.arbiter().spawn(async move {
-                        method.call(params, meta, subscriber);
-                    });
+                    let fut = async move {
+                        catch_panic_sync(&context, || method.call(params, meta, subscriber)).ok();
+                    };
+                    // Keep the span of the call when the method is executed inside the arbiter
+                    #[cfg(feature = "tracing")]
+                    let fut = tracing::Instrument::in_current_span(fut);
+                    // Same for the remote trace context that the call was sent with
+                    #[cfg(feature = "opentelemetry")]
+                    let fut = opentelemetry::context::FutureExt::with_current_context(fut);
+
+                    system.arbiter().spawn(fut);
                 } else {
-                    method.call(params, meta, subscriber);
+                    catch_panic_sync(&context, || method.call(params, meta, subscriber)).ok();
                 }
             }),
             (unsubscribe_name, move |id, meta| {
                 let system = unsubscribe_system.clone();
+                let context = unsubscribe_context.clone();
                 let method = unsubscribe_method.clone();
                 let execution = method.call(id, meta);
                 let (tx, rx) = futures::channel::oneshot::channel();
@@ -298,11 +1076,18 @@ where
                 Box::pin(async move {
                     // The future that will actually execute the method
                     let fut = async move {
-                        let response = execution.await;
-                        tx.send(response)
-                            .expect("Should be able to send result back to spawner");
+                        let response = catch_panic(&context, execution).await;
+                        // The receiver is gone if the caller stopped waiting for the response
+                        tx.send(response.and_then(|response| response)).ok();
                     };
 
+                    // Keep the span of the call when the method is executed inside the arbiter
+                    #[cfg(feature = "tracing")]
+                    let fut = tracing::Instrument::in_current_span(fut);
+                    // Same for the remote trace context that the call was sent with
+                    #[cfg(feature = "opentelemetry")]
+                    let fut = opentelemetry::context::FutureExt::with_current_context(fut);
+
                     // If an actix system is available, spawn there, otherwise simply wait on the future
                     if let Some(system) = system.clone() {
                         system.arbiter().spawn(fut);
@@ -310,14 +1095,83 @@ where
                         fut.await;
                     }
 
-                    rx.await
-                        .expect("Should be able to await the oneshot channel")
+                    rx.await.unwrap_or_else(|_| Err(canceled_execution_error()))
                 })
             }),
         )
     }
 }
 
+/// Wrap a JSON-RPC method so that when executed it is spawned into an Actix `arbiter` if a `system`
+/// is provided.
+///
+/// The execution is aborted if the call stops being waited for, e.g. because it timed out.
+#[cfg(feature = "with_actix")]
+fn actix_method<F>(system: &Option<actix::System>, name: &str, method: F) -> impl RpcMethodSimple
+where
+    F: RpcMethodSimple,
+{
+    let system = system.clone();
+    let context = format!("method {}", name);
+
+    move |params| {
+        let system = system.clone();
+        let context = context.clone();
+        let execution = method.call(params);
+        let (tx, rx) = futures::channel::oneshot::channel();
+
+        Box::pin(async move {
+            // The future that will actually execute the method
+            let fut = async move {
+                let response = catch_panic(&context, execution).await;
+                // The receiver is gone if the caller stopped waiting for the response
+                tx.send(response.and_then(|response| response)).ok();
+            };
+
+            // Keep the span of the call when the method is executed inside the arbiter
+            #[cfg(feature = "tracing")]
+            let fut = tracing::Instrument::in_current_span(fut);
+            // Same for the remote trace context that the call was sent with
+            #[cfg(feature = "opentelemetry")]
+            let fut = opentelemetry::context::FutureExt::with_current_context(fut);
+
+            // If an actix system is available, spawn there, otherwise simply wait on the future
+            let _abort_on_drop = if let Some(system) = system.clone() {
+                let (fut, abort_handle) = futures::future::abortable(fut);
+                system.arbiter().spawn(fut.map(|_| ()));
+
+                Some(AbortOnDrop(abort_handle))
+            } else {
+                fut.await;
+
+                None
+            };
+
+            rx.await.unwrap_or_else(|_| Err(canceled_execution_error()))
+        })
+    }
+}
+
+/// Aborts a method spawned into an Actix arbiter once the call stops being waited for.
+#[cfg(feature = "with_actix")]
+struct AbortOnDrop(futures::future::AbortHandle);
+
+#[cfg(feature = "with_actix")]
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Build the error that is returned when a method spawned into an Actix arbiter is dropped before
+/// completing, e.g. because the arbiter is shutting down.
+#[cfg(feature = "with_actix")]
+fn canceled_execution_error() -> jsonrpc_core::Error {
+    log::error!("A JSON-RPC method spawned into an Actix arbiter was dropped before completing");
+
+    jsonrpc_core::Error::internal_error()
+}
+
 /// A simple JSON-RPC server that only uses one transport.
 pub struct SingleTransportServer<H>
 where
@@ -330,6 +1184,118 @@ impl<H> SingleTransportServer<H>
 where
     H: Handler,
 {
+    /// Add a rate limiter that every method call needs to get past before being dispatched.
+    pub fn add_rate_limiter(&mut self, rate_limiter: RateLimiter) {
+        self.inner.add_rate_limiter(rate_limiter)
+    }
+
+    /// Add a sink that will receive an access log entry for every call handled by the server.
+    pub fn add_access_log_sink<S>(&mut self, sink: S)
+    where
+        S: AccessLogSink + 'static,
+    {
+        self.inner.add_access_log_sink(sink)
+    }
+
+    /// Report every method call that takes longer than a threshold, and keep the most recent ones.
+    pub fn set_slow_request_settings(&mut self, settings: Option<SlowRequestSettings>) {
+        self.inner.set_slow_request_settings(settings)
+    }
+
+    /// Get the `n` slowest method calls among the most recent slow ones.
+    pub fn slowest_requests(&self, n: usize) -> Vec<SlowRequest> {
+        self.inner.slowest_requests(n)
+    }
+
+    /// Set a check that needs to pass, along with the transport running, for the server to be
+    /// reported as ready.
+    pub fn set_readiness_check<F>(&mut self, check: F)
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.inner.set_readiness_check(check)
+    }
+
+    /// Tell whether the transport is running and the readiness check, if any, passes.
+    pub fn is_ready(&self) -> bool {
+        self.inner.is_ready()
+    }
+
+    /// Build an OpenRPC document that describes all the methods and subscriptions of the server.
+    pub fn openrpc_document(&self, info: &OpenRpcInfo) -> Value {
+        self.inner.openrpc_document(info)
+    }
+
+    /// Add the `rpc.discover` method, which returns the OpenRPC document of the server.
+    pub fn enable_discovery(&mut self, info: OpenRpcInfo) {
+        self.inner.enable_discovery(info)
+    }
+
+    /// Add a version of the API, i.e. a set of methods for the calls that ask for `version`.
+    pub fn add_api_version(&mut self, version: &str, module: Module<H>) -> Result<(), ModuleError> {
+        self.inner.add_api_version(version, module)
+    }
+
+    /// Set the version of the API that calls to versioned methods are dispatched to if they do not
+    /// ask for any.
+    pub fn set_default_api_version(&mut self, version: Option<&str>) {
+        self.inner.set_default_api_version(version)
+    }
+
+    /// Get the metadata of all the methods that calls asking for `version` can reach.
+    pub fn describe_api_version(&self, version: &str) -> Vec<MethodMetadata> {
+        self.inner.describe_api_version(version)
+    }
+
+    /// Add a topic whose subscribers are kept track of by the server itself.
+    pub fn add_topic(&mut self, topic: &str, subscribe: &str, unsubscribe: &str) {
+        self.inner.add_topic(topic, subscribe, unsubscribe)
+    }
+
+    /// Add a topic whose subscribers can get only the notifications that match their parameters.
+    pub fn add_filtered_topic<F>(
+        &mut self,
+        topic: &str,
+        subscribe: &str,
+        unsubscribe: &str,
+        filter: F,
+    ) where
+        F: TopicFilter,
+    {
+        self.inner
+            .add_filtered_topic(topic, subscribe, unsubscribe, filter)
+    }
+
+    /// Notify every subscriber to a topic, returning how many of them were notified.
+    pub fn publish<T>(&self, topic: &str, value: &T) -> usize
+    where
+        T: serde::Serialize,
+    {
+        self.inner.publish(topic, value)
+    }
+
+    /// Get a handle to the subscribers to the topics of the server.
+    pub fn topics(&self) -> Topics {
+        self.inner.topics()
+    }
+
+    /// Start recording Prometheus metrics about the calls, connections and subscriptions handled
+    /// by the server.
+    #[cfg(feature = "metrics")]
+    pub fn set_metrics(&mut self, metrics: crate::metrics::Metrics) {
+        self.inner.set_metrics(metrics)
+    }
+
+    /// Set how many method calls can be executing at the same time across the whole server.
+    pub fn set_concurrency_limit(&mut self, limit: Option<ConcurrencyLimit>) {
+        self.inner.set_concurrency_limit(limit)
+    }
+
+    /// Set how many calls to a specific method can be executing at the same time.
+    pub fn set_method_concurrency_limit(&mut self, name: &str, limit: Option<ConcurrencyLimit>) {
+        self.inner.set_method_concurrency_limit(name, limit)
+    }
+
     /// Create a simple server around an already existing instance of a transport.
     pub fn from_transport<T>(transport: T) -> Self
     where
@@ -393,6 +1359,18 @@ where
     fn describe_api(&self) -> Vec<String> {
         Server::describe_api(&self.inner)
     }
+
+    fn describe_api_detailed(&self) -> Vec<MethodMetadata> {
+        Server::describe_api_detailed(&self.inner)
+    }
+
+    fn add_alias(&mut self, alias: &str, target: &str, deprecation: Option<Deprecation>) {
+        Server::add_alias(&mut self.inner, alias, target, deprecation)
+    }
+
+    fn set_method_metadata(&mut self, metadata: MethodMetadata) {
+        Server::set_method_metadata(&mut self.inner, metadata)
+    }
 }
 
 #[cfg(feature = "with_actix")]
@@ -429,3 +1407,343 @@ where
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use futures::future;
+    use jsonrpc_core::serde_json::{self, json};
+
+    use super::*;
+    use crate::{
+        access_log::{AccessLogEntry, AccessLogOutcome},
+        concurrency::{ConcurrencyPermit, SERVER_BUSY_ERROR_CODE},
+        timeout::TIMEOUT_ERROR_CODE,
+        versioning::UNSUPPORTED_API_VERSION_ERROR_CODE,
+    };
+
+    /// Call a method of a server, returning the whole response.
+    fn call(server: &WittyMultiServer, method: &str, meta: Session) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method }).to_string();
+        let response = server.handle_request_sync(&request, meta).unwrap();
+
+        serde_json::from_str(&response).unwrap()
+    }
+
+    /// Let a single call to a method execute at a time, without queueing, and take up that slot so
+    /// that calls to the method are rejected as busy until the returned permit is dropped.
+    fn saturate(server: &mut WittyMultiServer, method: &str) -> ConcurrencyPermit {
+        server.set_method_concurrency_limit(
+            method,
+            Some(ConcurrencyLimit {
+                max_in_flight: NonZeroUsize::MIN,
+                max_queued: 0,
+            }),
+        );
+        let limiter = server.concurrency_limiters.read().unwrap().methods[method].clone();
+
+        futures::executor::block_on(limiter.acquire().unwrap()).unwrap()
+    }
+
+    /// A session that asks for a version of the API.
+    fn session(version: &str) -> Session {
+        let mut meta = Session::mock();
+        meta.set_api_version(version);
+
+        meta
+    }
+
+    /// A module with a single method that tells which version it belongs to, and an alias of it.
+    fn version(name: &'static str) -> Module<PubSubHandler<Session>> {
+        let mut module = Module::new("");
+        module.add_method("version", move |_| future::ready(Ok(Value::from(name))));
+        module.add_alias("old_version", "version", None);
+
+        module
+    }
+
+    #[test]
+    fn aliases_call_their_targets() {
+        let mut server = WittyMultiServer::new();
+        server.add_method("hello", |_| future::ready(Ok(Value::from("world"))));
+        server.add_alias(
+            "say_hello",
+            "hello",
+            Some(Deprecation {
+                note: None,
+                annotate_response: false,
+            }),
+        );
+
+        let response = call(&server, "say_hello", Session::mock());
+        assert_eq!(response["result"], json!("world"));
+    }
+
+    #[test]
+    fn aliases_share_the_concurrency_limits_of_their_targets() {
+        let mut server = WittyMultiServer::new();
+        server.add_method("hello", |_| future::ready(Ok(Value::from("world"))));
+        server.add_alias("say_hello", "hello", None);
+        let _permit = saturate(&mut server, "hello");
+
+        let response = call(&server, "say_hello", Session::mock());
+        assert_eq!(response["error"]["code"], json!(SERVER_BUSY_ERROR_CODE));
+    }
+
+    #[test]
+    fn aliases_of_aliases_call_the_method_at_the_end_of_the_chain() {
+        let mut server = WittyMultiServer::new();
+        server.add_alias("greet", "say_hello", None);
+        server.add_alias("say_hello", "hello", None);
+        server.add_method("hello", |_| future::ready(Ok(Value::from("world"))));
+
+        let response = call(&server, "greet", Session::mock());
+        assert_eq!(response["result"], json!("world"));
+
+        let _permit = saturate(&mut server, "hello");
+        let response = call(&server, "greet", Session::mock());
+        assert_eq!(response["error"]["code"], json!(SERVER_BUSY_ERROR_CODE));
+    }
+
+    #[test]
+    fn aliases_that_loop_are_not_found() {
+        let mut server = WittyMultiServer::new();
+        server.add_alias("ping", "pong", None);
+        server.add_alias("pong", "ping", None);
+
+        let response = call(&server, "ping", Session::mock());
+        assert_eq!(
+            response["error"]["code"],
+            json!(jsonrpc_core::ErrorCode::MethodNotFound.code())
+        );
+    }
+
+    #[test]
+    fn aliases_of_subscriptions_subscribe_and_unsubscribe() {
+        let mut server = WittyMultiServer::new();
+        server.add_topic("news", "subscribeNews", "unsubscribeNews");
+        server.add_alias("subscribe_news", "subscribeNews", None);
+        server.add_alias("unsubscribe_news", "unsubscribeNews", None);
+        let meta = Session::mock();
+
+        let response = call(&server, "subscribe_news", meta.clone());
+        let id = response["result"].clone();
+        assert!(!id.is_null(), "{}", response);
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "unsubscribe_news",
+            "params": [id],
+        });
+        let response = server
+            .handle_request_sync(&request.to_string(), meta)
+            .unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["result"], json!(true));
+    }
+
+    #[test]
+    fn aliases_of_discovery_return_the_openrpc_document() {
+        let mut server = WittyMultiServer::new();
+        server.add_alias("discover", DISCOVER_METHOD, None);
+        server.enable_discovery(OpenRpcInfo::default());
+
+        let response = call(&server, "discover", Session::mock());
+        assert!(response["result"]["openrpc"].is_string(), "{}", response);
+    }
+
+    #[test]
+    fn methods_that_exceed_their_timeout_are_answered_with_a_timeout_error() {
+        let mut server = WittyMultiServer::new();
+        server.add_method_with_timeout("slow", Duration::from_millis(50), |_| async {
+            futures_timer::Delay::new(Duration::from_secs(10)).await;
+
+            Ok(Value::from("too late"))
+        });
+
+        let response = call(&server, "slow", Session::mock());
+        assert_eq!(response["error"]["code"], json!(TIMEOUT_ERROR_CODE));
+        assert_eq!(response["error"]["data"]["timeout_ms"], json!(50));
+    }
+
+    #[test]
+    fn methods_that_complete_before_their_timeout_succeed() {
+        let mut server = WittyMultiServer::new();
+        server.add_method_with_timeout("fast", Duration::from_secs(10), |_| {
+            future::ready(Ok(Value::from("in time")))
+        });
+
+        let response = call(&server, "fast", Session::mock());
+        assert_eq!(response["result"], json!("in time"));
+    }
+
+    #[test]
+    fn calls_to_unknown_methods_are_recorded_in_the_access_log() {
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let sink_entries = entries.clone();
+        let mut server = WittyMultiServer::new();
+        server.add_method("hello", |_| future::ready(Ok(Value::from("world"))));
+        server.add_access_log_sink(move |entry: &AccessLogEntry| {
+            sink_entries.lock().unwrap().push(entry.clone());
+        });
+
+        call(&server, "hello", Session::mock());
+        call(&server, "goodbye", Session::mock());
+
+        let entries = entries.lock().unwrap();
+        let outcomes = entries
+            .iter()
+            .map(|entry| (entry.method.as_str(), entry.outcome.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("hello", AccessLogOutcome::Success),
+                (
+                    "goodbye",
+                    AccessLogOutcome::Error {
+                        code: jsonrpc_core::ErrorCode::MethodNotFound.code(),
+                        message: String::from("Method not found"),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn methods_added_after_a_call_are_handled_by_later_calls() {
+        let mut server = WittyMultiServer::new();
+        let response = call(&server, "hello", Session::mock());
+        assert_eq!(
+            response["error"]["code"],
+            json!(jsonrpc_core::ErrorCode::MethodNotFound.code())
+        );
+
+        server.add_method("hello", |_| future::ready(Ok(Value::from("world"))));
+
+        let response = call(&server, "hello", Session::mock());
+        assert_eq!(response["result"], json!("world"));
+    }
+
+    #[test]
+    fn typed_methods_describe_their_params_and_result() {
+        let mut server = WittyMultiServer::new();
+        server.add_typed_method_described("add", |(a, b): (i64, Option<i64>)| {
+            future::ok(vec![a + b.unwrap_or_default()])
+        });
+
+        let metadata = server
+            .describe_api_detailed()
+            .into_iter()
+            .find(|metadata| metadata.name == "add")
+            .unwrap();
+        assert_eq!(
+            metadata.params,
+            vec![
+                ContentDescriptor::new("param0", json!({ "type": "integer" })),
+                ContentDescriptor {
+                    required: false,
+                    ..ContentDescriptor::new(
+                        "param1",
+                        json!({ "anyOf": [{ "type": "integer" }, { "type": "null" }] })
+                    )
+                },
+            ]
+        );
+        assert_eq!(
+            metadata.result,
+            Some(ContentDescriptor::new(
+                "result",
+                json!({ "type": "array", "items": { "type": "integer" } })
+            ))
+        );
+    }
+
+    #[test]
+    fn typed_methods_only_need_their_types_to_be_convertible() {
+        let mut server = WittyMultiServer::new();
+        // Neither `HashSet` nor `IpAddr` can describe themselves
+        server.add_typed_method(
+            "first",
+            |(addresses,): (std::collections::HashSet<std::net::IpAddr>,)| {
+                future::ok(addresses.into_iter().min())
+            },
+        );
+
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "first",
+            "params": [["10.0.0.2", "10.0.0.1"]],
+        });
+        let response = server
+            .handle_request_sync(&request.to_string(), Session::mock())
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&response).unwrap()["result"],
+            json!("10.0.0.1")
+        );
+
+        let metadata = server
+            .describe_api_detailed()
+            .into_iter()
+            .find(|metadata| metadata.name == "first")
+            .unwrap();
+        assert_eq!(metadata, MethodMetadata::new("first"));
+    }
+
+    #[test]
+    fn versioned_methods_are_dispatched_to_the_requested_version() {
+        let mut server = WittyMultiServer::new();
+        server.add_api_version("v1", version("v1")).unwrap();
+        server.add_api_version("v2", version("v2")).unwrap();
+
+        assert_eq!(
+            call(&server, "version", session("v1"))["result"],
+            json!("v1")
+        );
+        assert_eq!(
+            call(&server, "version", session("v2"))["result"],
+            json!("v2")
+        );
+        assert_eq!(
+            call(&server, "old_version", session("v2"))["result"],
+            json!("v2")
+        );
+    }
+
+    #[test]
+    fn calls_without_a_supported_version_are_rejected() {
+        let mut server = WittyMultiServer::new();
+        server.add_api_version("v1", version("v1")).unwrap();
+
+        let response = call(&server, "version", Session::mock());
+        assert_eq!(
+            response["error"]["code"],
+            json!(UNSUPPORTED_API_VERSION_ERROR_CODE)
+        );
+        let response = call(&server, "version", session("v3"));
+        assert_eq!(
+            response["error"]["code"],
+            json!(UNSUPPORTED_API_VERSION_ERROR_CODE)
+        );
+
+        server.set_default_api_version(Some("v1"));
+        assert_eq!(
+            call(&server, "version", Session::mock())["result"],
+            json!("v1")
+        );
+    }
+
+    #[test]
+    fn versioned_aliases_share_the_concurrency_limits_of_their_targets() {
+        let mut server = WittyMultiServer::new();
+        server.add_api_version("v1", version("v1")).unwrap();
+        let _permit = saturate(&mut server, "version");
+
+        let response = call(&server, "old_version", session("v1"));
+        assert_eq!(response["error"]["code"], json!(SERVER_BUSY_ERROR_CODE));
+    }
+}
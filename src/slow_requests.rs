@@ -0,0 +1,333 @@
+use std::{
+    collections::{hash_map::DefaultHasher, VecDeque},
+    hash::{Hash, Hasher},
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use jsonrpc_core::{serde_json, Params};
+
+use crate::handler::ConnectionMetadata;
+
+/// The `log` target that slow requests are reported under, so that they can be filtered or routed
+/// separately from the rest of the logs.
+pub const SLOW_REQUEST_LOG_TARGET: &str = "witty_jsonrpc::slow";
+
+/// Settings for detecting slow method calls.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SlowRequestSettings {
+    /// How long a call can take before it is considered slow.
+    pub threshold: Duration,
+    /// How many of the most recent slow calls are kept for `slowest_requests`.
+    pub history: usize,
+}
+
+impl Default for SlowRequestSettings {
+    fn default() -> Self {
+        Self {
+            threshold: Duration::from_secs(1),
+            history: 100,
+        }
+    }
+}
+
+/// A record of a method call that took longer than the slow request threshold.
+#[derive(Clone, Debug)]
+pub struct SlowRequest {
+    /// When the call was received.
+    pub timestamp: SystemTime,
+    /// The name of the transport that the call came through, if known.
+    pub transport: Option<&'static str>,
+    /// The address of the remote peer, if known.
+    pub peer_addr: Option<SocketAddr>,
+    /// The session that the call belongs to, if the transport is session based.
+    pub session_id: Option<u64>,
+    /// The name of the method that was called.
+    pub method: String,
+    /// A short hash of the parameters, for telling apart calls to the same method without keeping
+    /// the parameters themselves around.
+    pub params_digest: String,
+    /// How long it took to complete the call, including any time spent waiting in queues.
+    pub duration: Duration,
+}
+
+/// The state of slow request detection, once enabled.
+#[derive(Debug)]
+struct Detector {
+    settings: SlowRequestSettings,
+    recent: VecDeque<SlowRequest>,
+}
+
+/// Detects method calls that take longer than a threshold, reports them through the `log` crate
+/// and keeps the most recent ones around.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SlowRequestLog {
+    detector: Arc<RwLock<Option<Detector>>>,
+}
+
+impl SlowRequestLog {
+    /// Enable slow request detection with the provided settings, or disable it if `None`.
+    ///
+    /// Changing the settings forgets the slow calls recorded so far.
+    pub(crate) fn set_settings(&self, settings: Option<SlowRequestSettings>) {
+        *self.detector.write().unwrap() = settings.map(|settings| Detector {
+            settings,
+            recent: VecDeque::with_capacity(settings.history),
+        });
+    }
+
+    /// Start timing a call to `method`.
+    ///
+    /// Returns `None` if detection is disabled, so that the parameters are not needlessly cloned.
+    pub(crate) fn begin<M>(&self, method: &str, meta: &M, params: &Params) -> Option<PendingCall>
+    where
+        M: ConnectionMetadata,
+    {
+        self.detector.read().unwrap().as_ref()?;
+
+        Some(PendingCall {
+            slow_request_log: self.clone(),
+            started: Instant::now(),
+            timestamp: SystemTime::now(),
+            transport: meta.transport(),
+            peer_addr: meta.peer_addr(),
+            session_id: meta.session_id(),
+            method: String::from(method),
+            params: params.clone(),
+        })
+    }
+
+    /// The `n` slowest calls among the most recent slow ones, from slowest to fastest.
+    pub(crate) fn slowest(&self, n: usize) -> Vec<SlowRequest> {
+        let mut slowest = self
+            .detector
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|detector| Vec::from(detector.recent.clone()))
+            .unwrap_or_default();
+        slowest.sort_by_key(|request| std::cmp::Reverse(request.duration));
+        slowest.truncate(n);
+
+        slowest
+    }
+}
+
+/// A call that is being timed.
+pub(crate) struct PendingCall {
+    slow_request_log: SlowRequestLog,
+    started: Instant,
+    timestamp: SystemTime,
+    transport: Option<&'static str>,
+    peer_addr: Option<SocketAddr>,
+    session_id: Option<u64>,
+    method: String,
+    params: Params,
+}
+
+impl PendingCall {
+    /// Stop timing the call, and record it if it took longer than the threshold.
+    pub(crate) fn finish(self) {
+        let duration = self.started.elapsed();
+        let threshold = match self.slow_request_log.detector.read().unwrap().as_ref() {
+            Some(detector) if duration >= detector.settings.threshold => {
+                detector.settings.threshold
+            }
+            _ => return,
+        };
+
+        let slow_request = SlowRequest {
+            timestamp: self.timestamp,
+            transport: self.transport,
+            peer_addr: self.peer_addr,
+            session_id: self.session_id,
+            method: self.method,
+            params_digest: params_digest(&self.params),
+            duration,
+        };
+        log::warn!(
+            target: SLOW_REQUEST_LOG_TARGET,
+            "Slow request: method={} params_digest={} duration={:?} threshold={:?}",
+            slow_request.method,
+            slow_request.params_digest,
+            slow_request.duration,
+            threshold,
+        );
+
+        let mut detector = self.slow_request_log.detector.write().unwrap();
+        let detector = match detector.as_mut() {
+            Some(detector) if detector.settings.history > 0 => detector,
+            _ => return,
+        };
+        if detector.recent.len() >= detector.settings.history {
+            detector.recent.pop_front();
+        }
+        detector.recent.push_back(slow_request);
+    }
+}
+
+/// Hash the serialized parameters of a call into 16 hexadecimal characters.
+///
+/// The digest is only meant for telling calls apart within the same process, so it is not stable
+/// across Rust versions.
+fn params_digest(params: &Params) -> String {
+    let mut hasher = DefaultHasher::new();
+    serde_json::to_vec(params)
+        .unwrap_or_default()
+        .hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use jsonrpc_core::serde_json::json;
+
+    use super::*;
+    use crate::handler::Session;
+
+    /// The messages logged under `SLOW_REQUEST_LOG_TARGET` by every test.
+    static SLOW_REQUEST_LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// A logger that keeps the messages logged under `SLOW_REQUEST_LOG_TARGET`.
+    struct SlowRequestLogger;
+
+    impl log::Log for SlowRequestLogger {
+        fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+            metadata.target() == SLOW_REQUEST_LOG_TARGET
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            if self.enabled(record.metadata()) {
+                SLOW_REQUEST_LOGS
+                    .lock()
+                    .unwrap()
+                    .push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn params(value: serde_json::Value) -> Params {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Time a call to `method` that takes at least `duration`.
+    fn call(slow_request_log: &SlowRequestLog, method: &str, duration: Duration) {
+        let pending = slow_request_log.begin(method, &Session::mock(), &params(json!([method])));
+        std::thread::sleep(duration);
+        if let Some(pending) = pending {
+            pending.finish();
+        }
+    }
+
+    /// A slow call to `method` that took `millis` milliseconds.
+    fn slow_request(method: &str, millis: u64) -> SlowRequest {
+        SlowRequest {
+            timestamp: SystemTime::now(),
+            transport: None,
+            peer_addr: None,
+            session_id: None,
+            method: String::from(method),
+            params_digest: params_digest(&Params::None),
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn calls_over_the_threshold_are_logged_and_kept() {
+        log::set_logger(&SlowRequestLogger).ok();
+        log::set_max_level(log::LevelFilter::Warn);
+        let slow_request_log = SlowRequestLog::default();
+        slow_request_log.set_settings(Some(SlowRequestSettings {
+            threshold: Duration::from_millis(50),
+            history: 10,
+        }));
+
+        call(&slow_request_log, "threshold_fast", Duration::ZERO);
+        call(
+            &slow_request_log,
+            "threshold_slow",
+            Duration::from_millis(60),
+        );
+
+        let digest = params_digest(&params(json!(["threshold_slow"])));
+        let logs = SLOW_REQUEST_LOGS.lock().unwrap();
+        assert!(logs
+            .iter()
+            .any(|message| message.contains("method=threshold_slow")
+                && message.contains(&format!("params_digest={}", digest))));
+        assert!(!logs
+            .iter()
+            .any(|message| message.contains("method=threshold_fast")));
+        let slowest = slow_request_log.slowest(10);
+        assert_eq!(slowest.len(), 1);
+        assert_eq!(slowest[0].method, "threshold_slow");
+        assert_eq!(slowest[0].params_digest, digest);
+        assert!(slowest[0].duration >= Duration::from_millis(60));
+    }
+
+    #[test]
+    fn calls_are_not_timed_unless_enabled() {
+        let slow_request_log = SlowRequestLog::default();
+
+        assert!(slow_request_log
+            .begin("method", &Session::mock(), &Params::None)
+            .is_none());
+    }
+
+    #[test]
+    fn params_digests_tell_params_apart() {
+        let digest = params_digest(&params(json!([1, "two"])));
+
+        assert_eq!(digest.len(), 16);
+        assert!(digest.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(digest, params_digest(&params(json!([1, "two"]))));
+        assert_ne!(digest, params_digest(&params(json!([2, "two"]))));
+        assert_ne!(digest, params_digest(&params(json!({ "one": 1 }))));
+    }
+
+    #[test]
+    fn the_slowest_calls_are_listed_from_slowest_to_fastest() {
+        let slow_request_log = SlowRequestLog::default();
+        slow_request_log.set_settings(Some(SlowRequestSettings::default()));
+        if let Some(detector) = slow_request_log.detector.write().unwrap().as_mut() {
+            for (method, millis) in [("a", 30), ("b", 10), ("c", 50), ("d", 20)] {
+                detector.recent.push_back(slow_request(method, millis));
+            }
+        }
+
+        let slowest = slow_request_log
+            .slowest(3)
+            .into_iter()
+            .map(|request| request.method)
+            .collect::<Vec<_>>();
+        assert_eq!(slowest, vec!["c", "a", "d"]);
+        assert_eq!(slow_request_log.slowest(10).len(), 4);
+    }
+
+    #[test]
+    fn only_the_most_recent_slow_calls_are_kept() {
+        let slow_request_log = SlowRequestLog::default();
+        slow_request_log.set_settings(Some(SlowRequestSettings {
+            threshold: Duration::ZERO,
+            history: 2,
+        }));
+
+        for method in ["history_a", "history_b", "history_c"] {
+            call(&slow_request_log, method, Duration::ZERO);
+        }
+
+        let mut kept = slow_request_log
+            .slowest(10)
+            .into_iter()
+            .map(|request| request.method)
+            .collect::<Vec<_>>();
+        kept.sort();
+        assert_eq!(kept, vec!["history_b", "history_c"]);
+    }
+}
@@ -0,0 +1,229 @@
+use futures::future::Either;
+use jsonrpc_core::{
+    middleware::{NoopCallFuture, NoopFuture},
+    Call, Id, Metadata, Middleware, Output,
+};
+use tracing::Instrument;
+
+use crate::{handler::ConnectionMetadata, trace_context::TraceContext};
+
+/// A JSON-RPC middleware that opens a `tracing` span for every call, carrying the method name,
+/// request id, transport and session id, as well as the id of the remote trace, if any.
+///
+/// The span is entered while the method is dispatched and stays attached to the future that
+/// computes the response, so that anything the method logs through `tracing` is tied to the call.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RequestSpans;
+
+impl<M> Middleware<M> for RequestSpans
+where
+    M: Metadata + ConnectionMetadata,
+{
+    type Future = NoopFuture;
+    type CallFuture = NoopCallFuture;
+
+    fn on_call<F, X>(&self, call: Call, meta: M, next: F) -> Either<Self::CallFuture, X>
+    where
+        F: Fn(Call, M) -> X + Send + Sync,
+        X: futures::Future<Output = Option<Output>> + Send + 'static,
+    {
+        let (method, id) = match &call {
+            Call::MethodCall(call) => (
+                call.method.as_str(),
+                match &call.id {
+                    Id::Num(id) => id.to_string(),
+                    Id::Str(id) => id.clone(),
+                    Id::Null => String::from("null"),
+                },
+            ),
+            Call::Notification(notification) => (notification.method.as_str(), String::new()),
+            Call::Invalid { .. } => return Either::Right(next(call, meta)),
+        };
+        let span = tracing::info_span!(
+            "jsonrpc_call",
+            method = method,
+            id = id.as_str(),
+            transport = meta.transport(),
+            session_id = meta.session_id(),
+            trace_id = meta.trace_context().map(TraceContext::trace_id),
+        );
+
+        let response = span.in_scope(|| next(call, meta));
+
+        Either::Left(Box::pin(response.instrument(span)))
+    }
+}
+
+#[cfg(all(test, feature = "tcp"))]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        fmt,
+        io::{BufRead, Write},
+        sync::{Mutex, OnceLock},
+    };
+
+    use jsonrpc_core::{serde_json, Value};
+    use tracing::{
+        field::{Field, Visit},
+        span,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    #[cfg(feature = "with_actix")]
+    use crate::server::ActixServer as _;
+    use crate::{
+        server::{Server as _, WittyMultiServer},
+        transports::{
+            free_address,
+            tcp::{TcpTransport, TcpTransportSettings},
+        },
+    };
+
+    type Fields = BTreeMap<String, String>;
+
+    /// A `tracing` layer that records, for every event, the fields of the span that it was emitted
+    /// in.
+    #[derive(Default)]
+    struct SpanRecorder {
+        events: Mutex<Vec<(Fields, Fields)>>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut Fields);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().into(), value.into());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.insert(field.name().into(), format!("{:?}", value));
+        }
+    }
+
+    impl<S> Layer<S> for &'static SpanRecorder
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attributes: &span::Attributes<'_>,
+            id: &span::Id,
+            ctx: Context<'_, S>,
+        ) {
+            let mut fields = Fields::new();
+            attributes.record(&mut FieldVisitor(&mut fields));
+            if let Some(span) = ctx.span(id) {
+                span.extensions_mut().insert(fields);
+            }
+        }
+
+        fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+            let mut fields = Fields::new();
+            event.record(&mut FieldVisitor(&mut fields));
+            let span = ctx
+                .event_span(event)
+                .and_then(|span| span.extensions().get::<Fields>().cloned())
+                .unwrap_or_default();
+            self.events.lock().unwrap().push((fields, span));
+        }
+    }
+
+    /// Install the recorder as the global subscriber, so that it also sees the events emitted on
+    /// other threads.
+    fn recorder() -> &'static SpanRecorder {
+        static RECORDER: OnceLock<&'static SpanRecorder> = OnceLock::new();
+
+        RECORDER.get_or_init(|| {
+            let recorder: &'static SpanRecorder = Box::leak(Box::default());
+            tracing::subscriber::set_global_default(tracing_subscriber::registry().with(recorder))
+                .unwrap();
+
+            recorder
+        })
+    }
+
+    /// Get the fields of the span that the event with some message was emitted in.
+    fn span_of(message: &str) -> Fields {
+        recorder()
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(fields, _)| fields.get("message").map(String::as_str) == Some(message))
+            .map(|(_, span)| span.clone())
+            .unwrap_or_else(|| panic!("no event with message {:?}", message))
+    }
+
+    /// Start a server on the TCP transport, with a method that emits an event with its own name.
+    fn server(address: &str) -> WittyMultiServer {
+        let mut server = WittyMultiServer::new();
+        server.add_transport(TcpTransport::new(TcpTransportSettings {
+            address: String::from(address),
+            ..Default::default()
+        }));
+        server.add_method("spans_traced", |_| async {
+            tracing::info!("spans_traced called");
+
+            Ok(Value::Null)
+        });
+
+        server
+    }
+
+    /// Call some methods over a single TCP connection, in order.
+    fn call_over_tcp(address: &str, methods: &[&str]) {
+        let stream = std::net::TcpStream::connect(address).unwrap();
+        let mut lines = std::io::BufReader::new(stream.try_clone().unwrap()).lines();
+        for (id, method) in methods.iter().enumerate() {
+            let call = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": method });
+            writeln!(&stream, "{}", call).unwrap();
+            lines.next().unwrap().unwrap();
+        }
+    }
+
+    #[test]
+    fn spans_carry_the_call_and_its_connection() {
+        recorder();
+        let address = free_address();
+        let mut server = server(&address);
+        #[cfg(feature = "with_actix")]
+        let system = {
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let system = actix::System::new();
+                tx.send(actix::System::current()).unwrap();
+                system.run().unwrap();
+            });
+
+            rx.recv().unwrap()
+        };
+        #[cfg(feature = "with_actix")]
+        server.add_actix_method(&Some(system.clone()), "spans_traced_actix", |_| async {
+            tracing::info!("spans_traced_actix called");
+
+            Ok(Value::Null)
+        });
+        server.start().unwrap();
+
+        call_over_tcp(&address, &["spans_traced", "spans_traced_actix"]);
+
+        let span = span_of("spans_traced called");
+        assert_eq!(span["method"], "spans_traced");
+        assert_eq!(span["id"], "0");
+        assert_eq!(span["transport"], "tcp");
+        assert!(span.contains_key("session_id"));
+
+        // Methods spawned into an actix arbiter run on another thread, but keep the span
+        #[cfg(feature = "with_actix")]
+        {
+            let actix_span = span_of("spans_traced_actix called");
+            assert_eq!(actix_span["method"], "spans_traced_actix");
+            assert_eq!(actix_span["id"], "1");
+            assert_eq!(actix_span["session_id"], span["session_id"]);
+            system.stop();
+        }
+
+        server.stop().unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use futures::future::{self, Either};
+use futures_timer::Delay;
+use jsonrpc_core::{serde_json::json, Error, ErrorCode, RpcMethodSimple};
+
+/// The JSON-RPC error code used for answering calls that did not complete before their deadline.
+pub const TIMEOUT_ERROR_CODE: i64 = -32007;
+
+/// Wrap a JSON-RPC method so that its execution is aborted if it takes longer than `timeout`.
+///
+/// When the deadline is exceeded, the future returned by the method is dropped and the call is
+/// answered with a timeout error.
+pub fn with_timeout<F>(name: &str, timeout: Duration, method: F) -> impl RpcMethodSimple
+where
+    F: RpcMethodSimple,
+{
+    let name = String::from(name);
+
+    move |params| {
+        let name = name.clone();
+        let execution = Box::pin(method.call(params));
+
+        async move {
+            match future::select(execution, Delay::new(timeout)).await {
+                Either::Left((response, _)) => response,
+                Either::Right(_) => {
+                    log::warn!(
+                        "JSON-RPC method {} timed out after {} ms",
+                        name,
+                        timeout.as_millis()
+                    );
+
+                    Err(timeout_error(timeout))
+                }
+            }
+        }
+    }
+}
+
+/// Build the JSON-RPC error that is returned when a call exceeds its deadline.
+pub fn timeout_error(timeout: Duration) -> Error {
+    let timeout_ms = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+
+    Error {
+        code: ErrorCode::ServerError(TIMEOUT_ERROR_CODE),
+        message: String::from("Request timed out"),
+        data: Some(json!({ "timeout_ms": timeout_ms })),
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use jsonrpc_core::{
+    serde_json::{self, Map},
+    Error, Params, Value,
+};
+use jsonrpc_pubsub::{Sink, Subscriber, SubscriptionId};
+
+use crate::handler::ConnectionMetadata;
+
+/// Tells whether a notification matches the criteria that a subscriber asked for.
+type Matcher = Arc<dyn Fn(&Value) -> bool + Send + Sync>;
+/// Turns the parameters that a subscriber sent into the matcher of its notifications.
+pub(crate) type FilterParser = Arc<dyn Fn(Params) -> Result<Matcher, Error> + Send + Sync>;
+
+/// A subscriber to a topic, along with the criteria it asked for, if any.
+struct Subscription {
+    sink: Sink,
+    matcher: Option<Matcher>,
+    /// The session that subscribed, which is the only one that can unsubscribe.
+    session_id: Option<u64>,
+}
+
+/// The subscribers to a single topic.
+type Sinks = HashMap<SubscriptionId, Subscription>;
+
+/// Decides server-side which notifications of a topic each subscriber gets, out of the parameters
+/// that it subscribed with, so that those that do not match are never sent.
+pub trait TopicFilter: Send + Sync + 'static {
+    /// What a subscriber asks for, e.g. a list of addresses.
+    type Criteria: Send + Sync + 'static;
+
+    /// Turn the parameters of a subscription into criteria, or reject the subscription with an
+    /// error if they are not valid.
+    fn parse(&self, params: Params) -> Result<Self::Criteria, Error>;
+
+    /// Tell whether a notification, as published to the topic, matches the criteria of a
+    /// subscriber.
+    fn matches(&self, criteria: &Self::Criteria, value: &Value) -> bool;
+}
+
+/// A `TopicFilter` made out of a function, whose criteria are deserialized from the parameters of
+/// subscriptions as with `Params::parse`.
+pub struct FilterFn<C, F> {
+    matches: F,
+    _criteria: PhantomData<fn() -> C>,
+}
+
+/// Make a `TopicFilter` out of a function that tells whether a notification matches some
+/// criteria, which are deserialized from the parameters of every subscription.
+pub fn filter_fn<C, F>(matches: F) -> FilterFn<C, F>
+where
+    C: serde::de::DeserializeOwned + Send + Sync + 'static,
+    F: Fn(&C, &Value) -> bool + Send + Sync + 'static,
+{
+    FilterFn {
+        matches,
+        _criteria: PhantomData,
+    }
+}
+
+impl<C, F> TopicFilter for FilterFn<C, F>
+where
+    C: serde::de::DeserializeOwned + Send + Sync + 'static,
+    F: Fn(&C, &Value) -> bool + Send + Sync + 'static,
+{
+    type Criteria = C;
+
+    fn parse(&self, params: Params) -> Result<Self::Criteria, Error> {
+        params.parse()
+    }
+
+    fn matches(&self, criteria: &Self::Criteria, value: &Value) -> bool {
+        (self.matches)(criteria, value)
+    }
+}
+
+/// Erase the type of a filter, for keeping it next to those of other topics.
+pub(crate) fn filter_parser<F>(filter: F) -> FilterParser
+where
+    F: TopicFilter,
+{
+    let filter = Arc::new(filter);
+
+    Arc::new(move |params| {
+        let criteria = filter.parse(params)?;
+        let filter = filter.clone();
+
+        Ok(Arc::new(move |value| filter.matches(&criteria, value)))
+    })
+}
+
+/// A registry of the subscribers to every topic, which takes care of the bookkeeping of
+/// subscriptions and of pushing notifications to all the subscribers of a topic at once.
+///
+/// Clones share the same subscribers, so a clone can be handed over to whatever produces the
+/// notifications, e.g. a background thread, while the server keeps the original.
+#[derive(Clone, Default)]
+pub struct Topics {
+    next_id: Arc<AtomicU64>,
+    sinks: Arc<RwLock<HashMap<String, Sinks>>>,
+}
+
+impl Topics {
+    /// Notify every subscriber to a topic, across all transports, returning how many of them were
+    /// notified.
+    ///
+    /// Notifications carry the value along with the subscription ID, as in
+    /// `{"subscription": 1, "result": value}`. Subscribers that asked for criteria are only
+    /// notified if the value matches them. Subscribers whose session is gone are dropped.
+    pub fn publish<T>(&self, topic: &str, value: &T) -> usize
+    where
+        T: serde::Serialize,
+    {
+        let value = match serde_json::to_value(value) {
+            Ok(value) => value,
+            Err(error) => {
+                log::error!(
+                    "Error serializing notification for topic {}: {}",
+                    topic,
+                    error
+                );
+
+                return 0;
+            }
+        };
+
+        let mut gone = Vec::new();
+        let mut notified = 0;
+        if let Some(sinks) = self.sinks.read().unwrap().get(topic) {
+            for (id, subscription) in sinks {
+                if let Some(matcher) = &subscription.matcher {
+                    if !matcher(&value) {
+                        continue;
+                    }
+                }
+                let mut params = Map::new();
+                params.insert(String::from("subscription"), Value::from(id.clone()));
+                params.insert(String::from("result"), value.clone());
+
+                match subscription.sink.notify(Params::Map(params)) {
+                    Ok(()) => notified += 1,
+                    Err(_) => gone.push(id.clone()),
+                }
+            }
+        }
+
+        if !gone.is_empty() {
+            log::debug!(
+                "Dropping {} subscribers to topic {} whose session is gone",
+                gone.len(),
+                topic
+            );
+            if let Some(sinks) = self.sinks.write().unwrap().get_mut(topic) {
+                for id in gone {
+                    sinks.remove(&id);
+                }
+            }
+        }
+
+        notified
+    }
+
+    /// Tell how many subscribers a topic has.
+    pub fn subscribers(&self, topic: &str) -> usize {
+        self.sinks
+            .read()
+            .unwrap()
+            .get(topic)
+            .map_or(0, HashMap::len)
+    }
+
+    /// Accept a subscriber to a topic, along with the criteria it asked for through the filter of
+    /// the topic, if any.
+    ///
+    /// Subscribers that send no parameters get every notification. Those that send parameters are
+    /// rejected if these are not valid criteria, or if the topic has no filter.
+    pub(crate) fn subscribe<M>(
+        &self,
+        topic: &str,
+        params: Params,
+        meta: &M,
+        subscriber: Subscriber,
+        filter: Option<&FilterParser>,
+    ) where
+        M: ConnectionMetadata,
+    {
+        let matcher = match (params, filter) {
+            (Params::None, _) => Ok(None),
+            (Params::Array(values), _) if values.is_empty() => Ok(None),
+            (params, Some(filter)) => filter(params).map(Some),
+            (params, None) => params.expect_no_params().map(|()| None),
+        };
+        let matcher = match matcher {
+            Ok(matcher) => matcher,
+            Err(error) => {
+                subscriber.reject(error).ok();
+
+                return;
+            }
+        };
+
+        let id = SubscriptionId::Number(self.next_id.fetch_add(1, Ordering::Relaxed));
+        if let Ok(sink) = subscriber.assign_id(id.clone()) {
+            self.sinks
+                .write()
+                .unwrap()
+                .entry(String::from(topic))
+                .or_default()
+                .insert(
+                    id,
+                    Subscription {
+                        sink,
+                        matcher,
+                        session_id: meta.session_id(),
+                    },
+                );
+        }
+    }
+
+    /// Drop a subscriber to a topic, telling whether it was subscribed.
+    ///
+    /// Subscribers can only be dropped by the session that subscribed, or by no one in particular
+    /// once that session is gone, i.e. if `caller` is `None`.
+    pub(crate) fn unsubscribe<M>(
+        &self,
+        topic: &str,
+        id: &SubscriptionId,
+        caller: Option<&M>,
+    ) -> Result<Value, Error>
+    where
+        M: ConnectionMetadata,
+    {
+        let mut sinks = self.sinks.write().unwrap();
+        let sinks = match sinks.get_mut(topic) {
+            Some(sinks) => sinks,
+            None => return Ok(Value::from(false)),
+        };
+        let owned = sinks.get(id).is_some_and(|subscription| {
+            caller.is_none_or(|caller| {
+                caller.session_id().is_some() && caller.session_id() == subscription.session_id
+            })
+        });
+        if owned {
+            sinks.remove(id);
+        }
+
+        Ok(Value::from(owned))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+    use jsonrpc_core::serde_json::json;
+
+    use super::*;
+    use crate::handler::Session;
+
+    /// Subscribe to a topic, returning the ID of the subscription along with the notifications
+    /// that it gets.
+    fn subscribe(
+        topics: &Topics,
+        params: Params,
+        meta: &Session,
+        filter: Option<&FilterParser>,
+    ) -> (
+        Result<SubscriptionId, Error>,
+        futures::channel::mpsc::UnboundedReceiver<String>,
+    ) {
+        let (subscriber, id, notifications) = Subscriber::new_test("topic");
+        topics.subscribe("topic", params, meta, subscriber, filter);
+
+        (futures::executor::block_on(id).unwrap(), notifications)
+    }
+
+    #[test]
+    fn only_the_subscribing_session_can_unsubscribe() {
+        let topics = Topics::default();
+        let owner = Session::mock();
+        let (id, _notifications) = subscribe(&topics, Params::None, &owner, None);
+        let id = id.unwrap();
+
+        let stranger = Session::mock();
+        assert_eq!(
+            topics.unsubscribe("topic", &id, Some(&stranger)),
+            Ok(Value::from(false))
+        );
+        assert_eq!(
+            topics.unsubscribe("topic", &id, Some(&Session::default())),
+            Ok(Value::from(false))
+        );
+        assert_eq!(topics.subscribers("topic"), 1);
+
+        assert_eq!(
+            topics.unsubscribe("topic", &id, Some(&owner)),
+            Ok(Value::from(true))
+        );
+        assert_eq!(topics.subscribers("topic"), 0);
+    }
+
+    #[test]
+    fn subscribers_are_dropped_once_their_session_is_gone() {
+        let topics = Topics::default();
+        let (id, _notifications) = subscribe(&topics, Params::None, &Session::mock(), None);
+
+        assert_eq!(
+            topics.unsubscribe::<Session>("topic", &id.unwrap(), None),
+            Ok(Value::from(true))
+        );
+        assert_eq!(topics.subscribers("topic"), 0);
+    }
+
+    #[test]
+    fn filtered_subscribers_only_get_matching_notifications() {
+        let topics = Topics::default();
+        let filter = filter_parser(filter_fn(|addresses: &Vec<String>, value: &Value| {
+            addresses
+                .iter()
+                .any(|address| value["address"] == address.as_str())
+        }));
+        let meta = Session::mock();
+        let (_, mut all) = subscribe(&topics, Params::None, &meta, Some(&filter));
+        let (_, mut filtered) = subscribe(
+            &topics,
+            Params::Array(vec![json!("a")]),
+            &meta,
+            Some(&filter),
+        );
+
+        assert_eq!(topics.publish("topic", &json!({ "address": "a" })), 2);
+        assert_eq!(topics.publish("topic", &json!({ "address": "b" })), 1);
+
+        let notification = futures::executor::block_on(filtered.next()).unwrap();
+        assert!(notification.contains(r#""address":"a""#));
+        assert!(filtered.try_recv().is_err());
+        assert!(futures::executor::block_on(all.next()).is_some());
+        assert!(futures::executor::block_on(all.next()).is_some());
+    }
+
+    #[test]
+    fn invalid_criteria_are_rejected() {
+        let topics = Topics::default();
+        let filter = filter_parser(filter_fn(|_: &Vec<String>, _: &Value| true));
+        let meta = Session::mock();
+
+        let (id, _) = subscribe(&topics, Params::Array(vec![json!(1)]), &meta, Some(&filter));
+        assert!(id.is_err());
+        let (id, _) = subscribe(&topics, Params::Array(vec![json!("a")]), &meta, None);
+        assert!(id.is_err());
+        assert_eq!(topics.subscribers("topic"), 0);
+    }
+}
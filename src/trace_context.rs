@@ -0,0 +1,206 @@
+/// The name of the HTTP header, and of the optional JSON-RPC envelope field for TCP, that carries
+/// the W3C trace context of a request.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// The name of the HTTP header, and of the optional JSON-RPC envelope field for TCP, that carries
+/// vendor specific trace information along with `traceparent`.
+pub const TRACESTATE: &str = "tracestate";
+
+/// The W3C trace context of a request, i.e. the trace and span that the caller was in when it sent
+/// the request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceContext {
+    trace_id: String,
+    parent_id: String,
+    flags: u8,
+    tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// Parse the values of the `traceparent` and `tracestate` headers.
+    ///
+    /// Returns `None` if `traceparent` is malformed, in which case the request should be treated
+    /// as the start of a new trace.
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let parent_id = parts.next()?;
+        let flags = parts.next()?;
+
+        let is_hex = |value: &str, len: usize| {
+            value.len() == len
+                && value
+                    .bytes()
+                    .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+        };
+        let is_zero = |value: &str| value.bytes().all(|byte| byte == b'0');
+
+        // Version 00 has exactly four parts, while later versions may append more
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+        if !is_hex(trace_id, 32) || is_zero(trace_id) || !is_hex(parent_id, 16) {
+            return None;
+        }
+        if is_zero(parent_id) || !is_hex(flags, 2) {
+            return None;
+        }
+
+        Some(Self {
+            trace_id: String::from(trace_id),
+            parent_id: String::from(parent_id),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+            tracestate: tracestate
+                .map(str::trim)
+                .filter(|tracestate| !tracestate.is_empty())
+                .map(String::from),
+        })
+    }
+
+    /// The id of the trace, as 32 hexadecimal characters.
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// The id of the span that the caller was in, as 16 hexadecimal characters.
+    pub fn parent_id(&self) -> &str {
+        &self.parent_id
+    }
+
+    /// Whether the caller may have recorded the trace.
+    pub fn sampled(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// The vendor specific trace information, if any.
+    pub fn tracestate(&self) -> Option<&str> {
+        self.tracestate.as_deref()
+    }
+
+    /// Convert into an OpenTelemetry context whose active span is the remote span of the caller.
+    #[cfg(feature = "opentelemetry")]
+    pub fn to_otel_context(&self) -> opentelemetry::Context {
+        use opentelemetry::trace::{
+            SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+        };
+
+        let span_context = SpanContext::new(
+            TraceId::from_hex(&self.trace_id).unwrap_or(TraceId::INVALID),
+            SpanId::from_hex(&self.parent_id).unwrap_or(SpanId::INVALID),
+            TraceFlags::new(self.flags),
+            true,
+            self.tracestate()
+                .and_then(|tracestate| tracestate.parse::<TraceState>().ok())
+                .unwrap_or_default(),
+        );
+
+        opentelemetry::Context::new().with_remote_span_context(span_context)
+    }
+}
+
+/// A JSON-RPC middleware that makes the trace context of every request the current OpenTelemetry
+/// context while its calls are dispatched and executed, so that any span started by a method
+/// continues the trace of the caller.
+#[cfg(all(
+    feature = "opentelemetry",
+    any(feature = "http", feature = "tcp", feature = "ws")
+))]
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct RequestTraces;
+
+#[cfg(all(
+    feature = "opentelemetry",
+    any(feature = "http", feature = "tcp", feature = "ws")
+))]
+impl<M> jsonrpc_core::Middleware<M> for RequestTraces
+where
+    M: jsonrpc_core::Metadata + crate::handler::ConnectionMetadata,
+{
+    type Future = jsonrpc_core::middleware::NoopFuture;
+    type CallFuture = jsonrpc_core::middleware::NoopCallFuture;
+
+    fn on_call<F, X>(
+        &self,
+        call: jsonrpc_core::Call,
+        meta: M,
+        next: F,
+    ) -> futures::future::Either<Self::CallFuture, X>
+    where
+        F: Fn(jsonrpc_core::Call, M) -> X + Send + Sync,
+        X: futures::Future<Output = Option<jsonrpc_core::Output>> + Send + 'static,
+    {
+        use opentelemetry::context::FutureExt;
+
+        let context = match meta.trace_context() {
+            Some(trace_context) => trace_context.to_otel_context(),
+            None => return futures::future::Either::Right(next(call, meta)),
+        };
+
+        let response = {
+            let _guard = context.clone().attach();
+            next(call, meta)
+        };
+
+        futures::future::Either::Left(Box::pin(response.with_context(context)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    #[test]
+    fn parses_valid_traceparent() {
+        let traceparent = format!("00-{}-{}-01", TRACE_ID, PARENT_ID);
+        let trace_context = TraceContext::parse(&traceparent, Some(" vendor=value ")).unwrap();
+
+        assert_eq!(trace_context.trace_id(), TRACE_ID);
+        assert_eq!(trace_context.parent_id(), PARENT_ID);
+        assert!(trace_context.sampled());
+        assert_eq!(trace_context.tracestate(), Some("vendor=value"));
+
+        let traceparent = format!("00-{}-{}-00", TRACE_ID, PARENT_ID);
+        let trace_context = TraceContext::parse(&traceparent, Some("")).unwrap();
+        assert!(!trace_context.sampled());
+        assert_eq!(trace_context.tracestate(), None);
+    }
+
+    #[test]
+    fn later_versions_may_have_more_parts() {
+        let traceparent = format!("01-{}-{}-01-extra", TRACE_ID, PARENT_ID);
+
+        assert!(TraceContext::parse(&traceparent, None).is_some());
+    }
+
+    #[test]
+    fn rejects_malformed_traceparent() {
+        let zero_trace_id = "0".repeat(32);
+        let zero_parent_id = "0".repeat(16);
+        let upper_trace_id = TRACE_ID.to_uppercase();
+        let malformed = [
+            String::from(""),
+            format!("00-{}-{}", TRACE_ID, PARENT_ID),
+            format!("00-{}-{}-01-extra", TRACE_ID, PARENT_ID),
+            format!("ff-{}-{}-01", TRACE_ID, PARENT_ID),
+            format!("0-{}-{}-01", TRACE_ID, PARENT_ID),
+            format!("00-{}-{}-01", zero_trace_id, PARENT_ID),
+            format!("00-{}-{}-01", TRACE_ID, zero_parent_id),
+            format!("00-{}-{}-01", upper_trace_id, PARENT_ID),
+            format!("00-{}-{}-1", TRACE_ID, PARENT_ID),
+            format!("00-{}-{}-01", &TRACE_ID[1..], PARENT_ID),
+        ];
+
+        for traceparent in malformed {
+            assert_eq!(
+                TraceContext::parse(&traceparent, None),
+                None,
+                "{}",
+                traceparent
+            );
+        }
+    }
+}
@@ -1,27 +1,147 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    cmp,
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    thread::JoinHandle,
+};
 
-use jsonrpc_http_server::{Server, ServerBuilder};
+use futures::{channel::oneshot, future::Either};
+use jsonrpc_core::{middleware::NoopCallFuture, serde_json::json, BoxFuture, Metadata, Middleware};
+use jsonrpc_http_server::{
+    cors::AccessControlAllowHeaders,
+    hyper::{
+        self,
+        header::{HeaderValue, CONTENT_LENGTH},
+        server::conn::AddrStream,
+        service::{make_service_fn, Service},
+        Body, Method, Request, StatusCode,
+    },
+    RequestMiddleware, RequestMiddlewareAction, Response, Rpc, ServerHandler,
+};
+pub use jsonrpc_http_server::{AccessControlAllowOrigin, Host, RestApi};
+use jsonrpc_server_utils::hosts;
 
 use crate::{
-    handler::Handler,
-    transports::{Transport, TransportError},
+    access_log::AccessLog,
+    handler::{ConnectionMetadata, Handler},
+    health::Readiness,
+    trace_context::{TraceContext, TRACEPARENT, TRACESTATE},
+    transports::{
+        io_handler_with_middleware,
+        limits::{
+            error_response, reject_request, ConnectionLimits, ConnectionTracker, IpNet,
+            RequestLimits,
+        },
+        transport_middleware, LoggedMiddleware, RequestMetrics, Transport, TransportError,
+        TransportMiddleware,
+    },
+    versioning::{ApiVersionSelection, API_VERSION_HEADER},
 };
 
+/// The header that reverse proxies put the addresses of the clients they forward requests for in.
+const FORWARDED_FOR: &str = "x-forwarded-for";
+/// The maximum size in bytes of request bodies if no limit is set.
+const DEFAULT_MAX_BODY_SIZE: usize = 5 * 1024 * 1024;
+
 /// Settings needed for constructing an `HttpTransport`.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct HttpTransportSettings {
     /// An IP or address to bind the HTTP listener to.
     pub address: String,
+    /// Restrictions on which peers can send requests.
+    ///
+    /// Requests from peers that are not allowed are answered with `403 Forbidden`. As the requests
+    /// of many clients can share a single connection through a reverse proxy, `max_connections`
+    /// and `max_connections_per_ip` limit how many requests are processed at the same time rather
+    /// than how many connections are open. Requests above those limits are answered with a
+    /// JSON-RPC error.
+    pub connection_limits: ConnectionLimits,
+    /// Limits on the size and shape of every request.
+    ///
+    /// Requests that declare a `Content-Length` above the maximum size are answered with a
+    /// JSON-RPC error and a `413 Payload Too Large` status. Bodies that exceed it without declaring
+    /// their length, e.g. chunked ones, are answered with the same JSON-RPC error as soon as they
+    /// reach it, but with a `200 OK` status, as by then the status is up to the underlying server.
+    pub request_limits: RequestLimits,
+    /// A path, e.g. `/metrics`, on which to serve the metrics of the server in the Prometheus
+    /// text format, if metrics are enabled on the server.
+    ///
+    /// The number of active connections is not recorded for HTTP. Just like calls, the metrics are
+    /// only served to peers that the `connection_limits` allow.
+    #[cfg(feature = "metrics")]
+    pub metrics_path: Option<String>,
+    /// A path, e.g. `/health`, on which to answer `GET` requests with `200 OK` for as long as the
+    /// server is alive.
+    ///
+    /// Just like calls, this path is only answered to peers that the `connection_limits` allow,
+    /// or that belong to the `probe_networks`.
+    pub health_path: Option<String>,
+    /// A path, e.g. `/ready`, on which to answer `GET` requests with `200 OK` if all the transports
+    /// of the server are running and its readiness check passes, or with
+    /// `503 Service Unavailable` otherwise.
+    ///
+    /// Just like the `health_path`, this path is only answered to peers that the
+    /// `connection_limits` allow, or that belong to the `probe_networks`.
+    pub ready_path: Option<String>,
+    /// The networks that probes can come from even if the allow list of the `connection_limits`
+    /// leaves them out, e.g. that of an orchestrator that reaches the transport without going
+    /// through the reverse proxies. Peers in the deny list are rejected all the same.
+    pub probe_networks: Vec<IpNet>,
+    /// The origins that browsers are allowed to make cross-origin requests from.
+    ///
+    /// If `None`, no CORS headers are sent and browsers will only allow same-origin requests.
+    pub cors_origins: Option<Vec<AccessControlAllowOrigin>>,
+    /// The values of the `Host` header that requests are accepted with, e.g. for protecting
+    /// against DNS rebinding.
+    ///
+    /// If `None`, requests are accepted regardless of their `Host` header.
+    pub allowed_hosts: Option<Vec<Host>>,
+    /// The maximum size in bytes of request bodies.
+    ///
+    /// Defaults to the maximum request size in `request_limits` if set, or else to the 5 MiB
+    /// default of the underlying server. Bodies larger than the maximum request size are rejected
+    /// all the same, with `413 Payload Too Large`.
+    pub max_body_size: Option<usize>,
+    /// Whether to keep connections alive between requests, which is enabled by default.
+    pub keep_alive: Option<bool>,
+    /// How many threads to process requests on, which is one by default.
+    pub threads: Option<NonZeroUsize>,
+    /// Whether to accept REST-style `POST /<method>/<param1>/<param2>` requests, which is
+    /// disabled by default.
+    pub rest_api: Option<RestApi>,
+    /// How requests select the version of the API that their calls are dispatched to, if the
+    /// server has versions of its API.
+    ///
+    /// Selecting the version from the path is not compatible with the REST API.
+    pub api_version: ApiVersionSelection,
+}
+
+/// A running HTTP server, along with the means for stopping it.
+struct Server {
+    stop: oneshot::Sender<()>,
+    thread: JoinHandle<()>,
 }
 
 /// A JSON-RPC over HTTP transport built around the `jsonrpc_http_server` library.
+///
+/// The requests of every connection are handled by the `jsonrpc_http_server` library, but the
+/// connections themselves are accepted by this transport, so that the address of their remote
+/// socket is known.
 pub struct HttpTransport<H>
 where
     H: Handler,
 {
     settings: HttpTransportSettings,
-    server_builder: Option<ServerBuilder<H::Metadata>>,
+    connection_tracker: ConnectionTracker,
+    rpc: Option<Rpc<H::Metadata, HttpMiddleware>>,
+    request_middleware: Option<Arc<dyn RequestMiddleware>>,
     server: Option<Server>,
+    request_metrics: RequestMetrics,
+    readiness: Readiness,
+    access_log: AccessLog,
 }
 
 impl<H> HttpTransport<H>
@@ -30,10 +150,17 @@ where
 {
     /// Create a new instance of this transport.
     pub fn new(settings: HttpTransportSettings) -> Self {
+        let connection_tracker = ConnectionTracker::new(settings.connection_limits.clone());
+
         Self {
             settings,
-            server_builder: None,
+            connection_tracker,
+            rpc: None,
+            request_middleware: None,
             server: None,
+            request_metrics: Default::default(),
+            readiness: Default::default(),
+            access_log: Default::default(),
         }
     }
 }
@@ -52,8 +179,136 @@ where
     }
 
     fn set_handler(&mut self, handler: Arc<Mutex<H>>) -> Result<(), TransportError> {
-        let handler = (*handler.lock().unwrap()).as_meta_io_handler();
-        self.server_builder = Some(ServerBuilder::new(handler));
+        let io_handler = io_handler_with_middleware(
+            &handler,
+            (
+                self.access_log.clone(),
+                (
+                    RequestAdmission(self.connection_tracker.clone()),
+                    transport_middleware(
+                        self.settings.request_limits,
+                        self.request_metrics.clone(),
+                    ),
+                ),
+            ),
+        );
+        let limits = self.settings.connection_limits.clone();
+        let meta_limits = limits.clone();
+        let probe_limits = probe_limits(&limits, &self.settings.probe_networks);
+        let request_limits = self.settings.request_limits;
+        let health_path = self.settings.health_path.clone();
+        let ready_path = self.settings.ready_path.clone();
+        let readiness = self.readiness.clone();
+        let api_version = self.settings.api_version.clone();
+        #[cfg(feature = "metrics")]
+        let metrics_endpoint = self
+            .settings
+            .metrics_path
+            .clone()
+            .zip(self.request_metrics.0.clone());
+        let meta_extractor = move |request: &Request<Body>| {
+            let mut meta = H::Metadata::default();
+            meta.set_transport("http");
+            let header = |name| {
+                request
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+            };
+            if let Some(ip) = peer_ip(&meta_limits, request) {
+                // The port is only known if the peer is not behind a proxy
+                let peer_addr = request
+                    .extensions()
+                    .get::<RemoteAddr>()
+                    .map(|RemoteAddr(remote_addr)| *remote_addr)
+                    .filter(|remote_addr| remote_addr.ip() == ip)
+                    .unwrap_or_else(|| SocketAddr::new(ip, 0));
+                meta.set_peer_addr(peer_addr);
+            }
+            if let Some(trace_context) = header(TRACEPARENT)
+                .and_then(|traceparent| TraceContext::parse(traceparent, header(TRACESTATE)))
+            {
+                meta.set_trace_context(trace_context);
+            }
+            if let Some(version) =
+                api_version.select(Some(request.uri().path()), header(API_VERSION_HEADER))
+            {
+                meta.set_api_version(version);
+            }
+
+            meta
+        };
+        let request_middleware = move |request: Request<Body>| {
+            let peer = peer_ip(&limits, &request);
+
+            #[cfg(feature = "metrics")]
+            if let Some((path, metrics)) = &metrics_endpoint {
+                if request.uri().path() == path && limits.is_allowed(peer) {
+                    return Response {
+                        code: StatusCode::OK,
+                        content_type: HeaderValue::from_static(
+                            crate::metrics::METRICS_CONTENT_TYPE,
+                        ),
+                        content: metrics.render(),
+                    }
+                    .into();
+                }
+            }
+
+            if request.method() == Method::GET && probe_limits.is_allowed(peer) {
+                let path = Some(request.uri().path());
+                if path == health_path.as_deref() {
+                    return Response::ok(json!({ "status": "ok" }).to_string()).into();
+                }
+                if path == ready_path.as_deref() {
+                    return readiness_response(&readiness).into();
+                }
+            }
+
+            let content_length = request
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<usize>().ok());
+
+            if !limits.is_allowed(peer) {
+                log::warn!(
+                    "Rejecting HTTP request from {:?}: address not allowed",
+                    peer
+                );
+
+                Response {
+                    code: StatusCode::FORBIDDEN,
+                    content_type: HeaderValue::from_static("text/plain; charset=utf-8"),
+                    content: String::from("Peer address is not allowed.\n"),
+                }
+                .into()
+            } else if matches!(
+                (request_limits.max_request_size, content_length),
+                (Some(max), Some(length)) if length > max
+            ) {
+                log::warn!("Rejecting oversized HTTP request from {:?}", peer);
+
+                Response {
+                    code: StatusCode::PAYLOAD_TOO_LARGE,
+                    content_type: HeaderValue::from_static("application/json; charset=utf-8"),
+                    content: error_response(request_limits.too_large_error()),
+                }
+                .into()
+            } else {
+                // Bodies that do not declare their length are only known to be oversized once they
+                // are read, which the underlying server does within the maximum body size
+                RequestMiddlewareAction::Proceed {
+                    should_continue_on_invalid_cors: false,
+                    request,
+                }
+            }
+        };
+        self.rpc = Some(Rpc {
+            handler: Arc::new(io_handler),
+            extractor: Arc::new(meta_extractor),
+        });
+        self.request_middleware = Some(Arc::new(request_middleware));
 
         Ok(())
     }
@@ -63,12 +318,78 @@ where
             return Ok(());
         }
 
-        let builder = self
-            .server_builder
-            .take()
+        let (rpc, request_middleware) = self
+            .rpc
+            .clone()
+            .zip(self.request_middleware.clone())
             .ok_or(TransportError::NoHandler)?;
-        let socket_addr = self.settings.address.parse::<std::net::SocketAddr>()?;
-        self.server = Some(builder.start_http(&socket_addr)?);
+        let socket_addr = self.settings.address.parse::<SocketAddr>()?;
+        let listener = std::net::TcpListener::bind(socket_addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(self.settings.threads.map_or(1, NonZeroUsize::get))
+            .thread_name(format!("witty-jsonrpc-http-{}", socket_addr))
+            .enable_all()
+            .build()?;
+        let server = {
+            let _guard = runtime.enter();
+            hyper::Server::from_tcp(listener).map_err(std::io::Error::other)?
+        };
+
+        let cors_origins = self.settings.cors_origins.clone();
+        let allowed_hosts = hosts::update(self.settings.allowed_hosts.clone(), &local_addr);
+        let rest_api = self.settings.rest_api.unwrap_or(RestApi::Disabled);
+        let max_body_size = cmp::min(
+            self.settings
+                .max_body_size
+                .or(self.settings.request_limits.max_request_size)
+                .unwrap_or(DEFAULT_MAX_BODY_SIZE),
+            self.settings
+                .request_limits
+                .max_request_size
+                .unwrap_or(usize::MAX),
+        );
+        let keep_alive = self.settings.keep_alive.unwrap_or(true);
+        let make_service = make_service_fn(move |connection: &AddrStream| {
+            let handler = ServerHandler::new(
+                rpc.downgrade(),
+                cors_origins.clone(),
+                None,
+                AccessControlAllowHeaders::Any,
+                allowed_hosts.clone(),
+                request_middleware.clone(),
+                rest_api,
+                None,
+                max_body_size,
+                keep_alive,
+            );
+            let service = WithRemoteAddr {
+                remote_addr: connection.remote_addr(),
+                handler,
+            };
+
+            futures::future::ok::<_, Infallible>(service)
+        });
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = server
+            .http1_keepalive(keep_alive)
+            .tcp_nodelay(true)
+            .tcp_sleep_on_accept_errors(true)
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                stopped.await.ok();
+            });
+
+        let thread = std::thread::Builder::new()
+            .name(format!("witty-jsonrpc-http-{}", socket_addr))
+            .spawn(move || {
+                if let Err(error) = runtime.block_on(server) {
+                    log::error!("Error running HTTP server: {}", error);
+                }
+            })?;
+        self.server = Some(Server { stop, thread });
 
         Ok(())
     }
@@ -77,9 +398,419 @@ where
         match self.server.take() {
             None => Ok(()),
             Some(server) => {
-                server.close();
+                // Connections with requests in flight are closed once those are answered
+                let _ = server.stop.send(());
+                server.thread.join().map_err(|_| TransportError::Unknown)?;
+
                 Ok(())
             }
         }
     }
+
+    fn set_readiness(&mut self, readiness: Readiness) {
+        self.readiness = readiness;
+    }
+
+    fn set_access_log(&mut self, access_log: AccessLog) {
+        self.access_log = access_log;
+    }
+
+    #[cfg(feature = "metrics")]
+    fn set_metrics(&mut self, metrics: crate::metrics::Metrics) {
+        self.request_metrics = RequestMetrics(Some(metrics));
+    }
+}
+
+/// The JSON-RPC middleware of the HTTP transport, which puts `RequestAdmission` in front of the
+/// middleware of every transport, behind the access log.
+type HttpMiddleware = LoggedMiddleware<(RequestAdmission, TransportMiddleware)>;
+
+/// Enforces the connection limits on the requests that are processed at the same time, as the
+/// underlying server does not expose its connections.
+struct RequestAdmission(ConnectionTracker);
+
+impl<M> Middleware<M> for RequestAdmission
+where
+    M: Metadata + ConnectionMetadata,
+{
+    type Future = BoxFuture<Option<jsonrpc_core::Response>>;
+    type CallFuture = NoopCallFuture;
+
+    fn on_request<F, X>(
+        &self,
+        request: jsonrpc_core::Request,
+        meta: M,
+        next: F,
+    ) -> Either<Self::Future, X>
+    where
+        F: Fn(jsonrpc_core::Request, M) -> X + Send + Sync,
+        X: futures::Future<Output = Option<jsonrpc_core::Response>> + Send + 'static,
+    {
+        let peer = meta.peer_addr().map(|peer_addr| peer_addr.ip());
+        match self.0.admit_peer(peer) {
+            Ok(permit) => {
+                let response = next(request, meta);
+
+                Either::Left(Box::pin(async move {
+                    let response = response.await;
+                    drop(permit);
+
+                    response
+                }))
+            }
+            Err(rejection) => {
+                log::warn!("Rejecting HTTP request from {:?}: {}", peer, rejection);
+                let response = reject_request(request, rejection.into());
+
+                Either::Left(Box::pin(futures::future::ready(response)))
+            }
+        }
+    }
+}
+
+/// The address of the remote socket of the connection that a request came through, which is
+/// added to the extensions of every request.
+#[derive(Clone, Copy, Debug)]
+struct RemoteAddr(SocketAddr);
+
+/// Serves the requests of a single connection, after adding the address of its remote socket to
+/// their extensions.
+struct WithRemoteAddr<M, S>
+where
+    M: Metadata,
+    S: Middleware<M>,
+{
+    remote_addr: SocketAddr,
+    handler: ServerHandler<M, S>,
+}
+
+impl<M, S> Service<Request<Body>> for WithRemoteAddr<M, S>
+where
+    M: Metadata + Unpin,
+    S: Middleware<M>,
+    S::Future: Unpin,
+    S::CallFuture: Unpin,
+{
+    type Response = <ServerHandler<M, S> as Service<Request<Body>>>::Response;
+    type Error = <ServerHandler<M, S> as Service<Request<Body>>>::Error;
+    type Future = <ServerHandler<M, S> as Service<Request<Body>>>::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.handler.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        request
+            .extensions_mut()
+            .insert(RemoteAddr(self.remote_addr));
+
+        self.handler.call(request)
+    }
+}
+
+/// Find the address of the peer that sent a request, out of the remote socket of its connection
+/// and, if that belongs to a trusted proxy, the `X-Forwarded-For` header.
+fn peer_ip(limits: &ConnectionLimits, request: &Request<Body>) -> Option<IpAddr> {
+    let RemoteAddr(remote_addr) = request.extensions().get::<RemoteAddr>()?;
+    let forwarded_for = request
+        .headers()
+        .get(FORWARDED_FOR)
+        .and_then(|value| value.to_str().ok());
+
+    limits.client_ip(remote_addr.ip(), forwarded_for)
+}
+
+/// Extend the allow list of some connection limits with the networks that probes can come from.
+///
+/// Without an allow list, every peer that is not denied is already allowed.
+fn probe_limits(limits: &ConnectionLimits, probe_networks: &[IpNet]) -> ConnectionLimits {
+    let mut probe_limits = limits.clone();
+    if !probe_limits.allowed_networks.is_empty() {
+        probe_limits
+            .allowed_networks
+            .extend_from_slice(probe_networks);
+    }
+
+    probe_limits
+}
+
+/// Build the response of the readiness endpoint, which tells apart the reasons for not being ready.
+fn readiness_response(readiness: &Readiness) -> Response {
+    let transports_running = readiness.transports_running();
+    let check_passes = readiness.check_passes();
+    let content = json!({
+        "status": if transports_running && check_passes { "ready" } else { "not ready" },
+        "transports_running": transports_running,
+        "check_passes": check_passes,
+    })
+    .to_string();
+
+    if transports_running && check_passes {
+        Response::ok(content)
+    } else {
+        Response::service_unavailable(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use jsonrpc_core::{serde_json, Value};
+
+    use super::*;
+    use crate::{
+        server::Server as _,
+        transports::{
+            echo_server, free_address,
+            limits::{ConnectionLimits, REQUEST_LIMIT_ERROR_CODE},
+        },
+    };
+
+    /// Send a request without a body and with some extra headers, returning the whole response.
+    fn send_with_headers(address: &str, method: &str, path: &str, headers: &[&str]) -> String {
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        let headers = headers
+            .iter()
+            .map(|header| format!("{}\r\n", header))
+            .collect::<String>();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+            method, path, headers
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        response
+    }
+
+    /// Send a request without a body, returning the status line of the response.
+    fn send_empty(address: &str, method: &str, path: &str) -> String {
+        let host = format!("Host: {}", address);
+        let response = send_with_headers(address, method, path, &[&host]);
+
+        String::from(response.lines().next().unwrap())
+    }
+
+    /// Send a call to the `echo` method, optionally forwarded for some addresses, returning the
+    /// status line of the response.
+    fn send_forwarded(address: &str, forwarded_for: Option<&str>) -> String {
+        let call = json!({ "jsonrpc": "2.0", "id": 1, "method": "echo" }).to_string();
+        let forwarded_for = forwarded_for
+            .map(|forwarded_for| format!("X-Forwarded-For: {}\r\n", forwarded_for))
+            .unwrap_or_default();
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n{}\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            address,
+            forwarded_for,
+            call.len(),
+            call
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        String::from(response.lines().next().unwrap())
+    }
+
+    /// Send a call with a chunked body, returning the status line and the body of the response.
+    fn send_chunked(address: &str, call: &str) -> (String, String) {
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            address,
+            call.len(),
+            call
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+
+        (
+            String::from(head.lines().next().unwrap()),
+            String::from(body),
+        )
+    }
+
+    #[test]
+    fn oversized_chunked_bodies_are_rejected_with_payload_too_large() {
+        let address = free_address();
+        let mut server = echo_server(HttpTransport::new(HttpTransportSettings {
+            address: address.clone(),
+            request_limits: RequestLimits {
+                max_request_size: Some(128),
+                ..Default::default()
+            },
+            max_body_size: Some(1024),
+            ..Default::default()
+        }));
+
+        let call = json!({ "jsonrpc": "2.0", "id": 1, "method": "echo", "params": ["hello"] });
+        let (status, body) = send_chunked(&address, &call.to_string());
+        assert!(status.contains("200 OK"));
+        let response = serde_json::from_str::<Value>(&body).unwrap();
+        assert_eq!(response["result"], json!(["hello"]));
+
+        let call =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "echo", "params": ["x".repeat(200)] });
+        let (status, _) = send_chunked(&address, &call.to_string());
+        assert!(status.contains("413 Payload Too Large"));
+
+        // Bodies that declare their length are rejected before being read, with a JSON-RPC error
+        let call = call.to_string();
+        let mut stream = std::net::TcpStream::connect(&address).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            address,
+            call.len(),
+            call
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains("413 Payload Too Large"));
+        let response = serde_json::from_str::<Value>(body).unwrap();
+        assert_eq!(response["error"]["code"], json!(REQUEST_LIMIT_ERROR_CODE));
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn cors_preflights_are_only_answered_for_allowed_origins() {
+        let address = free_address();
+        let mut server = echo_server(HttpTransport::new(HttpTransportSettings {
+            address: address.clone(),
+            cors_origins: Some(vec![AccessControlAllowOrigin::Value(
+                "https://allowed.example".into(),
+            )]),
+            ..Default::default()
+        }));
+        let host = format!("Host: {}", address);
+        let preflight = |origin: &str| {
+            let origin = format!("Origin: {}", origin);
+            send_with_headers(
+                &address,
+                "OPTIONS",
+                "/",
+                &[&host, &origin, "Access-Control-Request-Method: POST"],
+            )
+            .to_lowercase()
+        };
+
+        let response = preflight("https://allowed.example");
+        assert!(response.starts_with("http/1.1 200 ok"));
+        assert!(response.contains("access-control-allow-origin: https://allowed.example"));
+
+        let response = preflight("https://other.example");
+        assert!(response.starts_with("http/1.1 403 forbidden"));
+        assert!(!response.contains("access-control-allow-origin"));
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn requests_with_a_host_that_is_not_allowed_are_rejected() {
+        let address = free_address();
+        let mut server = echo_server(HttpTransport::new(HttpTransportSettings {
+            address: address.clone(),
+            allowed_hosts: Some(vec![Host::from("rpc.example")]),
+            ..Default::default()
+        }));
+        let status = |host: &str| {
+            let host = format!("Host: {}", host);
+            let response = send_with_headers(&address, "POST", "/", &[&host]);
+
+            String::from(response.lines().next().unwrap())
+        };
+
+        assert!(status("rebound.example").contains("403 Forbidden"));
+        // Requests to the allowed host, or straight to the address of the server, are accepted
+        assert!(!status("rpc.example").contains("403"));
+        assert!(!status(&address).contains("403"));
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn probes_are_only_answered_to_allowed_peers_and_probe_networks() {
+        let probes_server = |probe_networks: Vec<IpNet>, denied_networks: Vec<IpNet>| {
+            let address = free_address();
+            let server = echo_server(HttpTransport::new(HttpTransportSettings {
+                address: address.clone(),
+                connection_limits: ConnectionLimits {
+                    allowed_networks: vec!["10.0.0.0/8".parse().unwrap()],
+                    denied_networks,
+                    ..Default::default()
+                },
+                health_path: Some(String::from("/health")),
+                ready_path: Some(String::from("/ready")),
+                probe_networks,
+                ..Default::default()
+            }));
+
+            (address, server)
+        };
+        let localhost = || vec!["127.0.0.0/8".parse().unwrap()];
+
+        let (address, mut server) = probes_server(Vec::new(), Vec::new());
+        assert!(send_empty(&address, "GET", "/health").contains("403 Forbidden"));
+        assert!(send_empty(&address, "GET", "/ready").contains("403 Forbidden"));
+        server.stop().unwrap();
+
+        let (address, mut server) = probes_server(localhost(), Vec::new());
+        assert!(send_empty(&address, "GET", "/health").contains("200 OK"));
+        assert!(!send_empty(&address, "GET", "/ready").contains("403"));
+        assert!(send_empty(&address, "POST", "/").contains("403 Forbidden"));
+        server.stop().unwrap();
+
+        let (address, mut server) = probes_server(localhost(), localhost());
+        assert!(send_empty(&address, "GET", "/health").contains("403 Forbidden"));
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn peers_are_told_apart_by_their_socket_unless_it_is_a_trusted_proxy() {
+        let address = free_address();
+        let mut server = echo_server(HttpTransport::new(HttpTransportSettings {
+            address: address.clone(),
+            connection_limits: ConnectionLimits {
+                allowed_networks: vec!["127.0.0.0/8".parse().unwrap()],
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+
+        // Peers that are not trusted proxies cannot pass as someone else
+        assert!(send_forwarded(&address, None).contains("200 OK"));
+        assert!(send_forwarded(&address, Some("10.0.0.1")).contains("200 OK"));
+        server.stop().unwrap();
+
+        let address = free_address();
+        let mut server = echo_server(HttpTransport::new(HttpTransportSettings {
+            address: address.clone(),
+            connection_limits: ConnectionLimits {
+                allowed_networks: vec!["10.0.0.0/8".parse().unwrap()],
+                trusted_proxies: vec!["127.0.0.0/8".parse().unwrap()],
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+
+        assert!(send_forwarded(&address, None).contains("403 Forbidden"));
+        assert!(send_forwarded(&address, Some("10.0.0.1")).contains("200 OK"));
+        assert!(send_forwarded(&address, Some("10.0.0.1, 1.2.3.4")).contains("403 Forbidden"));
+
+        server.stop().unwrap();
+    }
 }
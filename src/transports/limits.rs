@@ -0,0 +1,550 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use futures::future::Either;
+pub use ipnet::IpNet;
+use jsonrpc_core::{
+    middleware::{NoopCallFuture, NoopFuture},
+    serde_json::json,
+    Call, Error, ErrorCode, Metadata, Middleware, Output, Params, Request, Response, Value,
+    Version,
+};
+
+/// The JSON-RPC error code used for notifying peers that their connection was rejected.
+pub const CONNECTION_REJECTED_ERROR_CODE: i64 = -32006;
+
+/// The JSON-RPC error code used for rejecting requests that exceed the `RequestLimits` of a
+/// transport.
+pub const REQUEST_LIMIT_ERROR_CODE: i64 = -32009;
+
+/// Settings that restrict which peers can connect to a transport, and how many connections can be
+/// open at the same time.
+///
+/// Peers are told apart by the address of the remote socket, unless it belongs to one of the
+/// `trusted_proxies`. This means that a transport behind a reverse proxy that is not one of the
+/// `trusted_proxies` judges every peer by the address of the proxy, so allowing the proxy allows
+/// everyone.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionLimits {
+    /// Maximum number of connections that can be open at the same time.
+    ///
+    /// Over HTTP, this is the maximum number of requests that can be processed at the same time.
+    pub max_connections: Option<usize>,
+    /// Maximum number of connections that a single IP address can have open at the same time.
+    ///
+    /// Over HTTP, this is the maximum number of requests from a single IP address that can be
+    /// processed at the same time.
+    pub max_connections_per_ip: Option<usize>,
+    /// If not empty, only peers whose address is in one of these networks are accepted.
+    pub allowed_networks: Vec<IpNet>,
+    /// Peers whose address is in one of these networks are always rejected.
+    pub denied_networks: Vec<IpNet>,
+    /// The networks of the reverse proxies in front of the transport, whose `X-Forwarded-For`
+    /// header is trusted for learning the address of the actual peer.
+    ///
+    /// The header is read from right to left, and the first address that is not in one of these
+    /// networks is taken as the address of the peer, so that clients cannot make it up. The header
+    /// of peers that are not one of these proxies is ignored.
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl ConnectionLimits {
+    /// Tell whether a peer is allowed to connect according to the allow and deny lists.
+    ///
+    /// Peers with an unknown address are only allowed if there are neither allow nor deny lists.
+    pub fn is_allowed(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) => {
+                !self.denied_networks.iter().any(|net| net.contains(&ip))
+                    && (self.allowed_networks.is_empty()
+                        || self.allowed_networks.iter().any(|net| net.contains(&ip)))
+            }
+            None => self.allowed_networks.is_empty() && self.denied_networks.is_empty(),
+        }
+    }
+
+    /// Find the address of a peer out of the address of the remote socket and the value of the
+    /// `X-Forwarded-For` header, if any.
+    ///
+    /// The header is only taken into account if the socket belongs to one of the trusted proxies.
+    /// Addresses that cannot be parsed make the address of the peer unknown.
+    pub fn client_ip(&self, socket_ip: IpAddr, forwarded_for: Option<&str>) -> Option<IpAddr> {
+        let mut client_ip = socket_ip;
+        let hops = forwarded_for
+            .into_iter()
+            .flat_map(|header| header.rsplit(','));
+        for hop in hops {
+            if !self.is_trusted_proxy(client_ip) {
+                break;
+            }
+            client_ip = hop.trim().parse().ok()?;
+        }
+
+        Some(client_ip)
+    }
+
+    /// Tell whether an address belongs to one of the trusted proxies.
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Enumerates the reasons why a connection can be rejected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConnectionRejection {
+    /// The address of the peer is not allowed by the allow and deny lists.
+    AddressNotAllowed,
+    /// The transport already has as many open connections as allowed.
+    TooManyConnections,
+    /// The peer already has as many open connections as allowed.
+    TooManyConnectionsFromPeer,
+}
+
+impl fmt::Display for ConnectionRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AddressNotAllowed => write!(f, "peer address is not allowed"),
+            Self::TooManyConnections => write!(f, "too many open connections"),
+            Self::TooManyConnectionsFromPeer => {
+                write!(f, "too many open connections from the same address")
+            }
+        }
+    }
+}
+
+impl From<ConnectionRejection> for Error {
+    fn from(value: ConnectionRejection) -> Self {
+        Error {
+            code: ErrorCode::ServerError(CONNECTION_REJECTED_ERROR_CODE),
+            message: format!("Connection rejected: {}", value),
+            data: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    open: usize,
+    open_per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Keeps count of the open connections of a transport, and enforces `ConnectionLimits` on new
+/// ones.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionTracker {
+    limits: ConnectionLimits,
+    state: Arc<Mutex<TrackerState>>,
+}
+
+impl ConnectionTracker {
+    /// Create a new tracker that enforces the provided limits.
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits,
+            state: Default::default(),
+        }
+    }
+
+    /// The limits that this tracker enforces.
+    pub fn limits(&self) -> &ConnectionLimits {
+        &self.limits
+    }
+
+    /// Decide whether a new connection from `ip` can be accepted, and count it if so.
+    ///
+    /// The connection keeps counting towards the limits until the returned permit is dropped.
+    pub fn admit(&self, ip: IpAddr) -> Result<ConnectionPermit, ConnectionRejection> {
+        self.admit_peer(Some(ip))
+    }
+
+    /// Decide whether a new connection from a peer whose address may be unknown can be accepted,
+    /// and count it if so.
+    ///
+    /// Connections from unknown peers are subject to the allow and deny lists as explained in
+    /// `ConnectionLimits::is_allowed`, and only count towards `max_connections`.
+    pub fn admit_peer(&self, ip: Option<IpAddr>) -> Result<ConnectionPermit, ConnectionRejection> {
+        if !self.limits.is_allowed(ip) {
+            return Err(ConnectionRejection::AddressNotAllowed);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if matches!(self.limits.max_connections, Some(max) if state.open >= max) {
+            return Err(ConnectionRejection::TooManyConnections);
+        }
+        if let Some(ip) = ip {
+            let open_from_ip = state.open_per_ip.get(&ip).copied().unwrap_or_default();
+            if matches!(self.limits.max_connections_per_ip, Some(max) if open_from_ip >= max) {
+                return Err(ConnectionRejection::TooManyConnectionsFromPeer);
+            }
+            state.open_per_ip.insert(ip, open_from_ip + 1);
+        }
+        state.open += 1;
+
+        Ok(ConnectionPermit {
+            tracker: self.clone(),
+            ip,
+        })
+    }
+
+    fn release(&self, ip: Option<IpAddr>) {
+        let mut state = self.state.lock().unwrap();
+        state.open = state.open.saturating_sub(1);
+        if let Some(ip) = ip {
+            if let Some(count) = state.open_per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    state.open_per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
+
+/// Proof that a connection was admitted by a `ConnectionTracker`.
+///
+/// The connection stops counting towards the limits once this is dropped.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    tracker: ConnectionTracker,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.tracker.release(self.ip);
+    }
+}
+
+/// Limits on the size and shape of the JSON-RPC requests that a transport accepts.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestLimits {
+    /// Maximum size in bytes of a single request, or of a single line for TCP.
+    ///
+    /// Over HTTP, oversized requests are answered with `413 Payload Too Large`, along with a
+    /// JSON-RPC error if they declare their length up front. Over TCP and WebSockets,
+    /// the error is sent and then the connection is closed, as the rest of the stream cannot be
+    /// trusted.
+    pub max_request_size: Option<usize>,
+    /// Maximum number of calls in a batch request.
+    pub max_batch_size: Option<usize>,
+    /// Maximum nesting depth of the parameters of every call, where a flat array or object of
+    /// parameters has a depth of 1.
+    pub max_depth: Option<usize>,
+}
+
+impl RequestLimits {
+    /// Check a parsed request against the batch size and nesting depth limits.
+    pub fn check(&self, request: &Request) -> Result<(), Error> {
+        let calls = match request {
+            Request::Single(call) => std::slice::from_ref(call),
+            Request::Batch(calls) => calls.as_slice(),
+        };
+
+        if let Some(max_batch_size) = self.max_batch_size {
+            if calls.len() > max_batch_size {
+                return Err(request_limit_error(
+                    format!(
+                        "Batch of {} calls exceeds the maximum of {}",
+                        calls.len(),
+                        max_batch_size
+                    ),
+                    "max_batch_size",
+                    max_batch_size,
+                ));
+            }
+        }
+
+        if let Some(max_depth) = self.max_depth {
+            let too_deep = calls.iter().any(|call| {
+                let params = match call {
+                    Call::MethodCall(call) => &call.params,
+                    Call::Notification(notification) => &notification.params,
+                    Call::Invalid { .. } => return false,
+                };
+                let params_depth = match params {
+                    Params::None => 0,
+                    Params::Array(values) => 1 + values.iter().map(depth).max().unwrap_or(0),
+                    Params::Map(values) => 1 + values.values().map(depth).max().unwrap_or(0),
+                };
+
+                params_depth > max_depth
+            });
+
+            if too_deep {
+                return Err(request_limit_error(
+                    format!(
+                        "Parameters are nested deeper than the maximum of {}",
+                        max_depth
+                    ),
+                    "max_depth",
+                    max_depth,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the error that is returned when a request is larger than `max_request_size`.
+    pub fn too_large_error(&self) -> Error {
+        let max_request_size = self.max_request_size.unwrap_or_default();
+
+        request_limit_error(
+            format!(
+                "Request exceeds the maximum size of {} bytes",
+                max_request_size
+            ),
+            "max_request_size",
+            max_request_size,
+        )
+    }
+}
+
+impl<M> Middleware<M> for RequestLimits
+where
+    M: Metadata,
+{
+    type Future = NoopFuture;
+    type CallFuture = NoopCallFuture;
+
+    fn on_request<F, X>(&self, request: Request, meta: M, next: F) -> Either<Self::Future, X>
+    where
+        F: Fn(Request, M) -> X + Send + Sync,
+        X: futures::Future<Output = Option<Response>> + Send + 'static,
+    {
+        match self.check(&request) {
+            Ok(()) => Either::Right(next(request, meta)),
+            Err(error) => {
+                log::debug!("Rejecting JSON-RPC request: {}", error.message);
+                let response = reject_request(request, error);
+
+                Either::Left(Box::pin(futures::future::ready(response)))
+            }
+        }
+    }
+}
+
+/// Calculate the nesting depth of a JSON value, where scalars have a depth of 0.
+fn depth(value: &Value) -> usize {
+    match value {
+        Value::Array(values) => 1 + values.iter().map(depth).max().unwrap_or(0),
+        Value::Object(values) => 1 + values.values().map(depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Build the JSON-RPC error that is returned when a request exceeds one of the `RequestLimits`.
+fn request_limit_error(message: String, limit: &str, max: usize) -> Error {
+    Error {
+        code: ErrorCode::ServerError(REQUEST_LIMIT_ERROR_CODE),
+        message,
+        data: Some(json!({ "limit": limit, "max": max })),
+    }
+}
+
+/// Serialize a JSON-RPC error response that is not tied to any particular call.
+#[cfg(any(feature = "http", feature = "tcp", feature = "ws"))]
+pub(crate) fn error_response(error: Error) -> String {
+    jsonrpc_core::serde_json::to_string(&Response::from(error, Some(Version::V2)))
+        .unwrap_or_default()
+}
+
+/// Answer every call in a JSON-RPC request with the same error, without dispatching any of them.
+pub(crate) fn reject_request(request: Request, error: Error) -> Option<Response> {
+    let reject_call = |call: Call| match call {
+        Call::MethodCall(call) => Some(Output::from(Err(error.clone()), call.id, call.jsonrpc)),
+        Call::Notification(_) => None,
+        Call::Invalid { id } => Some(Output::from(Err(error.clone()), id, Some(Version::V2))),
+    };
+
+    match request {
+        Request::Single(call) => reject_call(call).map(Response::Single),
+        Request::Batch(calls) => {
+            let outputs = calls
+                .into_iter()
+                .filter_map(reject_call)
+                .collect::<Vec<_>>();
+            if outputs.is_empty() {
+                None
+            } else {
+                Some(Response::Batch(outputs))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_core::serde_json;
+
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    fn net(net: &str) -> IpNet {
+        net.parse().unwrap()
+    }
+
+    fn request(json: &str) -> Request {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn allow_and_deny_lists_apply_to_known_peers() {
+        let limits = ConnectionLimits {
+            allowed_networks: vec![net("10.0.0.0/8")],
+            denied_networks: vec![net("10.0.0.0/24")],
+            ..Default::default()
+        };
+
+        assert!(limits.is_allowed(Some(ip("10.1.0.1"))));
+        assert!(!limits.is_allowed(Some(ip("10.0.0.1"))));
+        assert!(!limits.is_allowed(Some(ip("192.168.0.1"))));
+    }
+
+    #[test]
+    fn unknown_peers_are_rejected_by_any_list() {
+        let denying = ConnectionLimits {
+            denied_networks: vec![net("10.0.0.0/8")],
+            ..Default::default()
+        };
+        let allowing = ConnectionLimits {
+            allowed_networks: vec![net("10.0.0.0/8")],
+            ..Default::default()
+        };
+
+        assert!(ConnectionLimits::default().is_allowed(None));
+        assert!(!denying.is_allowed(None));
+        assert!(!allowing.is_allowed(None));
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_without_trusted_proxies() {
+        let limits = ConnectionLimits::default();
+
+        assert_eq!(
+            limits.client_ip(ip("10.0.0.1"), Some("1.2.3.4")),
+            Some(ip("10.0.0.1"))
+        );
+    }
+
+    #[test]
+    fn forwarded_for_is_read_from_the_right_past_trusted_proxies() {
+        let limits = ConnectionLimits {
+            trusted_proxies: vec![net("10.0.0.0/8")],
+            ..Default::default()
+        };
+
+        // Clients can prepend whatever they want, but not what the proxies append
+        assert_eq!(
+            limits.client_ip(ip("10.0.0.1"), Some("6.6.6.6, 1.2.3.4, 10.0.0.2")),
+            Some(ip("1.2.3.4"))
+        );
+        // Peers that are not trusted proxies cannot forward for anyone
+        assert_eq!(
+            limits.client_ip(ip("1.2.3.4"), Some("6.6.6.6")),
+            Some(ip("1.2.3.4"))
+        );
+        assert_eq!(limits.client_ip(ip("10.0.0.1"), None), Some(ip("10.0.0.1")));
+        assert_eq!(
+            limits.client_ip(ip("10.0.0.1"), Some("1.2.3.4, garbage")),
+            None
+        );
+    }
+
+    #[test]
+    fn tracker_enforces_limits_until_permits_are_dropped() {
+        let tracker = ConnectionTracker::new(ConnectionLimits {
+            max_connections: Some(2),
+            max_connections_per_ip: Some(1),
+            denied_networks: vec![net("10.0.0.0/8")],
+            ..Default::default()
+        });
+
+        let first = tracker.admit(ip("1.1.1.1")).unwrap();
+        assert_eq!(
+            tracker.admit(ip("1.1.1.1")).unwrap_err(),
+            ConnectionRejection::TooManyConnectionsFromPeer
+        );
+        assert_eq!(
+            tracker.admit(ip("10.0.0.1")).unwrap_err(),
+            ConnectionRejection::AddressNotAllowed
+        );
+        assert_eq!(
+            tracker.admit_peer(None).unwrap_err(),
+            ConnectionRejection::AddressNotAllowed
+        );
+        let _second = tracker.admit(ip("2.2.2.2")).unwrap();
+        assert_eq!(
+            tracker.admit(ip("3.3.3.3")).unwrap_err(),
+            ConnectionRejection::TooManyConnections
+        );
+
+        drop(first);
+        assert!(tracker.admit(ip("1.1.1.1")).is_ok());
+    }
+
+    #[test]
+    fn unknown_peers_only_count_towards_the_total() {
+        let tracker = ConnectionTracker::new(ConnectionLimits {
+            max_connections: Some(2),
+            max_connections_per_ip: Some(1),
+            ..Default::default()
+        });
+
+        let _first = tracker.admit_peer(None).unwrap();
+        let _second = tracker.admit_peer(None).unwrap();
+        assert_eq!(
+            tracker.admit_peer(None).unwrap_err(),
+            ConnectionRejection::TooManyConnections
+        );
+    }
+
+    #[test]
+    fn request_limits_check_batch_size_and_depth() {
+        let limits = RequestLimits {
+            max_batch_size: Some(2),
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        let call = r#"{"jsonrpc":"2.0","id":1,"method":"a","params":[[1]]}"#;
+
+        assert!(limits.check(&request(call)).is_ok());
+        assert!(limits
+            .check(&request(&format!("[{},{}]", call, call)))
+            .is_ok());
+
+        let error = limits
+            .check(&request(&format!("[{},{},{}]", call, call, call)))
+            .unwrap_err();
+        assert_eq!(error.code, ErrorCode::ServerError(REQUEST_LIMIT_ERROR_CODE));
+        assert_eq!(error.data.unwrap()["limit"], "max_batch_size");
+
+        let error = limits
+            .check(&request(
+                r#"{"jsonrpc":"2.0","id":1,"method":"a","params":{"a":[{"b":1}]}}"#,
+            ))
+            .unwrap_err();
+        assert_eq!(error.data.unwrap()["limit"], "max_depth");
+    }
+
+    #[test]
+    fn rejected_requests_only_answer_method_calls() {
+        let error = Error::internal_error();
+        let batch =
+            request(r#"[{"jsonrpc":"2.0","id":1,"method":"a"},{"jsonrpc":"2.0","method":"b"}]"#);
+
+        match reject_request(batch, error.clone()) {
+            Some(Response::Batch(outputs)) => assert_eq!(outputs.len(), 1),
+            response => panic!("unexpected response {:?}", response),
+        }
+        let notification = request(r#"{"jsonrpc":"2.0","method":"b"}"#);
+        assert_eq!(reject_request(notification, error), None);
+    }
+}
@@ -1,17 +1,116 @@
 use std::sync::{Arc, Mutex};
 
-use crate::handler::Handler;
+#[cfg(any(feature = "http", feature = "tcp", feature = "ws"))]
+use jsonrpc_core::MetaIoHandler;
+
+use crate::{access_log::AccessLog, handler::Handler, health::Readiness};
 
 /// A JSON-RPC over HTTP transport built around the `jsonrpc_http_server` library.
 #[cfg(feature = "http")]
 pub mod http;
-/// A JSON-RPC over TCP transport built around the `jsonrpc_tcp_server` library.
+/// Limits on the connections and requests that transports accept.
+pub mod limits;
+/// A JSON-RPC over TCP transport built on top of `tokio`.
 #[cfg(feature = "tcp")]
 pub mod tcp;
-/// A JSON-RPC over WebSockets transport built around the `jsonrpc_ws_server` library.
+/// A JSON-RPC over WebSockets transport built on top of the `ws` library.
 #[cfg(feature = "ws")]
 pub mod ws;
 
+#[cfg(all(
+    any(feature = "http", feature = "tcp", feature = "ws"),
+    feature = "metrics"
+))]
+pub(crate) use crate::metrics::RequestMetrics;
+#[cfg(all(
+    any(feature = "http", feature = "tcp", feature = "ws"),
+    feature = "tracing"
+))]
+use crate::spans::RequestSpans;
+#[cfg(all(
+    any(feature = "http", feature = "tcp", feature = "ws"),
+    feature = "opentelemetry"
+))]
+use crate::trace_context::RequestTraces;
+#[cfg(all(
+    any(feature = "http", feature = "tcp", feature = "ws"),
+    not(feature = "metrics")
+))]
+pub(crate) use jsonrpc_core::NoopMiddleware as RequestMetrics;
+#[cfg(all(
+    any(feature = "http", feature = "tcp", feature = "ws"),
+    not(feature = "tracing")
+))]
+use jsonrpc_core::NoopMiddleware as RequestSpans;
+#[cfg(all(
+    any(feature = "http", feature = "tcp", feature = "ws"),
+    not(feature = "opentelemetry")
+))]
+use jsonrpc_core::NoopMiddleware as RequestTraces;
+
+/// The JSON-RPC middleware that the built-in transports put in front of the methods of the server.
+///
+/// Remote trace contexts are only propagated if the `opentelemetry` feature is enabled, and spans
+/// and metrics are only recorded if the `tracing` and `metrics` features are enabled.
+#[cfg(any(feature = "http", feature = "tcp", feature = "ws"))]
+pub(crate) type TransportMiddleware = (
+    limits::RequestLimits,
+    RequestTraces,
+    RequestSpans,
+    RequestMetrics,
+);
+
+/// Some JSON-RPC middleware behind the access log of the server, which goes first so that it also
+/// records the calls that the rest of the middleware rejects.
+#[cfg(any(feature = "http", feature = "tcp", feature = "ws"))]
+pub(crate) type LoggedMiddleware<X> = (AccessLog, X);
+
+/// Take a snapshot of the IO handler of a server, wrapped in the middleware that the built-in
+/// transports use.
+#[cfg(any(feature = "tcp", feature = "ws"))]
+pub(crate) fn transport_io_handler<H>(
+    handler: &Arc<Mutex<H>>,
+    access_log: AccessLog,
+    request_limits: limits::RequestLimits,
+    request_metrics: RequestMetrics,
+) -> MetaIoHandler<H::Metadata, LoggedMiddleware<TransportMiddleware>>
+where
+    H: Handler,
+{
+    io_handler_with_middleware(
+        handler,
+        (
+            access_log,
+            transport_middleware(request_limits, request_metrics),
+        ),
+    )
+}
+
+/// Build the middleware that the built-in transports use.
+#[cfg(any(feature = "http", feature = "tcp", feature = "ws"))]
+pub(crate) fn transport_middleware(
+    request_limits: limits::RequestLimits,
+    request_metrics: RequestMetrics,
+) -> TransportMiddleware {
+    (request_limits, RequestTraces, RequestSpans, request_metrics)
+}
+
+/// Take a snapshot of the IO handler of a server, wrapped in some middleware.
+#[cfg(any(feature = "http", feature = "tcp", feature = "ws"))]
+pub(crate) fn io_handler_with_middleware<H, X>(
+    handler: &Arc<Mutex<H>>,
+    middleware: X,
+) -> MetaIoHandler<H::Metadata, X>
+where
+    H: Handler,
+    X: jsonrpc_core::Middleware<H::Metadata>,
+{
+    let mut io_handler = MetaIoHandler::with_middleware(middleware);
+    io_handler.extend_with((*handler.lock().unwrap()).as_meta_io_handler());
+
+    io_handler
+}
+
 /// Enumerates all the different errors that a `Transport` can get into.
 #[derive(Debug)]
 pub enum TransportError {
@@ -74,4 +173,47 @@ where
     /// Stopping a transport is assumed to also stop any underlying listeners and sockets, and to
     /// completely halt the processing of further JSON-RPC messages.
     fn stop(&mut self) -> Result<(), TransportError>;
+    /// Get access to whether the server that the transport belongs to is ready to take requests.
+    ///
+    /// This takes effect the next time that the handler is set. Transports that do not report
+    /// readiness can simply ignore this.
+    fn set_readiness(&mut self, _readiness: Readiness) {}
+    /// Get access to the access log of the server that the transport belongs to, for recording
+    /// every call that the transport handles.
+    ///
+    /// This takes effect the next time that the handler is set. Transports that do not keep access
+    /// logs can simply ignore this.
+    fn set_access_log(&mut self, _access_log: AccessLog) {}
+    /// Start recording metrics about the calls and connections handled by this transport.
+    ///
+    /// This takes effect the next time that the handler is set. Transports that do not support
+    /// metrics can simply ignore this.
+    #[cfg(feature = "metrics")]
+    fn set_metrics(&mut self, _metrics: crate::metrics::Metrics) {}
+}
+
+/// Find an address on localhost that a transport can be bound to.
+#[cfg(all(test, any(feature = "http", feature = "tcp", feature = "ws")))]
+pub(crate) fn free_address() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Start a server on a transport, with a single `echo` method that returns its parameters.
+#[cfg(all(test, any(feature = "http", feature = "tcp", feature = "ws")))]
+pub(crate) fn echo_server<T>(transport: T) -> crate::server::WittyMultiServer
+where
+    T: Transport<jsonrpc_pubsub::PubSubHandler<crate::handler::Session>> + 'static,
+{
+    use crate::server::Server;
+
+    let mut server = crate::server::WittyMultiServer::new();
+    server.add_transport(transport);
+    server.add_method("echo", |params: jsonrpc_core::Params| {
+        futures::future::ready(params.parse::<jsonrpc_core::Value>())
+    });
+    server.start().unwrap();
+
+    server
 }
@@ -1,28 +1,102 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    borrow::Cow,
+    net::SocketAddr,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::Duration,
+};
 
-use jsonrpc_core::NoopMiddleware;
-use jsonrpc_tcp_server::{RequestContext, Server, ServerBuilder};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{self, Either},
+    stream::FuturesUnordered,
+    StreamExt,
+};
+use jsonrpc_core::{serde_json, Error, MetaIoHandler, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream},
+    time::Instant,
+};
 
 use crate::{
-    handler::Handler,
-    transports::{Transport, TransportError},
+    access_log::AccessLog,
+    handler::{ConnectionMetadata, Handler},
+    trace_context::{TraceContext, TRACEPARENT, TRACESTATE},
+    transports::{
+        limits::{error_response, ConnectionLimits, ConnectionTracker, RequestLimits},
+        transport_io_handler, LoggedMiddleware, RequestMetrics, Transport, TransportError,
+        TransportMiddleware,
+    },
 };
 
+/// How many requests of a single connection are handled at the same time, unless set otherwise.
+pub const DEFAULT_MAX_REQUESTS_IN_FLIGHT: usize = 16;
+
 /// Settings needed for constructing a `TcpTransport`.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct TcpTransportSettings {
     /// An IP or address to bind the TCP listener to.
     pub address: String,
+    /// Restrictions on which peers can connect, and how many connections can be open at once.
+    ///
+    /// Connections that break these limits are sent a single JSON-RPC error and closed right away.
+    pub connection_limits: ConnectionLimits,
+    /// Limits on the size and shape of every request, where each line is a separate request.
+    pub request_limits: RequestLimits,
+    /// How long a connection can go without sending a request before it is considered dead and
+    /// closed, which also drops all of its subscriptions.
+    ///
+    /// Clients that only listen to subscription notifications need to send some request every now
+    /// and then to stay connected. If `None`, connections are never closed for being idle.
+    pub idle_timeout: Option<Duration>,
+    /// How many requests of a single connection can be handled at the same time, which is
+    /// `DEFAULT_MAX_REQUESTS_IN_FLIGHT` by default.
+    ///
+    /// Once that many are in flight, no more requests are read from the connection until one of
+    /// them is answered. Responses are written in the order in which they complete, which is not
+    /// necessarily that of the requests.
+    pub max_requests_in_flight: Option<NonZeroUsize>,
+    /// The version of the API for every call received through this transport, if the server has
+    /// versions of its API.
+    ///
+    /// If `None`, calls to versioned methods are dispatched to the default version of the server.
+    pub api_version: Option<String>,
+}
+
+/// The settings that every connection accepted by a `TcpTransport` is served with.
+#[derive(Clone, Debug)]
+struct ConnectionSettings {
+    request_limits: RequestLimits,
+    idle_timeout: Option<Duration>,
+    max_in_flight: usize,
+    api_version: Option<String>,
+}
+
+/// A running TCP listener, along with the means for stopping it.
+struct Server {
+    stop: oneshot::Sender<()>,
+    thread: JoinHandle<()>,
 }
 
-/// A JSON-RPC over TCP transport built around the `jsonrpc_tcp_server` library.
+/// A JSON-RPC over TCP transport built on top of `tokio`.
+///
+/// Requests and responses are delimited by line breaks.
+///
+/// As there are no headers in this transport, the W3C trace context of a request can be sent in
+/// optional `traceparent` and `tracestate` fields of the JSON-RPC envelope, next to `jsonrpc`,
+/// `method`, etc. Those fields are removed before the request is handled.
 pub struct TcpTransport<H>
 where
     H: Handler,
 {
     settings: TcpTransportSettings,
-    server_builder: Option<ServerBuilder<H::Metadata, NoopMiddleware>>,
+    io_handler: Option<Arc<MetaIoHandler<H::Metadata, LoggedMiddleware<TransportMiddleware>>>>,
     server: Option<Server>,
+    request_metrics: RequestMetrics,
+    access_log: AccessLog,
+    connection_tracker: ConnectionTracker,
 }
 
 impl<H> TcpTransport<H>
@@ -31,10 +105,15 @@ where
 {
     /// Create a new instance of this transport.
     pub fn new(settings: TcpTransportSettings) -> Self {
+        let connection_tracker = ConnectionTracker::new(settings.connection_limits.clone());
+
         Self {
             settings,
-            server_builder: None,
+            io_handler: None,
             server: None,
+            request_metrics: Default::default(),
+            access_log: Default::default(),
+            connection_tracker,
         }
     }
 }
@@ -53,12 +132,13 @@ where
     }
 
     fn set_handler(&mut self, handler: Arc<Mutex<H>>) -> Result<(), TransportError> {
-        let handler = (*handler.lock().unwrap()).as_meta_io_handler();
-        let server_builder =
-            ServerBuilder::new(handler).session_meta_extractor(|context: &RequestContext| {
-                H::metadata_from_sender(context.sender.clone())
-            });
-        self.server_builder = Some(server_builder);
+        let io_handler = transport_io_handler(
+            &handler,
+            self.access_log.clone(),
+            self.settings.request_limits,
+            self.request_metrics.clone(),
+        );
+        self.io_handler = Some(Arc::new(io_handler));
 
         Ok(())
     }
@@ -68,12 +148,47 @@ where
             return Ok(());
         }
 
-        let builder = self
-            .server_builder
-            .take()
-            .ok_or(TransportError::NoHandler)?;
-        let socket_addr = self.settings.address.parse::<std::net::SocketAddr>()?;
-        self.server = Some(builder.start(&socket_addr)?);
+        let io_handler = self.io_handler.clone().ok_or(TransportError::NoHandler)?;
+        let socket_addr = self.settings.address.parse::<SocketAddr>()?;
+        let listener = std::net::TcpListener::bind(socket_addr)?;
+        listener.set_nonblocking(true)?;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()?;
+        let listener = {
+            let _guard = runtime.enter();
+            TcpListener::from_std(listener)?
+        };
+        let connection_tracker = self.connection_tracker.clone();
+        let connection_settings = ConnectionSettings {
+            request_limits: self.settings.request_limits,
+            idle_timeout: self.settings.idle_timeout,
+            max_in_flight: self
+                .settings
+                .max_requests_in_flight
+                .map_or(DEFAULT_MAX_REQUESTS_IN_FLIGHT, NonZeroUsize::get),
+            api_version: self.settings.api_version.clone(),
+        };
+        #[cfg(feature = "metrics")]
+        let metrics = self.request_metrics.0.clone();
+        let (stop, stopped) = oneshot::channel();
+
+        let thread = std::thread::Builder::new()
+            .name(format!("witty-jsonrpc-tcp-{}", socket_addr))
+            .spawn(move || {
+                let accept = accept_connections::<H>(
+                    listener,
+                    io_handler,
+                    connection_tracker,
+                    connection_settings,
+                    #[cfg(feature = "metrics")]
+                    metrics,
+                );
+                runtime.block_on(future::select(Box::pin(accept), stopped));
+            })?;
+        self.server = Some(Server { stop, thread });
 
         Ok(())
     }
@@ -82,10 +197,436 @@ where
         match self.server.take() {
             None => Ok(()),
             Some(server) => {
-                server.close();
+                // The runtime is dropped along with the thread, closing the listener and all connections
+                let _ = server.stop.send(());
+                server.thread.join().map_err(|_| TransportError::Unknown)?;
 
                 Ok(())
             }
         }
     }
+
+    fn set_access_log(&mut self, access_log: AccessLog) {
+        self.access_log = access_log;
+    }
+
+    #[cfg(feature = "metrics")]
+    fn set_metrics(&mut self, metrics: crate::metrics::Metrics) {
+        self.request_metrics = RequestMetrics(Some(metrics));
+    }
+}
+
+/// Accept incoming connections forever, and spawn a task for serving each of them.
+async fn accept_connections<H>(
+    listener: TcpListener,
+    io_handler: Arc<MetaIoHandler<H::Metadata, LoggedMiddleware<TransportMiddleware>>>,
+    connection_tracker: ConnectionTracker,
+    connection_settings: ConnectionSettings,
+    #[cfg(feature = "metrics")] metrics: Option<crate::metrics::Metrics>,
+) where
+    H: Handler + 'static,
+{
+    loop {
+        let (mut stream, peer_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                log::warn!("Error accepting TCP connection: {}", error);
+                continue;
+            }
+        };
+
+        match connection_tracker.admit(peer_addr.ip()) {
+            Ok(permit) => {
+                let io_handler = io_handler.clone();
+                let connection_settings = connection_settings.clone();
+                #[cfg(feature = "metrics")]
+                let active_connection = metrics.as_ref().map(|m| m.track_connection("tcp"));
+                tokio::spawn(async move {
+                    serve_connection::<H>(stream, peer_addr, io_handler, connection_settings).await;
+                    drop(permit);
+                    #[cfg(feature = "metrics")]
+                    drop(active_connection);
+                });
+            }
+            Err(rejection) => {
+                log::warn!("Rejecting TCP connection from {}: {}", peer_addr, rejection);
+                let message = error_response(Error::from(rejection));
+                tokio::spawn(async move {
+                    let _ = stream.write_all(format!("{}\n", message).as_bytes()).await;
+                });
+            }
+        }
+    }
+}
+
+/// Process the requests coming through a single connection, and write back the responses along
+/// with any subscription notifications.
+async fn serve_connection<H>(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    io_handler: Arc<MetaIoHandler<H::Metadata, LoggedMiddleware<TransportMiddleware>>>,
+    settings: ConnectionSettings,
+) where
+    H: Handler,
+{
+    let (read_half, mut write_half) = stream.into_split();
+    let (sender, mut receiver) = mpsc::unbounded::<String>();
+    let mut meta = H::metadata_from_sender(sender.clone());
+    meta.set_peer_addr(peer_addr);
+    meta.set_transport("tcp");
+    if let Some(api_version) = &settings.api_version {
+        meta.set_api_version(api_version);
+    }
+
+    let reader = read_requests(
+        BufReader::new(read_half),
+        &io_handler,
+        meta,
+        &sender,
+        &settings,
+    );
+    let writer = async {
+        while let Some(message) = receiver.next().await {
+            write_half
+                .write_all(format!("{}\n", message).as_bytes())
+                .await?;
+        }
+
+        Ok::<(), std::io::Error>(())
+    };
+
+    let last_message = match future::select(Box::pin(reader), Box::pin(writer)).await {
+        Either::Left((last_message, _)) => last_message,
+        Either::Right(_) => return,
+    };
+
+    // Flush whatever was queued before the peer stopped sending requests
+    let mut pending = Vec::new();
+    while let Ok(message) = receiver.try_recv() {
+        pending.push(message);
+    }
+    for message in pending.into_iter().chain(last_message) {
+        if write_half
+            .write_all(format!("{}\n", message).as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Read and handle line delimited requests until the peer closes the connection.
+///
+/// Requests are handled concurrently, up to `settings.max_in_flight` at a time, and no more
+/// requests are read while that many are in flight. The idle timeout counts from the last request
+/// received, whether or not calls are still in flight.
+///
+/// If a line exceeds the maximum request size, reading stops and the error response that needs to
+/// be sent to the peer is returned once the calls in flight are answered.
+async fn read_requests<M>(
+    mut reader: BufReader<OwnedReadHalf>,
+    io_handler: &MetaIoHandler<M, LoggedMiddleware<TransportMiddleware>>,
+    meta: M,
+    sender: &mpsc::UnboundedSender<String>,
+    settings: &ConnectionSettings,
+) -> Option<String>
+where
+    M: jsonrpc_pubsub::PubSubMetadata + ConnectionMetadata,
+{
+    let ConnectionSettings {
+        request_limits,
+        idle_timeout,
+        max_in_flight,
+        ..
+    } = *settings;
+    let mut line = Vec::new();
+    let mut in_flight = FuturesUnordered::new();
+    let mut idle_deadline = idle_timeout.map(|idle_timeout| Instant::now() + idle_timeout);
+
+    let last_message = loop {
+        if in_flight.len() >= max_in_flight {
+            // Stop reading until some call completes
+            if let Some(Some(response)) = in_flight.next().await {
+                if sender.unbounded_send(response).is_err() {
+                    return None;
+                }
+            }
+            continue;
+        }
+
+        let read = read_line(&mut reader, &mut line, request_limits, idle_deadline);
+        let read = if in_flight.is_empty() {
+            read.await
+        } else {
+            // Keep reading while waiting for the calls in flight, which resumes where it left off
+            // if a call completes first
+            match future::select(Box::pin(read), in_flight.next()).await {
+                Either::Left((read, _)) => read,
+                Either::Right((response, _)) => {
+                    if let Some(Some(response)) = response {
+                        if sender.unbounded_send(response).is_err() {
+                            return None;
+                        }
+                    }
+                    continue;
+                }
+            }
+        };
+
+        match read {
+            None => {
+                log::debug!("Closing idle TCP connection");
+
+                return None;
+            }
+            Some(Ok(0)) if line.is_empty() => break None,
+            Some(Ok(_)) => {}
+            Some(Err(error)) => {
+                log::debug!("Error reading from TCP connection: {}", error);
+
+                return None;
+            }
+        }
+
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        if matches!(request_limits.max_request_size, Some(max) if line.len() > max) {
+            log::warn!("Closing TCP connection after receiving an oversized request");
+
+            break Some(error_response(request_limits.too_large_error()));
+        }
+        idle_deadline = idle_timeout.map(|idle_timeout| Instant::now() + idle_timeout);
+
+        let request = String::from_utf8_lossy(&line).trim().to_string();
+        line.clear();
+        if request.is_empty() {
+            continue;
+        }
+
+        let (request, trace_context) = extract_trace_context(&request);
+        let mut meta = meta.clone();
+        if let Some(trace_context) = trace_context {
+            meta.set_trace_context(trace_context);
+        }
+        in_flight.push(io_handler.handle_request(&request, meta));
+    };
+
+    // Answer the calls in flight before the connection is closed
+    while let Some(response) = in_flight.next().await {
+        if let Some(response) = response {
+            if sender.unbounded_send(response).is_err() {
+                return None;
+            }
+        }
+    }
+
+    last_message
+}
+
+/// Read the next line of a connection into `line`, giving up if the peer is idle past `deadline`.
+///
+/// Never buffer more than one byte past the maximum request size, so that oversized lines are
+/// detected without having to read them whole. Reading can be cancelled and then resumed, as the
+/// bytes read so far are kept in `line`.
+async fn read_line(
+    reader: &mut BufReader<OwnedReadHalf>,
+    line: &mut Vec<u8>,
+    request_limits: RequestLimits,
+    deadline: Option<Instant>,
+) -> Option<std::io::Result<usize>> {
+    let read = async {
+        match request_limits.max_request_size {
+            Some(max) => {
+                let max = u64::try_from(max.saturating_add(1).saturating_sub(line.len()))
+                    .unwrap_or(u64::MAX);
+                reader.take(max).read_until(b'\n', line).await
+            }
+            None => reader.read_until(b'\n', line).await,
+        }
+    };
+
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, read).await.ok(),
+        None => Some(read.await),
+    }
+}
+
+/// Remove the trace context fields from the envelope of a request, be it a single call or a batch.
+///
+/// The whole batch is assigned the first valid trace context found in any of its calls.
+fn extract_trace_context(request: &str) -> (Cow<'_, str>, Option<TraceContext>) {
+    // Avoid parsing the request twice when there is nothing to extract
+    if !request.contains(TRACEPARENT) {
+        return (Cow::Borrowed(request), None);
+    }
+    let mut value = match serde_json::from_str::<Value>(request) {
+        Ok(value) => value,
+        Err(_) => return (Cow::Borrowed(request), None),
+    };
+
+    let calls = match &mut value {
+        Value::Array(calls) => calls.iter_mut().collect(),
+        call => vec![call],
+    };
+    let mut trace_context = None;
+    for call in calls {
+        if let Value::Object(call) = call {
+            let traceparent = call.remove(TRACEPARENT);
+            let tracestate = call.remove(TRACESTATE);
+            if trace_context.is_none() {
+                trace_context =
+                    traceparent
+                        .as_ref()
+                        .and_then(Value::as_str)
+                        .and_then(|traceparent| {
+                            TraceContext::parse(
+                                traceparent,
+                                tracestate.as_ref().and_then(Value::as_str),
+                            )
+                        });
+            }
+        }
+    }
+
+    (Cow::Owned(value.to_string()), trace_context)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Write};
+
+    use jsonrpc_core::serde_json::json;
+
+    use super::*;
+    use crate::{
+        access_log::{AccessLogEntry, AccessLogOutcome},
+        server::Server as _,
+        transports::{echo_server, free_address, limits::REQUEST_LIMIT_ERROR_CODE},
+    };
+
+    #[test]
+    fn oversized_lines_are_answered_with_an_error_before_closing() {
+        let address = free_address();
+        let mut server = echo_server(TcpTransport::new(TcpTransportSettings {
+            address: address.clone(),
+            request_limits: RequestLimits {
+                max_request_size: Some(128),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+
+        let mut stream = std::net::TcpStream::connect(&address).unwrap();
+        let call =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "echo", "params": ["x".repeat(200)] });
+        writeln!(stream, "{}", call).unwrap();
+        let mut lines = std::io::BufReader::new(stream).lines();
+        let response = serde_json::from_str::<Value>(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], json!(REQUEST_LIMIT_ERROR_CODE));
+        assert!(lines.next().is_none());
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn calls_are_handled_concurrently_up_to_the_maximum_in_flight() {
+        // Tell which of a slow call and a fast one sent right after it is answered first
+        let first_answered = |max_requests_in_flight| {
+            let address = free_address();
+            let mut server = echo_server(TcpTransport::new(TcpTransportSettings {
+                address: address.clone(),
+                max_requests_in_flight: NonZeroUsize::new(max_requests_in_flight),
+                ..Default::default()
+            }));
+            server.add_method("sleep", |_| async {
+                futures_timer::Delay::new(Duration::from_millis(300)).await;
+
+                Ok(Value::Null)
+            });
+
+            let mut stream = std::net::TcpStream::connect(&address).unwrap();
+            let sleep = json!({ "jsonrpc": "2.0", "id": 1, "method": "sleep" });
+            let echo = json!({ "jsonrpc": "2.0", "id": 2, "method": "echo", "params": [] });
+            writeln!(stream, "{}\n{}", sleep, echo).unwrap();
+            let mut lines = std::io::BufReader::new(stream).lines();
+            let first = serde_json::from_str::<Value>(&lines.next().unwrap().unwrap()).unwrap();
+            let second = serde_json::from_str::<Value>(&lines.next().unwrap().unwrap()).unwrap();
+            server.stop().unwrap();
+            assert_ne!(first["id"], second["id"]);
+
+            first["id"].clone()
+        };
+
+        assert_eq!(first_answered(2), json!(2));
+        assert_eq!(first_answered(1), json!(1));
+    }
+
+    #[test]
+    fn connections_are_closed_once_idle_for_too_long() {
+        let idle_timeout = Duration::from_millis(300);
+        let address = free_address();
+        let mut server = echo_server(TcpTransport::new(TcpTransportSettings {
+            address: address.clone(),
+            idle_timeout: Some(idle_timeout),
+            ..Default::default()
+        }));
+
+        let mut stream = std::net::TcpStream::connect(&address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut lines = std::io::BufReader::new(stream.try_clone().unwrap()).lines();
+        // Every request resets the timeout, so the connection outlives it while in use
+        let mut last_request = Instant::now();
+        for id in 0..4 {
+            std::thread::sleep(idle_timeout / 3);
+            let call = json!({ "jsonrpc": "2.0", "id": id, "method": "echo", "params": [] });
+            last_request = Instant::now();
+            writeln!(stream, "{}", call).unwrap();
+            let response = serde_json::from_str::<Value>(&lines.next().unwrap().unwrap()).unwrap();
+            assert_eq!(response["id"], json!(id));
+        }
+
+        assert!(lines.next().is_none());
+        assert!(last_request.elapsed() >= idle_timeout);
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn calls_rejected_by_the_request_limits_are_recorded_in_the_access_log() {
+        let address = free_address();
+        let mut server = echo_server(TcpTransport::new(TcpTransportSettings {
+            address: address.clone(),
+            request_limits: RequestLimits {
+                max_depth: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = Mutex::new(sender);
+        server.add_access_log_sink(move |entry: &AccessLogEntry| {
+            sender.lock().unwrap().send(entry.clone()).ok();
+        });
+
+        let mut stream = std::net::TcpStream::connect(&address).unwrap();
+        let call = json!({ "jsonrpc": "2.0", "id": 1, "method": "echo", "params": [[1]] });
+        writeln!(stream, "{}", call).unwrap();
+        let mut lines = std::io::BufReader::new(stream).lines();
+        let response = serde_json::from_str::<Value>(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(response["error"]["code"], json!(REQUEST_LIMIT_ERROR_CODE));
+
+        let entry = receiver.recv().unwrap();
+        assert_eq!(entry.method, "echo");
+        assert_eq!(entry.transport, Some("tcp"));
+        assert!(matches!(
+            entry.outcome,
+            AccessLogOutcome::Error { code, .. } if code == REQUEST_LIMIT_ERROR_CODE
+        ));
+
+        server.stop().unwrap();
+    }
 }
@@ -1,27 +1,118 @@
-use std::sync::{Arc, Mutex};
+use std::{
+    cmp,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
-pub use jsonrpc_ws_server::Error;
-use jsonrpc_ws_server::{jsonrpc_core::NoopMiddleware, RequestContext, Server, ServerBuilder};
+use futures::{
+    channel::{mpsc, oneshot},
+    future::{self, FutureExt, Shared},
+    StreamExt,
+};
+use jsonrpc_core::MetaIoHandler;
+use jsonrpc_server_utils::{hosts, Pattern};
+use jsonrpc_ws_server::ws;
+pub use jsonrpc_ws_server::{Error, Host, Origin};
 
 use crate::{
-    handler::Handler,
-    transports::{Transport, TransportError},
+    access_log::AccessLog,
+    handler::{ConnectionMetadata, Handler},
+    trace_context::{TraceContext, TRACEPARENT, TRACESTATE},
+    transports::{
+        limits::{
+            error_response, ConnectionLimits, ConnectionPermit, ConnectionTracker, RequestLimits,
+        },
+        transport_io_handler, LoggedMiddleware, RequestMetrics, Transport, TransportError,
+        TransportMiddleware,
+    },
+    versioning::{ApiVersionSelection, API_VERSION_HEADER},
 };
 
+/// The maximum number of connections that can be open at the same time if no limit is set.
+const DEFAULT_MAX_CONNECTIONS: usize = 100;
+/// The maximum size in bytes of a single message if no limit is set.
+const DEFAULT_MAX_PAYLOAD: usize = 5 * 1024 * 1024;
+/// How many bytes above the maximum size a message can have and still be answered with an error,
+/// instead of having the connection dropped as soon as it starts arriving.
+const OVERSIZE_ALLOWANCE: usize = 64 * 1024;
+/// The token of the timeout that triggers sending a ping.
+const PING: ws::util::Token = ws::util::Token(1);
+/// The token of the timeout that triggers checking whether a ping was answered.
+const PONG_DEADLINE: ws::util::Token = ws::util::Token(2);
+
 /// Settings needed for constructing a `WsTransport`.
+#[derive(Debug, Default)]
 pub struct WsTransportSettings {
     /// An IP or address to bind the WebSockets listener to.
     pub address: String,
+    /// Restrictions on which peers can connect, and how many connections can be open at once.
+    ///
+    /// These are checked right after the handshake, and connections that break them are closed
+    /// with a policy violation.
+    pub connection_limits: ConnectionLimits,
+    /// Limits on the size and shape of every message.
+    ///
+    /// Messages above the maximum size are answered with a JSON-RPC error, and then the connection
+    /// is closed with a `Size` close code. Messages that are more than 64 KiB above the maximum
+    /// size make the connection be dropped right away, without any error.
+    pub request_limits: RequestLimits,
+    /// The origins that browsers are allowed to open connections from, which protects browser
+    /// clients from cross-site WebSocket hijacking.
+    ///
+    /// If `None`, connections are accepted regardless of their `Origin` header. Connections
+    /// without an `Origin` header, i.e. those not opened by browsers, are always accepted.
+    pub allowed_origins: Option<Vec<Origin>>,
+    /// The values of the `Host` header that connections are accepted with, besides the address
+    /// that the listener is bound to.
+    ///
+    /// If `None`, connections are accepted regardless of their `Host` header. Otherwise,
+    /// connections without a `Host` header are rejected.
+    pub allowed_hosts: Option<Vec<Host>>,
+    /// The maximum number of connections that can be open at the same time.
+    ///
+    /// Defaults to the maximum in `connection_limits` if set, or else to 100.
+    pub max_connections: Option<usize>,
+    /// The maximum size in bytes of a single message.
+    ///
+    /// Defaults to the maximum request size in `request_limits` if set, or else to 5 MiB.
+    pub max_payload: Option<usize>,
+    /// How often to send a ping to every connection, e.g. for keeping idle connections open
+    /// through proxies and load balancers.
+    ///
+    /// If `None`, no pings are sent.
+    pub ping_interval: Option<Duration>,
+    /// How long to wait for a peer to answer a ping, or to send anything else, before considering
+    /// the connection dead and closing it, which also drops all of its subscriptions.
+    ///
+    /// This only applies if `ping_interval` is set. If `None`, connections are never considered
+    /// dead for not answering pings.
+    pub pong_timeout: Option<Duration>,
+    /// How connections select the version of the API that their calls are dispatched to during
+    /// the handshake, if the server has versions of its API.
+    pub api_version: ApiVersionSelection,
 }
 
-/// A JSON-RPC over WebSockets transport built around the `jsonrpc_ws_server` library.
+/// A running WebSockets listener, along with the means for stopping it.
+struct Server {
+    broadcaster: ws::Sender,
+    listener_thread: JoinHandle<()>,
+    stop_executor: oneshot::Sender<()>,
+    executor_thread: JoinHandle<()>,
+}
+
+/// A JSON-RPC over WebSockets transport built on top of the `ws` library that powers
+/// `jsonrpc_ws_server`.
 pub struct WsTransport<H>
 where
     H: Handler,
 {
     settings: WsTransportSettings,
-    server_builder: Option<ServerBuilder<H::Metadata, NoopMiddleware>>,
+    io_handler: Option<Arc<MetaIoHandler<H::Metadata, LoggedMiddleware<TransportMiddleware>>>>,
     server: Option<Server>,
+    request_metrics: RequestMetrics,
+    access_log: AccessLog,
 }
 
 impl<H> WsTransport<H>
@@ -32,15 +123,51 @@ where
     pub fn new(settings: WsTransportSettings) -> Self {
         Self {
             settings,
-            server_builder: None,
+            io_handler: None,
             server: None,
+            request_metrics: Default::default(),
+            access_log: Default::default(),
         }
     }
+
+    /// The maximum size in bytes of a single message.
+    fn max_payload(&self) -> usize {
+        self.settings
+            .max_payload
+            .or(self.settings.request_limits.max_request_size)
+            .unwrap_or(DEFAULT_MAX_PAYLOAD)
+    }
+
+    /// Derive the settings of the underlying server from those of the transport.
+    fn server_settings(&self) -> ws::Settings {
+        let max_payload = self.max_payload();
+        // Messages slightly above the maximum size still get through, so that they can be answered
+        // with an error before closing the connection
+        let max_message_size = max_payload.saturating_add(OVERSIZE_ALLOWANCE);
+        let mut settings = ws::Settings::default();
+        settings.max_connections = self
+            .settings
+            .max_connections
+            .or(self.settings.connection_limits.max_connections)
+            .unwrap_or(DEFAULT_MAX_CONNECTIONS);
+        // Do not accept messages too far above the maximum size, be they fragmented or not
+        settings.max_fragment_size = max_message_size;
+        settings.max_total_fragments_size = max_message_size;
+        // Do not grow the buffer of non-final fragments, as that would allow for DoS attacks
+        settings.fragments_grow = false;
+        // Accept only handshakes that use the GET method, and frames that are masked
+        settings.method_strict = true;
+        settings.masking_strict = true;
+        settings.shutdown_on_interrupt = false;
+        settings.fragments_capacity = cmp::max(1, max_payload / settings.fragment_size);
+
+        settings
+    }
 }
 
 impl<H> Transport<H> for WsTransport<H>
 where
-    H: Handler,
+    H: Handler + Send + 'static,
     H::Metadata: Default,
 {
     fn requires_reset(&self) -> bool {
@@ -52,12 +179,13 @@ where
     }
 
     fn set_handler(&mut self, handler: Arc<Mutex<H>>) -> Result<(), TransportError> {
-        let handler = (*handler.lock().unwrap()).as_meta_io_handler();
-        let server_builder =
-            ServerBuilder::new(handler).session_meta_extractor(|context: &RequestContext| {
-                H::metadata_from_sender(context.sender())
-            });
-        self.server_builder = Some(server_builder);
+        let io_handler = transport_io_handler(
+            &handler,
+            self.access_log.clone(),
+            self.settings.request_limits,
+            self.request_metrics.clone(),
+        );
+        self.io_handler = Some(Arc::new(io_handler));
 
         Ok(())
     }
@@ -67,12 +195,62 @@ where
             return Ok(());
         }
 
-        let builder = self
-            .server_builder
-            .take()
-            .ok_or(TransportError::NoHandler)?;
-        let socket_addr = self.settings.address.parse::<std::net::SocketAddr>()?;
-        self.server = Some(builder.start(&socket_addr)?);
+        let io_handler = self.io_handler.clone().ok_or(TransportError::NoHandler)?;
+        let socket_addr = self.settings.address.parse::<SocketAddr>()?;
+
+        // Method calls are executed on a runtime of their own, so that they do not block the
+        // event loop of the listener
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let executor = runtime.handle().clone();
+        let (stop_executor, executor_stopped) = oneshot::channel::<()>();
+        let executor_thread = std::thread::Builder::new()
+            .name(format!("witty-jsonrpc-ws-executor-{}", socket_addr))
+            .spawn(move || {
+                runtime.block_on(executor_stopped).ok();
+            })?;
+
+        let factory = ConnectionFactory::<H> {
+            shared: Arc::new(SharedSettings {
+                io_handler,
+                executor,
+                max_payload: self.max_payload(),
+                request_limits: self.settings.request_limits,
+                connection_tracker: ConnectionTracker::new(self.settings.connection_limits.clone()),
+                allowed_origins: self.settings.allowed_origins.clone(),
+                allowed_hosts: hosts::update(self.settings.allowed_hosts.clone(), &socket_addr),
+                ping_interval: self.settings.ping_interval,
+                pong_timeout: self.settings.pong_timeout,
+                api_version: self.settings.api_version.clone(),
+                #[cfg(feature = "metrics")]
+                metrics: self.request_metrics.0.clone(),
+            }),
+        };
+        let listener = match bind_listener(self.server_settings(), factory, socket_addr) {
+            Ok(listener) => listener,
+            Err(error) => {
+                stop_executor.send(()).ok();
+                executor_thread.join().ok();
+
+                return Err(error);
+            }
+        };
+        let broadcaster = listener.broadcaster();
+        let listener_thread = std::thread::Builder::new()
+            .name(format!("witty-jsonrpc-ws-{}", socket_addr))
+            .spawn(move || {
+                if let Err(error) = listener.run() {
+                    log::error!("Error while running WebSockets server: {}", error);
+                }
+            })?;
+
+        self.server = Some(Server {
+            broadcaster,
+            listener_thread,
+            stop_executor,
+            executor_thread,
+        });
 
         Ok(())
     }
@@ -81,10 +259,646 @@ where
         match self.server.take() {
             None => Ok(()),
             Some(server) => {
-                server.close();
+                // Dropping the runtime aborts the method calls that are still executing
+                server.broadcaster.shutdown().ok();
+                server
+                    .listener_thread
+                    .join()
+                    .map_err(|_| TransportError::Unknown)?;
+                server.stop_executor.send(()).ok();
+                server
+                    .executor_thread
+                    .join()
+                    .map_err(|_| TransportError::Unknown)?;
 
                 Ok(())
             }
         }
     }
+
+    fn set_access_log(&mut self, access_log: AccessLog) {
+        self.access_log = access_log;
+    }
+
+    #[cfg(feature = "metrics")]
+    fn set_metrics(&mut self, metrics: crate::metrics::Metrics) {
+        self.request_metrics = RequestMetrics(Some(metrics));
+    }
+}
+
+/// Everything that the connections of a listener share.
+struct SharedSettings<M>
+where
+    M: jsonrpc_pubsub::PubSubMetadata + ConnectionMetadata,
+{
+    io_handler: Arc<MetaIoHandler<M, LoggedMiddleware<TransportMiddleware>>>,
+    executor: tokio::runtime::Handle,
+    max_payload: usize,
+    request_limits: RequestLimits,
+    connection_tracker: ConnectionTracker,
+    allowed_origins: Option<Vec<Origin>>,
+    allowed_hosts: Option<Vec<Host>>,
+    ping_interval: Option<Duration>,
+    pong_timeout: Option<Duration>,
+    api_version: ApiVersionSelection,
+    #[cfg(feature = "metrics")]
+    metrics: Option<crate::metrics::Metrics>,
+}
+
+/// Creates a `Connection` for every socket that the listener accepts.
+struct ConnectionFactory<H>
+where
+    H: Handler,
+{
+    shared: Arc<SharedSettings<H::Metadata>>,
+}
+
+impl<H> ws::Factory for ConnectionFactory<H>
+where
+    H: Handler,
+{
+    type Handler = Connection<H>;
+
+    fn connection_made(&mut self, out: ws::Sender) -> Self::Handler {
+        let (closing, closed) = oneshot::channel();
+
+        Connection {
+            out,
+            shared: self.shared.clone(),
+            meta: None,
+            _permit: None,
+            awaiting_pong_since: None,
+            _closing: closing,
+            closed: closed.shared(),
+            #[cfg(feature = "metrics")]
+            _active_connection: self
+                .shared
+                .metrics
+                .as_ref()
+                .map(|metrics| metrics.track_connection("ws")),
+        }
+    }
+}
+
+/// The state of a single WebSockets connection.
+struct Connection<H>
+where
+    H: Handler,
+{
+    out: ws::Sender,
+    shared: Arc<SharedSettings<H::Metadata>>,
+    /// The metadata of the session, which is only available after the handshake.
+    meta: Option<H::Metadata>,
+    /// Keeps the connection counting towards the connection limits, once admitted.
+    _permit: Option<ConnectionPermit>,
+    /// When the oldest ping that the peer has not answered yet was sent, if any.
+    awaiting_pong_since: Option<Instant>,
+    /// Dropped along with the connection, which resolves `closed`.
+    _closing: oneshot::Sender<()>,
+    /// Resolves once the connection is gone, for aborting the tasks that serve it.
+    closed: Shared<oneshot::Receiver<()>>,
+    #[cfg(feature = "metrics")]
+    _active_connection: Option<crate::metrics::ActiveConnection>,
+}
+
+impl<H> Connection<H>
+where
+    H: Handler,
+{
+    /// Spawn a task on the executor, which is aborted if the connection goes away.
+    fn spawn<F>(&self, task: F)
+    where
+        F: futures::Future<Output = ()> + Send + 'static,
+    {
+        let closed = self.closed.clone();
+        self.shared
+            .executor
+            .spawn(future::select(Box::pin(task), closed));
+    }
+
+    /// Decide whether to accept a handshake, returning the response to reject it with if not.
+    fn check_handshake(&self, request: &ws::Request) -> Option<ws::Response> {
+        let origin = header(request, "origin");
+        if !origin_is_allowed(&self.shared.allowed_origins, origin) {
+            log::warn!(
+                "Rejecting WebSockets handshake: origin {:?} not allowed",
+                origin
+            );
+
+            return Some(forbidden("Connection Origin has been rejected."));
+        }
+
+        let host = header(request, "host");
+        if !hosts::is_host_valid(host, &self.shared.allowed_hosts) {
+            log::warn!(
+                "Rejecting WebSockets handshake: host {:?} not allowed",
+                host
+            );
+
+            return Some(forbidden("Connection Host has been rejected."));
+        }
+
+        None
+    }
+
+    /// Schedule the next ping, if pings are enabled.
+    fn schedule_ping(&self) {
+        if let Some(interval) = self.shared.ping_interval {
+            if let Err(error) = self.out.timeout(duration_to_ms(interval), PING) {
+                log::warn!("Error scheduling a WebSockets ping: {}", error);
+            }
+        }
+    }
+
+    /// Schedule checking whether the ping that was just sent gets answered in time, if there is a
+    /// timeout for that.
+    fn schedule_pong_deadline(&self) {
+        if let Some(timeout) = self.shared.pong_timeout {
+            if let Err(error) = self.out.timeout(duration_to_ms(timeout), PONG_DEADLINE) {
+                log::warn!("Error scheduling a WebSockets pong deadline: {}", error);
+            }
+        }
+    }
+}
+
+impl<H> ws::Handler for Connection<H>
+where
+    H: Handler,
+{
+    fn on_request(&mut self, request: &ws::Request) -> ws::Result<ws::Response> {
+        if let Some(response) = self.check_handshake(request) {
+            return Ok(response);
+        }
+
+        // Forward the messages sent through the session, e.g. subscription notifications
+        let (sender, mut receiver) = mpsc::unbounded::<String>();
+        let out = self.out.clone();
+        self.spawn(async move {
+            while let Some(message) = receiver.next().await {
+                if let Err(error) = out.send(message) {
+                    log::warn!("Error sending a WebSockets message: {}", error);
+
+                    return;
+                }
+            }
+        });
+
+        let mut meta = H::metadata_from_sender(sender);
+        meta.set_transport("ws");
+        if let Some(trace_context) = header(request, TRACEPARENT)
+            .and_then(|traceparent| TraceContext::parse(traceparent, header(request, TRACESTATE)))
+        {
+            meta.set_trace_context(trace_context);
+        }
+        if let Some(version) = self.shared.api_version.select(
+            Some(request.resource()),
+            header(request, API_VERSION_HEADER),
+        ) {
+            meta.set_api_version(version);
+        }
+        self.meta = Some(meta);
+
+        let mut response = ws::Response::from_request(request)?;
+        if let Some(protocol) = request
+            .protocols()
+            .ok()
+            .and_then(|protocols| protocols.first().map(|protocol| String::from(*protocol)))
+        {
+            response.set_protocol(&protocol);
+        }
+
+        Ok(response)
+    }
+
+    fn on_open(&mut self, handshake: ws::Handshake) -> ws::Result<()> {
+        let limits = self.shared.connection_tracker.limits();
+        let peer = handshake.peer_addr.and_then(|peer_addr| {
+            limits.client_ip(
+                peer_addr.ip(),
+                header(&handshake.request, "x-forwarded-for"),
+            )
+        });
+        match self.shared.connection_tracker.admit_peer(peer) {
+            Ok(permit) => {
+                self._permit = Some(permit);
+                if let (Some(meta), Some(ip)) = (&mut self.meta, peer) {
+                    // The port is only known if the peer is not behind a proxy
+                    let peer_addr = handshake
+                        .peer_addr
+                        .filter(|peer_addr| peer_addr.ip() == ip)
+                        .unwrap_or_else(|| SocketAddr::new(ip, 0));
+                    meta.set_peer_addr(peer_addr);
+                }
+            }
+            Err(rejection) => {
+                log::warn!(
+                    "Rejecting WebSockets connection from {:?}: {}",
+                    peer,
+                    rejection
+                );
+                // Do not handle any message that arrives before the connection is closed
+                self.meta = None;
+
+                return self
+                    .out
+                    .close_with_reason(ws::CloseCode::Policy, rejection.to_string());
+            }
+        }
+
+        self.schedule_ping();
+
+        Ok(())
+    }
+
+    fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+        let meta = match &self.meta {
+            Some(meta) => meta.clone(),
+            None => return Ok(()),
+        };
+        if message.len() > self.shared.max_payload {
+            log::warn!("Closing WebSockets connection after receiving an oversized message");
+            let error = RequestLimits {
+                max_request_size: Some(self.shared.max_payload),
+                ..self.shared.request_limits
+            }
+            .too_large_error();
+            // Do not handle any message that arrives before the connection is closed
+            self.meta = None;
+            self.out.send(error_response(error.clone()))?;
+
+            return self
+                .out
+                .close_with_reason(ws::CloseCode::Size, error.message);
+        }
+        let out = self.out.clone();
+        let response = self
+            .shared
+            .io_handler
+            .handle_request(message.as_text()?, meta);
+
+        self.spawn(async move {
+            if let Some(response) = response.await {
+                if let Err(error) = out.send(response) {
+                    log::warn!("Error sending a WebSockets response: {}", error);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    fn on_timeout(&mut self, event: ws::util::Token) -> ws::Result<()> {
+        match event {
+            PING => {
+                self.out.ping(Vec::new())?;
+                self.awaiting_pong_since.get_or_insert_with(Instant::now);
+                self.schedule_ping();
+                self.schedule_pong_deadline();
+            }
+            // Any frame counts as an answer, so the peer is only considered dead if it has sent
+            // nothing at all since the oldest unanswered ping, and that was long enough ago
+            PONG_DEADLINE => {
+                let timeout = self.shared.pong_timeout.unwrap_or_default();
+                let expired = self
+                    .awaiting_pong_since
+                    .is_some_and(|since| since.elapsed() >= timeout);
+                if expired {
+                    log::debug!("Closing WebSockets connection that did not answer a ping");
+
+                    // IO errors make the underlying server drop the connection right away,
+                    // instead of waiting for a closing handshake that will never come
+                    return Err(ws::Error::from(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "the peer did not answer a ping in time",
+                    )));
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn on_frame(&mut self, frame: ws::Frame) -> ws::Result<Option<ws::Frame>> {
+        self.awaiting_pong_since = None;
+
+        // Keep the default checks of the underlying server
+        if frame.has_rsv1() || frame.has_rsv2() || frame.has_rsv3() {
+            return Err(ws::Error::new(
+                ws::ErrorKind::Protocol,
+                "Encountered frame with reserved bits set.",
+            ));
+        }
+
+        Ok(Some(frame))
+    }
+}
+
+/// Create the underlying server and bind it to an address.
+fn bind_listener<H>(
+    settings: ws::Settings,
+    factory: ConnectionFactory<H>,
+    socket_addr: SocketAddr,
+) -> Result<ws::WebSocket<ConnectionFactory<H>>, TransportError>
+where
+    H: Handler,
+{
+    let listener = ws::Builder::new()
+        .with_settings(settings)
+        .build(factory)
+        .map_err(Error::from)?;
+
+    Ok(listener.bind(socket_addr).map_err(Error::from)?)
+}
+
+/// Read a header of a handshake request as a string.
+fn header<'a>(request: &'a ws::Request, name: &str) -> Option<&'a str> {
+    request
+        .header(name)
+        .and_then(|value| std::str::from_utf8(value).ok())
+}
+
+/// Tell whether the `Origin` header of a handshake matches any of the allowed origins.
+///
+/// Handshakes without an `Origin` header are always allowed, as they do not come from browsers,
+/// and so are all origins if there is no list of allowed ones.
+fn origin_is_allowed(allowed: &Option<Vec<Origin>>, origin: Option<&str>) -> bool {
+    match (origin, allowed) {
+        (None, _) | (_, None) => true,
+        (Some(origin), Some(allowed)) => allowed.iter().any(|pattern| pattern.matches(origin)),
+    }
+}
+
+/// Build the response for rejecting a handshake.
+fn forbidden(message: &str) -> ws::Response {
+    let mut response = ws::Response::new(403, "Forbidden", format!("{}\n", message).into_bytes());
+    response
+        .headers_mut()
+        .push((String::from("Connection"), b"close".to_vec()));
+
+    response
+}
+
+/// Convert a duration into the milliseconds that the underlying server expects, which must not be
+/// zero.
+fn duration_to_ms(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis())
+        .unwrap_or(u64::MAX)
+        .max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_core::{
+        serde_json::{self, json},
+        Params, Value,
+    };
+    use jsonrpc_pubsub::{Subscriber, SubscriptionId};
+
+    use super::*;
+    use crate::{
+        handler::Session,
+        server::Server as _,
+        transports::{echo_server, free_address, limits::REQUEST_LIMIT_ERROR_CODE},
+    };
+
+    /// A client that sends a single message, and keeps track of what it gets until it is closed.
+    struct Client {
+        out: ws::Sender,
+        message: String,
+        received: Vec<String>,
+        closed: std::sync::mpsc::Sender<(Vec<String>, ws::CloseCode)>,
+    }
+
+    impl ws::Handler for Client {
+        fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+            self.out.send(self.message.clone())
+        }
+
+        fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+            self.received.push(message.into_text()?);
+
+            Ok(())
+        }
+
+        fn on_close(&mut self, code: ws::CloseCode, _reason: &str) {
+            self.closed
+                .send((std::mem::take(&mut self.received), code))
+                .ok();
+        }
+    }
+
+    /// A client that sends a single message, and then ignores the pings of the server.
+    struct Unresponsive {
+        out: ws::Sender,
+        message: String,
+        closed: std::sync::mpsc::Sender<()>,
+    }
+
+    impl ws::Handler for Unresponsive {
+        fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+            self.out.send(self.message.clone())
+        }
+
+        fn on_frame(&mut self, frame: ws::Frame) -> ws::Result<Option<ws::Frame>> {
+            // Dropping pings keeps the client from answering them
+            if frame.opcode() == ws::OpCode::Ping {
+                Ok(None)
+            } else {
+                Ok(Some(frame))
+            }
+        }
+
+        fn on_close(&mut self, _code: ws::CloseCode, _reason: &str) {
+            self.closed.send(()).ok();
+        }
+    }
+
+    /// Send a handshake with some extra headers, returning the connection along with the status
+    /// line of the response, which is empty if the connection is closed without one.
+    fn handshake(address: &str, headers: &[&str]) -> (std::net::TcpStream, String) {
+        use std::io::{Read, Write};
+
+        let mut stream = std::net::TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let headers = headers
+            .iter()
+            .map(|header| format!("{}\r\n", header))
+            .collect::<String>();
+        write!(
+            stream,
+            "GET / HTTP/1.1\r\n{}Upgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            headers
+        )
+        // Refused connections can be closed before the handshake is even sent
+        .ok();
+
+        let mut head = Vec::new();
+        let mut byte = [0];
+        while !head.ends_with(b"\r\n\r\n") && matches!(stream.read(&mut byte), Ok(1)) {
+            head.push(byte[0]);
+        }
+        let status = String::from_utf8_lossy(&head)
+            .lines()
+            .next()
+            .map(String::from)
+            .unwrap_or_default();
+
+        (stream, status)
+    }
+
+    #[test]
+    fn handshakes_from_origins_that_are_not_allowed_are_rejected() {
+        let address = free_address();
+        let mut server = echo_server(WsTransport::new(WsTransportSettings {
+            address: address.clone(),
+            allowed_origins: Some(vec![Origin::from("https://allowed.example")]),
+            ..Default::default()
+        }));
+        let host = format!("Host: {}", address);
+        let status = |headers: &[&str]| handshake(&address, headers).1;
+
+        assert!(status(&[&host, "Origin: https://other.example"]).contains("403"));
+        assert!(status(&[&host, "Origin: https://allowed.example"]).contains("101"));
+        // Clients other than browsers send no origin at all
+        assert!(status(&[&host]).contains("101"));
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn handshakes_with_a_host_that_is_not_allowed_are_rejected() {
+        let address = free_address();
+        let mut server = echo_server(WsTransport::new(WsTransportSettings {
+            address: address.clone(),
+            allowed_hosts: Some(vec![Host::from("rpc.example")]),
+            ..Default::default()
+        }));
+        let status = |headers: &[&str]| handshake(&address, headers).1;
+
+        assert!(status(&["Host: rebound.example"]).contains("403"));
+        assert!(status(&[]).contains("403"));
+        assert!(status(&["Host: rpc.example"]).contains("101"));
+        assert!(status(&[&format!("Host: {}", address)]).contains("101"));
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn connections_past_the_maximum_are_refused() {
+        let address = free_address();
+        let mut server = echo_server(WsTransport::new(WsTransportSettings {
+            address: address.clone(),
+            max_connections: Some(1),
+            ..Default::default()
+        }));
+        let host = format!("Host: {}", address);
+
+        let (first, status) = handshake(&address, &[&host]);
+        assert!(status.contains("101"));
+        let (_, status) = handshake(&address, &[&host]);
+        assert!(!status.contains("101"));
+
+        // Room is made for new connections once the first one is closed
+        drop(first);
+        let accepted = (0..50).any(|_| {
+            std::thread::sleep(Duration::from_millis(20));
+
+            handshake(&address, &[&host]).1.contains("101")
+        });
+        assert!(accepted);
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn connections_that_do_not_answer_pings_are_reaped_along_with_their_subscriptions() {
+        let address = free_address();
+        let mut server = crate::server::WittyMultiServer::new();
+        server.add_transport(WsTransport::new(WsTransportSettings {
+            address: address.clone(),
+            ping_interval: Some(Duration::from_millis(50)),
+            pong_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        }));
+        let (unsubscribed, unsubscriptions) = std::sync::mpsc::channel();
+        server.add_subscription(
+            "ticks",
+            (
+                "subscribeTicks",
+                |_: Params, _: Session, subscriber: Subscriber| {
+                    subscriber.assign_id(SubscriptionId::Number(1)).ok();
+                },
+            ),
+            (
+                "unsubscribeTicks",
+                move |id: SubscriptionId, meta: Option<Session>| {
+                    // Subscriptions of dropped sessions are cancelled without a session
+                    unsubscribed.send((id, meta.is_none())).ok();
+
+                    futures::future::ok(Value::Bool(true))
+                },
+            ),
+        );
+        server.start().unwrap();
+
+        let (closed, closes) = std::sync::mpsc::channel();
+        let url = format!("ws://{}", address);
+        let started = Instant::now();
+        std::thread::spawn(move || {
+            ws::connect(url, |out| Unresponsive {
+                out,
+                message: json!({ "jsonrpc": "2.0", "id": 1, "method": "subscribeTicks" })
+                    .to_string(),
+                closed: closed.clone(),
+            })
+            .ok();
+        });
+
+        closes.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert_eq!(
+            unsubscriptions
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap(),
+            (SubscriptionId::Number(1), true)
+        );
+
+        server.stop().unwrap();
+    }
+
+    #[test]
+    fn oversized_messages_are_answered_with_an_error_before_closing() {
+        let address = free_address();
+        let mut server = echo_server(WsTransport::new(WsTransportSettings {
+            address: address.clone(),
+            request_limits: RequestLimits {
+                max_request_size: Some(128),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+
+        let call =
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "echo", "params": ["x".repeat(200)] });
+        let (closed, received) = std::sync::mpsc::channel();
+        ws::connect(format!("ws://{}", address), |out| Client {
+            out,
+            message: call.to_string(),
+            received: Vec::new(),
+            closed: closed.clone(),
+        })
+        .unwrap();
+
+        let (messages, code) = received.recv().unwrap();
+        assert_eq!(code, ws::CloseCode::Size);
+        assert_eq!(messages.len(), 1);
+        let response = serde_json::from_str::<Value>(&messages[0]).unwrap();
+        assert_eq!(response["error"]["code"], json!(REQUEST_LIMIT_ERROR_CODE));
+
+        server.stop().unwrap();
+    }
 }
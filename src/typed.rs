@@ -0,0 +1,226 @@
+use std::future::Future;
+
+use jsonrpc_core::{serde_json, BoxFuture, Error, Params, RpcMethodSimple, Value};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::metadata::{ContentDescriptor, DescribeType};
+
+/// Wrap a JSON-RPC method that takes and returns Rust types, so that it can be added to a server.
+///
+/// The parameters of every call are deserialized into `P` before calling the method, and the
+/// result is serialized back into JSON. Positional parameters are deserialized as a sequence
+/// (e.g. into a tuple or a struct, in field order), and named parameters as a map (e.g. into a
+/// struct, by field name). Calls without parameters can only be deserialized into types that
+/// accept `null`, such as `()` or `Option<T>`.
+///
+/// Calls whose parameters cannot be deserialized into `P` are answered with a standard invalid
+/// params error (`-32602`), without calling the method.
+pub fn typed_method<P, R, F, X>(method: F) -> impl RpcMethodSimple
+where
+    P: DeserializeOwned + Send + 'static,
+    R: Serialize + Send + 'static,
+    F: Fn(P) -> X + Send + Sync + 'static,
+    X: Future<Output = Result<R, Error>> + Send + 'static,
+{
+    move |params: Params| {
+        let execution = params.parse::<P>().map(&method);
+
+        async move { result_to_value(execution?.await?) }
+    }
+}
+
+/// Deserialize the parameters of a call into a tuple with one element per parameter, given the
+/// names of the parameters in order.
+///
+/// Positional parameters are matched by position, and missing trailing ones are taken as `null`,
+/// so that they can be omitted if their type is an `Option<T>`. Named parameters are matched by
+/// name, and missing ones are also taken as `null`, but unknown names are rejected.
+///
+/// If `names` is empty, `T` is expected to be `()` and the call must have no parameters at all.
+pub fn parse_params<T>(params: Params, names: &[&str]) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let value = match params {
+        _ if names.is_empty() => {
+            params.expect_no_params()?;
+
+            Value::Null
+        }
+        Params::None => Value::Array(vec![Value::Null; names.len()]),
+        Params::Array(mut values) => {
+            if values.len() < names.len() {
+                values.resize(names.len(), Value::Null);
+            }
+
+            Value::Array(values)
+        }
+        Params::Map(mut map) => {
+            let values = names
+                .iter()
+                .map(|name| map.remove(*name).unwrap_or(Value::Null))
+                .collect();
+            if let Some(unknown) = map.keys().next() {
+                return Err(Error::invalid_params(format!(
+                    "Invalid params: unknown parameter `{}`.",
+                    unknown
+                )));
+            }
+
+            Value::Array(values)
+        }
+    };
+
+    serde_json::from_value(value)
+        .map_err(|error| Error::invalid_params(format!("Invalid params: {}.", error)))
+}
+
+/// Serialize the result of a JSON-RPC method, answering with an internal error if that fails.
+pub fn result_to_value<R>(result: R) -> Result<Value, Error>
+where
+    R: Serialize,
+{
+    serde_json::to_value(result).map_err(|error| {
+        log::error!(
+            "Error serializing the result of a JSON-RPC method: {}",
+            error
+        );
+
+        Error::internal_error()
+    })
+}
+
+/// Describe the result of a JSON-RPC method exposed through the `rpc` attribute macro with the
+/// `schema` option, out of the type that the method returns.
+pub fn describe_result<T, O>() -> ContentDescriptor
+where
+    O: MethodOutput<T>,
+    T: DescribeType,
+{
+    ContentDescriptor::new("result", T::schema())
+}
+
+/// The possible return types of the JSON-RPC methods exposed through the `rpc` attribute macro,
+/// i.e. either a `jsonrpc_core::Result<T>` or a `jsonrpc_core::BoxFuture` of one.
+pub trait MethodOutput<T> {
+    /// Turn the returned value into a future, if it is not one already.
+    fn into_future(self) -> BoxFuture<Result<T, Error>>;
+}
+
+impl<T> MethodOutput<T> for Result<T, Error>
+where
+    T: Send + 'static,
+{
+    fn into_future(self) -> BoxFuture<Result<T, Error>> {
+        Box::pin(futures::future::ready(self))
+    }
+}
+
+impl<T> MethodOutput<T> for BoxFuture<Result<T, Error>>
+where
+    T: Send + 'static,
+{
+    fn into_future(self) -> BoxFuture<Result<T, Error>> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpc_core::{serde_json::json, ErrorCode};
+
+    use super::*;
+
+    fn params(value: Value) -> Params {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Call a method with some parameters, waiting for its result.
+    fn call<M>(method: &M, value: Value) -> Result<Value, Error>
+    where
+        M: RpcMethodSimple,
+    {
+        futures::executor::block_on(method.call(params(value)))
+    }
+
+    #[test]
+    fn params_and_results_are_converted_from_and_into_json() {
+        let method = typed_method(|(a, b): (i64, i64)| futures::future::ok(a + b));
+
+        assert_eq!(call(&method, json!([1, 2])), Ok(json!(3)));
+    }
+
+    #[test]
+    fn methods_without_params_accept_calls_without_params() {
+        let method = typed_method(|(): ()| futures::future::ok("pong"));
+
+        assert_eq!(
+            futures::executor::block_on(method.call(Params::None)),
+            Ok(json!("pong"))
+        );
+    }
+
+    #[test]
+    fn invalid_params_are_rejected_without_calling_the_method() {
+        let method = typed_method(|_: (i64,)| -> futures::future::Ready<Result<(), Error>> {
+            unreachable!("the method must not be called")
+        });
+
+        let error = call(&method, json!(["one"])).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+    }
+
+    #[test]
+    fn errors_of_the_method_are_passed_through() {
+        let method = typed_method(|(): ()| futures::future::err::<(), _>(Error::invalid_request()));
+
+        let error = futures::executor::block_on(method.call(Params::None)).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn positional_params_can_omit_trailing_options() {
+        let parsed: (String, Option<bool>) =
+            parse_params(params(json!(["a", true])), &["name", "shout"]).unwrap();
+        assert_eq!(parsed, (String::from("a"), Some(true)));
+
+        let parsed: (String, Option<bool>) =
+            parse_params(params(json!(["a"])), &["name", "shout"]).unwrap();
+        assert_eq!(parsed, (String::from("a"), None));
+
+        let parsed: (Option<String>,) = parse_params(Params::None, &["name"]).unwrap();
+        assert_eq!(parsed, (None,));
+    }
+
+    #[test]
+    fn named_params_are_matched_by_name() {
+        let parsed: (String, Option<bool>) = parse_params(
+            params(json!({ "shout": false, "name": "a" })),
+            &["name", "shout"],
+        )
+        .unwrap();
+        assert_eq!(parsed, (String::from("a"), Some(false)));
+
+        let error =
+            parse_params::<(String,)>(params(json!({ "name": "a", "other": 1 })), &["name"])
+                .unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert!(error.message.contains("`other`"));
+    }
+
+    #[test]
+    fn missing_required_params_are_rejected() {
+        let error = parse_params::<(String,)>(params(json!([])), &["name"]).unwrap_err();
+
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+    }
+
+    #[test]
+    fn methods_without_params_reject_any() {
+        assert!(parse_params::<()>(Params::None, &[]).is_ok());
+        assert!(parse_params::<()>(params(json!([])), &[]).is_ok());
+        assert!(parse_params::<()>(params(json!([1])), &[]).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use jsonrpc_core::{serde_json::json, Error, ErrorCode, RpcMethodSimple};
+use jsonrpc_pubsub::{SubscribeRpcMethod, UnsubscribeRpcMethod};
+
+use crate::{
+    deprecation::{alias_metadata, call_alias, Deprecation, DeprecationLog},
+    handler::Handler,
+    metadata::MethodMetadata,
+    server::{BoxedMethod, Server},
+};
+
+/// The JSON-RPC error code used for rejecting calls to versioned methods that ask for a version of
+/// the API that the server does not have, or that do not ask for any when there is no default.
+pub const UNSUPPORTED_API_VERSION_ERROR_CODE: i64 = -32010;
+
+/// The header that HTTP requests and WebSockets handshakes can ask for a version of the API with,
+/// if enabled.
+pub const API_VERSION_HEADER: &str = "x-api-version";
+
+/// How a transport tells which version of the API the calls it receives are meant for.
+///
+/// The first of these that applies wins: the path, the `X-Api-Version` header, and then the fixed
+/// `version`. Calls that do not ask for any version are dispatched to the default version of the
+/// server, if any.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ApiVersionSelection {
+    /// The version of the API for every call received through the transport, unless it asks for
+    /// another one.
+    pub version: Option<String>,
+    /// Whether the first segment of the path of HTTP requests and WebSockets handshakes selects
+    /// the version, e.g. `/v2` or `/v2/rpc`. Requests to the root path do not select any.
+    ///
+    /// Over WebSockets, the version is selected once for the whole connection.
+    pub from_path: bool,
+    /// Whether the `X-Api-Version` header of HTTP requests and WebSockets handshakes selects the
+    /// version.
+    pub from_header: bool,
+}
+
+impl ApiVersionSelection {
+    /// Tell which version of the API a request asks for, out of its path and its `X-Api-Version`
+    /// header.
+    pub fn select<'a>(&'a self, path: Option<&'a str>, header: Option<&'a str>) -> Option<&'a str> {
+        let from_path = path
+            .filter(|_| self.from_path)
+            .and_then(|path| path.trim_start_matches('/').split(['/', '?']).next())
+            .filter(|version| !version.is_empty());
+        let from_header = header
+            .filter(|_| self.from_header)
+            .map(str::trim)
+            .filter(|version| !version.is_empty());
+
+        from_path.or(from_header).or(self.version.as_deref())
+    }
+}
+
+/// The methods of a single version of the API, as mounted from a `Module`.
+pub(crate) struct ApiVersion {
+    methods: Arc<RwLock<HashMap<String, BoxedMethod>>>,
+    metadata: BTreeMap<String, MethodMetadata>,
+    /// The targets of the aliases of this version, by alias.
+    aliases: HashMap<String, String>,
+    deprecation_log: DeprecationLog,
+}
+
+impl ApiVersion {
+    /// Create a version without any methods, whose deprecated aliases are reported to
+    /// `deprecation_log`.
+    pub(crate) fn new(deprecation_log: DeprecationLog) -> Self {
+        Self {
+            methods: Default::default(),
+            metadata: BTreeMap::new(),
+            aliases: HashMap::new(),
+            deprecation_log,
+        }
+    }
+
+    /// Get a method of this version by its name.
+    fn method(&self, name: &str) -> Option<BoxedMethod> {
+        self.methods.read().unwrap().get(name).cloned()
+    }
+}
+
+impl<H> Server<H> for ApiVersion
+where
+    H: Handler,
+{
+    type Error = ();
+
+    fn start(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn add_method<F>(&mut self, name: &str, method: F)
+    where
+        F: RpcMethodSimple,
+    {
+        let method = Arc::new(method);
+
+        self.metadata
+            .entry(String::from(name))
+            .or_insert_with(|| MethodMetadata::new(name));
+        self.methods.write().unwrap().insert(
+            String::from(name),
+            Arc::new(move |params| {
+                let method = method.clone();
+
+                Box::pin(async move { method.call(params).await })
+            }),
+        );
+    }
+
+    fn add_subscription<F, G>(
+        &mut self,
+        notification: &str,
+        _subscribe: (&str, F),
+        _unsubscribe: (&str, G),
+    ) where
+        F: SubscribeRpcMethod<H::Metadata>,
+        G: UnsubscribeRpcMethod<H::Metadata>,
+    {
+        // Modules with subscriptions are rejected before getting here
+        log::error!(
+            "Ignoring subscription {}, as subscriptions cannot be part of an API version",
+            notification
+        );
+    }
+
+    fn describe_api(&self) -> Vec<String> {
+        self.methods.read().unwrap().keys().cloned().collect()
+    }
+
+    fn describe_api_detailed(&self) -> Vec<MethodMetadata> {
+        self.metadata.values().cloned().collect()
+    }
+
+    fn add_alias(&mut self, alias: &str, target: &str, deprecation: Option<Deprecation>) {
+        let methods = self.methods.clone();
+        let deprecation_log = self.deprecation_log.clone();
+        let alias_name = String::from(alias);
+        let target_name = String::from(target);
+        let metadata = alias_metadata(
+            alias,
+            target,
+            self.metadata.get(target),
+            deprecation.as_ref(),
+        );
+
+        Server::<H>::add_method(self, alias, move |params| {
+            let method = methods.read().unwrap().get(&target_name).cloned();
+
+            call_alias(
+                &alias_name,
+                &target_name,
+                deprecation.as_ref(),
+                &deprecation_log,
+                method.map(|method| method(params)),
+            )
+        });
+        self.metadata.insert(String::from(alias), metadata);
+        self.aliases
+            .insert(String::from(alias), String::from(target));
+    }
+
+    fn set_method_metadata(&mut self, metadata: MethodMetadata) {
+        self.metadata.insert(metadata.name.clone(), metadata);
+    }
+}
+
+/// All the versions of the API of a server, along with the one that calls are dispatched to if
+/// they do not ask for any.
+#[derive(Default)]
+pub(crate) struct ApiVersions {
+    default: Option<String>,
+    versions: BTreeMap<String, ApiVersion>,
+}
+
+impl ApiVersions {
+    /// Set the version that calls are dispatched to if they do not ask for any.
+    pub(crate) fn set_default(&mut self, version: Option<&str>) {
+        self.default = version.map(String::from);
+    }
+
+    /// Add a version, returning the one it replaces, if any.
+    pub(crate) fn insert(&mut self, version: &str, methods: ApiVersion) -> Option<ApiVersion> {
+        self.versions.insert(String::from(version), methods)
+    }
+
+    /// Tell whether a method is part of any version, as opposed to being shared by all of them.
+    pub(crate) fn is_versioned(&self, name: &str) -> bool {
+        self.versions
+            .values()
+            .any(|version| version.method(name).is_some())
+    }
+
+    /// Get the version of a method that a call asks for, or else the default one.
+    pub(crate) fn method(&self, name: &str, requested: Option<&str>) -> Result<BoxedMethod, Error> {
+        self.resolve(requested)?
+            .method(name)
+            .ok_or_else(Error::method_not_found)
+    }
+
+    /// Get the target of an alias in the version that a call asks for, or else in the default one.
+    pub(crate) fn alias_target(&self, name: &str, requested: Option<&str>) -> Option<String> {
+        self.resolve(requested).ok()?.aliases.get(name).cloned()
+    }
+
+    /// Describe all the methods that a call can reach if it asks for `requested`, i.e. those that
+    /// are shared by all versions plus those of the selected version.
+    ///
+    /// If no version can be selected, every method is described just like the server does.
+    pub(crate) fn describe(
+        &self,
+        shared: &BTreeMap<String, MethodMetadata>,
+        requested: Option<&str>,
+    ) -> Vec<MethodMetadata> {
+        let mut metadata = shared.clone();
+        if let Ok(version) = self.resolve(requested) {
+            metadata.retain(|name, _| !self.is_versioned(name));
+            metadata.extend(
+                version
+                    .metadata
+                    .iter()
+                    .map(|(name, method)| (name.clone(), method.clone())),
+            );
+        }
+
+        metadata.into_values().collect()
+    }
+
+    /// Find the version that a call asks for, or else the default one.
+    fn resolve(&self, requested: Option<&str>) -> Result<&ApiVersion, Error> {
+        let version = requested.or(self.default.as_deref());
+        let message = match version {
+            Some(version) => match self.versions.get(version) {
+                Some(methods) => return Ok(methods),
+                None => format!("Unsupported API version: {}.", version),
+            },
+            None => String::from("No API version was selected."),
+        };
+
+        Err(Error {
+            code: ErrorCode::ServerError(UNSUPPORTED_API_VERSION_ERROR_CODE),
+            message,
+            data: Some(json!({
+                "supported": self.versions.keys().map(String::as_str).collect::<Vec<_>>(),
+            })),
+        })
+    }
+}
@@ -0,0 +1,254 @@
+#![cfg(feature = "derive")]
+
+use std::{collections::HashSet, net::IpAddr};
+
+use witty_jsonrpc::{
+    handler::Session,
+    jsonrpc_core::{serde_json, Result},
+    jsonrpc_pubsub::{Subscriber, SubscriptionId},
+    prelude::*,
+};
+
+/// An API whose arguments are named just like the locals that the `rpc` macro generates, which
+/// must not clash with them.
+#[witty_jsonrpc::rpc]
+pub trait ClashingApi {
+    #[rpc(name = "concat", schema)]
+    fn concat(
+        &self,
+        api: String,
+        server: String,
+        params: String,
+        parse: String,
+        error: Option<String>,
+    ) -> Result<String>;
+
+    #[rpc(name = "count", schema)]
+    fn count(&self, subscribe_api: Vec<u8>, unsubscribe_api: Vec<u8>) -> Result<usize>;
+
+    #[rpc(subscription = "clashes", subscribe, name = "subscribeClashes", schema)]
+    fn subscribe_clashes(&self, subscriber: Subscriber, _meta: String, params: Option<String>);
+
+    #[rpc(
+        subscription = "clashes",
+        unsubscribe,
+        name = "unsubscribeClashes",
+        schema
+    )]
+    fn unsubscribe_clashes(&self, _meta: SubscriptionId) -> Result<bool>;
+}
+
+struct Clashing;
+
+impl ClashingApi for Clashing {
+    fn concat(
+        &self,
+        api: String,
+        server: String,
+        params: String,
+        parse: String,
+        error: Option<String>,
+    ) -> Result<String> {
+        Ok([api, server, params, parse, error.unwrap_or_default()].concat())
+    }
+
+    fn count(&self, subscribe_api: Vec<u8>, unsubscribe_api: Vec<u8>) -> Result<usize> {
+        Ok(subscribe_api.len() + unsubscribe_api.len())
+    }
+
+    fn subscribe_clashes(&self, subscriber: Subscriber, _meta: String, params: Option<String>) {
+        let id = SubscriptionId::String(_meta + &params.unwrap_or_default());
+        subscriber.assign_id(id).ok();
+    }
+
+    fn unsubscribe_clashes(&self, _meta: SubscriptionId) -> Result<bool> {
+        Ok(_meta == SubscriptionId::String(String::from("ab")))
+    }
+}
+
+/// An API whose types can be converted from and into JSON, but cannot describe themselves.
+#[witty_jsonrpc::rpc]
+pub trait PlainApi {
+    #[rpc(name = "first")]
+    fn first(&self, addresses: HashSet<IpAddr>, fallback: Option<IpAddr>)
+        -> Result<Option<IpAddr>>;
+}
+
+struct Plain;
+
+impl PlainApi for Plain {
+    fn first(
+        &self,
+        addresses: HashSet<IpAddr>,
+        fallback: Option<IpAddr>,
+    ) -> Result<Option<IpAddr>> {
+        Ok(addresses.into_iter().min().or(fallback))
+    }
+}
+
+/// Call a method of a server within a session, returning the result of the call.
+fn call(
+    server: &WittyMultiServer,
+    method: &str,
+    params: serde_json::Value,
+    meta: &Session,
+) -> serde_json::Value {
+    let request = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+    let response = server
+        .handle_request_sync(&request.to_string(), meta.clone())
+        .unwrap();
+
+    serde_json::from_str::<serde_json::Value>(&response).unwrap()["result"].take()
+}
+
+#[test]
+fn arguments_do_not_clash_with_generated_code() {
+    let mut server = WittyMultiServer::new();
+    Clashing.register_rpc(&mut server);
+    let meta = Session::mock();
+
+    assert_eq!(
+        call(
+            &server,
+            "concat",
+            serde_json::json!(["a", "b", "c", "d"]),
+            &meta
+        ),
+        serde_json::json!("abcd")
+    );
+    assert_eq!(
+        call(
+            &server,
+            "concat",
+            serde_json::json!(["a", "b", "c", "d", "e"]),
+            &meta
+        ),
+        serde_json::json!("abcde")
+    );
+    assert_eq!(
+        call(&server, "count", serde_json::json!([[1, 2], [3]]), &meta),
+        serde_json::json!(3)
+    );
+}
+
+#[test]
+fn subscription_arguments_do_not_clash_with_generated_code() {
+    let mut server = WittyMultiServer::new();
+    Clashing.register_rpc(&mut server);
+    // Subscriptions can only be cancelled from the session that they belong to
+    let meta = Session::mock();
+
+    assert_eq!(
+        call(
+            &server,
+            "subscribeClashes",
+            serde_json::json!(["a", "b"]),
+            &meta
+        ),
+        serde_json::json!("ab")
+    );
+    assert_eq!(
+        call(
+            &server,
+            "unsubscribeClashes",
+            serde_json::json!(["ab"]),
+            &meta
+        ),
+        serde_json::json!(true)
+    );
+}
+
+#[test]
+fn parameters_and_results_are_described_by_their_types() {
+    let mut server = WittyMultiServer::new();
+    Clashing.register_rpc(&mut server);
+    let describe = |name: &str| {
+        server
+            .describe_api_detailed()
+            .into_iter()
+            .find(|metadata| metadata.name == name)
+            .unwrap()
+    };
+
+    let count = describe("count");
+    let bytes = serde_json::json!({
+        "type": "array",
+        "items": { "type": "integer", "minimum": 0 },
+    });
+    assert_eq!(
+        count.params,
+        vec![
+            ContentDescriptor::new("subscribe_api", bytes.clone()),
+            ContentDescriptor::new("unsubscribe_api", bytes),
+        ]
+    );
+    assert_eq!(
+        count.result,
+        Some(ContentDescriptor::new(
+            "result",
+            serde_json::json!({ "type": "integer", "minimum": 0 })
+        ))
+    );
+
+    let concat = describe("concat");
+    let error = concat.params.last().unwrap();
+    assert!(!error.required);
+    assert_eq!(
+        error.schema,
+        serde_json::json!({ "anyOf": [{ "type": "string" }, { "type": "null" }] })
+    );
+
+    // Subscribe methods have no result of their own
+    let subscribe = describe("subscribeClashes");
+    assert_eq!(subscribe.params.len(), 2);
+    assert_eq!(subscribe.result, None);
+    let unsubscribe = describe("unsubscribeClashes");
+    assert_eq!(
+        unsubscribe.result,
+        Some(ContentDescriptor::new(
+            "result",
+            serde_json::json!({ "type": "boolean" })
+        ))
+    );
+}
+
+#[test]
+fn parameters_and_results_are_described_with_empty_schemas_by_default() {
+    let mut server = WittyMultiServer::new();
+    Plain.register_rpc(&mut server);
+
+    assert_eq!(
+        call(
+            &server,
+            "first",
+            serde_json::json!([["10.0.0.2", "10.0.0.1"]]),
+            &Session::mock()
+        ),
+        serde_json::json!("10.0.0.1")
+    );
+
+    let first = server
+        .describe_api_detailed()
+        .into_iter()
+        .find(|metadata| metadata.name == "first")
+        .unwrap();
+    assert_eq!(
+        first.params,
+        vec![
+            ContentDescriptor::new("addresses", serde_json::json!({})),
+            ContentDescriptor {
+                required: false,
+                ..ContentDescriptor::new("fallback", serde_json::json!({}))
+            },
+        ]
+    );
+    assert_eq!(
+        first.result,
+        Some(ContentDescriptor::new("result", serde_json::json!({})))
+    );
+}
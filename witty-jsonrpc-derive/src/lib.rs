@@ -0,0 +1,679 @@
+//! # witty-jsonrpc-derive
+//!
+//! Procedural macros for exposing Rust traits and types as `witty-jsonrpc` APIs.
+//!
+//! These are re-exported by `witty-jsonrpc` when its `derive` feature is enabled, so they are
+//! better used through there, e.g. as `#[witty_jsonrpc::rpc]`.
+
+#![deny(rust_2018_idioms)]
+#![deny(missing_docs)]
+
+use std::collections::BTreeMap;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{quote, quote_spanned};
+use syn::{
+    parse_macro_input, parse_quote, spanned::Spanned, Attribute, FnArg, Ident, ImplItem, Item,
+    LitStr, Pat, ReturnType, Signature, TraitItem, Type,
+};
+
+/// Expose the methods of a trait or an `impl` block as JSON-RPC methods and subscriptions.
+///
+/// Every method annotated with `#[rpc]` is registered onto a server by the `register_rpc` method
+/// that this macro adds to the trait or `impl` block, which takes `self` by value and can be
+/// used with any `Server<H>`:
+///
+/// ```ignore
+/// #[witty_jsonrpc::rpc]
+/// pub trait NodeApi {
+///     #[rpc(name = "getBlock")]
+///     fn get_block(&self, hash: String) -> jsonrpc_core::Result<Block>;
+///
+///     #[rpc(subscription = "blocks", subscribe, name = "subscribeBlocks")]
+///     fn subscribe_blocks(&self, subscriber: Subscriber, verbose: Option<bool>);
+///
+///     #[rpc(subscription = "blocks", unsubscribe, name = "unsubscribeBlocks")]
+///     fn unsubscribe_blocks(&self, id: SubscriptionId) -> jsonrpc_core::Result<bool>;
+/// }
+///
+/// Node::new().register_rpc(&mut server);
+/// ```
+///
+/// The arguments of the methods are their JSON-RPC parameters, which can be passed either by
+/// position or by name, and must be owned types that implement `Deserialize`. Methods must take
+/// `&self` and return either a `jsonrpc_core::Result<T>` or a `jsonrpc_core::BoxFuture` of one,
+/// where `T` implements `Serialize`.
+///
+/// The `#[rpc]` attribute of every method supports these options:
+/// - `name = "..."`: the name of the JSON-RPC method, which defaults to the name of the Rust
+///   method.
+/// - `alias = "..."`: another name that the method can be called by, which can be repeated.
+/// - `deprecated_alias = "..."`: an old name that the method can still be called by, but whose
+///   calls are reported as deprecated. This can be repeated too.
+/// - `tag = "..."`: a name for grouping related methods, which can be repeated to add more tags.
+/// - `subscription = "..."`, along with either `subscribe` or `unsubscribe`: marks the method
+///   as one half of a subscription, whose notifications are sent under that name. Every
+///   subscription needs exactly one method of each kind. Subscribe methods take the
+///   `jsonrpc_pubsub::Subscriber` as their first argument, followed by their parameters, and
+///   return nothing. Unsubscribe methods take just the `jsonrpc_pubsub::SubscriptionId`.
+/// - `schema`: describes the parameters and the result of the method with the JSON Schemas of
+///   their types, which must then implement `witty_jsonrpc::metadata::DescribeType`. Without it,
+///   they are described with the empty schema, which accepts anything.
+///
+/// The doc comments, the parameters, the result and the `#[deprecated]` attribute of every method
+/// also become its metadata, which is what describes it in `Server::describe_api_detailed` and in
+/// the OpenRPC document of the server. Parameters of type `Option` are described as not required.
+#[proc_macro_attribute]
+pub fn rpc(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return syn::Error::new(
+            Span::call_site(),
+            "the `rpc` attribute macro takes no arguments",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let item = parse_macro_input!(item as Item);
+
+    expand(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// The different kinds of methods that can be exposed.
+enum Kind {
+    Method,
+    Subscribe(LitStr),
+    Unsubscribe(LitStr),
+}
+
+/// A method annotated with `#[rpc]`, along with its options.
+struct RpcMethod {
+    kind: Kind,
+    name: LitStr,
+    signature: Signature,
+    /// The lines of the doc comments of the method.
+    docs: Vec<String>,
+    tags: Vec<LitStr>,
+    /// The other names of the method, along with whether they are deprecated.
+    aliases: Vec<(LitStr, bool)>,
+    /// Whether the method has a `#[deprecated]` attribute.
+    deprecated: bool,
+    /// Whether the parameters and the result are described with the JSON Schemas of their types.
+    schema: bool,
+}
+
+/// Both halves of a subscription, as found so far.
+#[derive(Default)]
+struct Subscription {
+    subscribe: Option<RpcMethod>,
+    unsubscribe: Option<RpcMethod>,
+}
+
+fn expand(mut item: Item) -> syn::Result<TokenStream2> {
+    let mut methods = Vec::new();
+
+    match &mut item {
+        Item::Trait(item_trait) => {
+            for trait_item in &mut item_trait.items {
+                if let TraitItem::Fn(method) = trait_item {
+                    if let Some(method) = take_rpc_method(&mut method.attrs, &method.sig)? {
+                        methods.push(method);
+                    }
+                }
+            }
+
+            let register = register_rpc(methods, quote!())?;
+            item_trait.items.push(parse_quote!(#register));
+        }
+        Item::Impl(item_impl) if item_impl.trait_.is_none() => {
+            for impl_item in &mut item_impl.items {
+                if let ImplItem::Fn(method) = impl_item {
+                    if let Some(method) = take_rpc_method(&mut method.attrs, &method.sig)? {
+                        methods.push(method);
+                    }
+                }
+            }
+
+            let register = register_rpc(methods, quote!(pub))?;
+            item_impl.items.push(parse_quote!(#register));
+        }
+        item => {
+            return Err(syn::Error::new(
+                item.span(),
+                "the `rpc` attribute macro can only be used on traits and inherent `impl` blocks",
+            ))
+        }
+    }
+
+    Ok(quote!(#item))
+}
+
+/// Remove the `#[rpc]` attribute from a method, and parse its options if it was there.
+fn take_rpc_method(
+    attrs: &mut Vec<Attribute>,
+    signature: &Signature,
+) -> syn::Result<Option<RpcMethod>> {
+    let position = match attrs.iter().position(|attr| attr.path().is_ident("rpc")) {
+        Some(position) => position,
+        None => return Ok(None),
+    };
+    let attr = attrs.remove(position);
+
+    let mut name = None;
+    let mut subscription = None;
+    let mut subscribe = false;
+    let mut unsubscribe = false;
+    let mut schema = false;
+    let mut tags = Vec::new();
+    let mut aliases = Vec::new();
+    if !matches!(attr.meta, syn::Meta::Path(_)) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                name = Some(meta.value()?.parse::<LitStr>()?);
+            } else if meta.path.is_ident("alias") {
+                aliases.push((meta.value()?.parse::<LitStr>()?, false));
+            } else if meta.path.is_ident("deprecated_alias") {
+                aliases.push((meta.value()?.parse::<LitStr>()?, true));
+            } else if meta.path.is_ident("tag") {
+                tags.push(meta.value()?.parse::<LitStr>()?);
+            } else if meta.path.is_ident("subscription") {
+                subscription = Some(meta.value()?.parse::<LitStr>()?);
+            } else if meta.path.is_ident("subscribe") {
+                subscribe = true;
+            } else if meta.path.is_ident("unsubscribe") {
+                unsubscribe = true;
+            } else if meta.path.is_ident("schema") {
+                schema = true;
+            } else {
+                return Err(meta.error("unsupported `rpc` option"));
+            }
+
+            Ok(())
+        })?;
+    }
+
+    let kind = match (subscription, subscribe, unsubscribe) {
+        (None, false, false) => Kind::Method,
+        (Some(subscription), true, false) => Kind::Subscribe(subscription),
+        (Some(subscription), false, true) => Kind::Unsubscribe(subscription),
+        (Some(_), false, false) => {
+            return Err(syn::Error::new(
+                attr.span(),
+                "subscription methods must be marked as either `subscribe` or `unsubscribe`",
+            ))
+        }
+        (None, _, _) => {
+            return Err(syn::Error::new(
+                attr.span(),
+                "`subscribe` and `unsubscribe` methods must name their `subscription`",
+            ))
+        }
+        (Some(_), true, true) => {
+            return Err(syn::Error::new(
+                attr.span(),
+                "methods cannot be both `subscribe` and `unsubscribe`",
+            ))
+        }
+    };
+
+    if !aliases.is_empty() && !matches!(kind, Kind::Method) {
+        return Err(syn::Error::new(
+            attr.span(),
+            "only methods can have aliases, not subscriptions",
+        ));
+    }
+
+    check_signature(signature)?;
+
+    Ok(Some(RpcMethod {
+        kind,
+        name: name.unwrap_or_else(|| LitStr::new(&signature.ident.to_string(), signature.span())),
+        signature: signature.clone(),
+        docs: docs(attrs),
+        tags,
+        aliases,
+        deprecated: attrs.iter().any(|attr| attr.path().is_ident("deprecated")),
+        schema,
+    }))
+}
+
+/// Extract the lines of the doc comments found among some attributes.
+fn docs(attrs: &[Attribute]) -> Vec<String> {
+    attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                value:
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(line),
+                        ..
+                    }),
+                ..
+            }) => Some(line.value().trim().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Tell whether a type is an `Option`, i.e. whether the parameter can be left out.
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "Option"),
+        _ => false,
+    }
+}
+
+/// Make sure that a method can be exposed, i.e. that it takes `&self` and is not `async` nor
+/// generic.
+fn check_signature(signature: &Signature) -> syn::Result<()> {
+    if let Some(asyncness) = signature.asyncness {
+        return Err(syn::Error::new(
+            asyncness.span(),
+            "`async` methods cannot be exposed, return a `jsonrpc_core::BoxFuture` instead",
+        ));
+    }
+    if !signature.generics.params.is_empty() {
+        return Err(syn::Error::new(
+            signature.generics.span(),
+            "generic methods cannot be exposed",
+        ));
+    }
+    match signature.receiver() {
+        Some(receiver) if receiver.reference.is_some() && receiver.mutability.is_none() => Ok(()),
+        _ => Err(syn::Error::new(
+            signature.span(),
+            "exposed methods must take `&self`",
+        )),
+    }
+}
+
+/// The names and types of the arguments of a method, other than `self`.
+fn arguments(signature: &Signature) -> syn::Result<Vec<(Ident, Type)>> {
+    signature
+        .inputs
+        .iter()
+        .filter_map(|input| match input {
+            FnArg::Receiver(_) => None,
+            FnArg::Typed(argument) => Some(argument),
+        })
+        .map(|argument| {
+            let name = match &*argument.pat {
+                Pat::Ident(pat) => pat.ident.clone(),
+                pat => {
+                    return Err(syn::Error::new(
+                        pat.span(),
+                        "the arguments of exposed methods must be plain identifiers",
+                    ))
+                }
+            };
+            if let Type::Reference(reference) = &*argument.ty {
+                return Err(syn::Error::new(
+                    reference.span(),
+                    "the arguments of exposed methods must be owned types",
+                ));
+            }
+
+            Ok((name, (*argument.ty).clone()))
+        })
+        .collect()
+}
+
+/// Generate the code that parses the parameters of a call into the provided arguments, returning
+/// an invalid params error from the enclosing function or closure if that fails.
+fn parse_arguments(params: &Ident, arguments: &[(Ident, Type)]) -> TokenStream2 {
+    let idents = arguments.iter().map(|(ident, _)| ident);
+    let types = arguments.iter().map(|(_, ty)| ty);
+    let names = arguments
+        .iter()
+        .map(|(ident, _)| LitStr::new(ident.to_string().trim_start_matches("r#"), ident.span()));
+
+    quote! {
+        let (#(#idents,)*): (#(#types,)*) =
+            ::witty_jsonrpc::typed::parse_params(#params, &[#(#names),*])?;
+    }
+}
+
+/// Create the identifier of a local of the generated code, which cannot clash with the arguments of
+/// the exposed methods.
+fn local(name: &str) -> Ident {
+    Ident::new(name, Span::mixed_site())
+}
+
+/// Generate the `register_rpc` method, which adds all the exposed methods to a server.
+fn register_rpc(methods: Vec<RpcMethod>, visibility: TokenStream2) -> syn::Result<TokenStream2> {
+    let mut registrations = Vec::new();
+    let mut subscriptions = BTreeMap::<String, Subscription>::new();
+
+    for method in methods {
+        match &method.kind {
+            Kind::Method => registrations.push(register_method(&method)?),
+            Kind::Subscribe(subscription) | Kind::Unsubscribe(subscription) => {
+                let entry = subscriptions.entry(subscription.value()).or_default();
+                let half = match method.kind {
+                    Kind::Subscribe(_) => &mut entry.subscribe,
+                    _ => &mut entry.unsubscribe,
+                };
+                if half.is_some() {
+                    return Err(syn::Error::new(
+                        method.signature.span(),
+                        "every subscription needs exactly one `subscribe` and one `unsubscribe` \
+                         method",
+                    ));
+                }
+                *half = Some(method);
+            }
+        }
+    }
+
+    for subscription in subscriptions.into_values() {
+        match subscription {
+            Subscription {
+                subscribe: Some(subscribe),
+                unsubscribe: Some(unsubscribe),
+            } => registrations.push(register_subscription(&subscribe, &unsubscribe)?),
+            Subscription {
+                subscribe: Some(method),
+                unsubscribe: None,
+            }
+            | Subscription {
+                subscribe: None,
+                unsubscribe: Some(method),
+            } => {
+                return Err(syn::Error::new(
+                    method.signature.span(),
+                    "every subscription needs exactly one `subscribe` and one `unsubscribe` \
+                     method",
+                ))
+            }
+            Subscription {
+                subscribe: None,
+                unsubscribe: None,
+            } => {}
+        }
+    }
+
+    let api = local("api");
+    let server = local("server");
+    let body = if registrations.is_empty() {
+        quote!(let _ = (self, #server);)
+    } else {
+        quote! {
+            let #api = ::std::sync::Arc::new(self);
+            #(#registrations)*
+        }
+    };
+
+    Ok(quote! {
+        /// Add all the JSON-RPC methods and subscriptions exposed through the `rpc` attribute
+        /// macro to a server.
+        // Deprecated methods are still exposed, just flagged as such in their metadata
+        #[allow(deprecated)]
+        #visibility fn register_rpc<WittyHandler, WittyServer>(self, #server: &mut WittyServer)
+        where
+            Self: Sized + Send + Sync + 'static,
+            WittyHandler: ::witty_jsonrpc::handler::Handler,
+            WittyServer: ::witty_jsonrpc::server::Server<WittyHandler>,
+        {
+            #body
+        }
+    })
+}
+
+/// Generate the code that sets the metadata of a method, out of its doc comments and its
+/// parameters and result.
+///
+/// Following the conventions of Rust documentation, the first paragraph of the doc comments is
+/// taken as the summary of the method, and all of them as its description. The parameters and the
+/// result are only described with the schemas of their types if the method is marked with
+/// `schema`.
+fn describe_method(method: &RpcMethod, arguments: &[(Ident, Type)]) -> TokenStream2 {
+    let name = &method.name;
+    let summary = method
+        .docs
+        .iter()
+        .take_while(|line| !line.is_empty())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let description = method.docs.join("\n").trim().to_string();
+    let summary = optional_text(&summary);
+    let description = optional_text(&description);
+    let params = arguments.iter().map(|(ident, ty)| {
+        let name = ident.to_string();
+        let name = name.trim_start_matches("r#");
+
+        if method.schema {
+            quote! {
+                ::witty_jsonrpc::metadata::ContentDescriptor {
+                    required: !<#ty as ::witty_jsonrpc::metadata::DescribeType>::optional(),
+                    ..::witty_jsonrpc::metadata::ContentDescriptor::new(
+                        #name,
+                        <#ty as ::witty_jsonrpc::metadata::DescribeType>::schema(),
+                    )
+                }
+            }
+        } else {
+            let required = !is_option(ty);
+
+            quote! {
+                ::witty_jsonrpc::metadata::ContentDescriptor {
+                    name: ::std::string::String::from(#name),
+                    required: #required,
+                    ..::std::default::Default::default()
+                }
+            }
+        }
+    });
+    // Subscribe methods return nothing, as their results are sent through the subscriber
+    let result = match (&method.kind, &method.signature.output) {
+        (Kind::Method | Kind::Unsubscribe(_), ReturnType::Type(_, output)) if method.schema => {
+            quote! {
+                ::std::option::Option::Some(
+                    ::witty_jsonrpc::typed::describe_result::<_, #output>(),
+                )
+            }
+        }
+        (Kind::Method | Kind::Unsubscribe(_), ReturnType::Type(..)) => quote! {
+            ::std::option::Option::Some(::witty_jsonrpc::metadata::ContentDescriptor {
+                name: ::std::string::String::from("result"),
+                ..::std::default::Default::default()
+            })
+        },
+        _ => quote!(::std::option::Option::None),
+    };
+
+    let deprecated = method.deprecated;
+    let tags = &method.tags;
+    let server = local("server");
+
+    quote! {
+        {
+            let mut metadata = ::witty_jsonrpc::metadata::MethodMetadata::new(#name);
+            metadata.summary = #summary;
+            metadata.description = #description;
+            metadata.params = ::std::vec![#(#params),*];
+            metadata.result = #result;
+            metadata.deprecated = #deprecated;
+            metadata.tags = ::std::vec![#(::std::string::String::from(#tags)),*];
+            ::witty_jsonrpc::server::Server::set_method_metadata(#server, metadata);
+        }
+    }
+}
+
+/// Generate an `Option<String>` expression out of some text, which is `None` if it is empty.
+fn optional_text(text: &str) -> TokenStream2 {
+    if text.is_empty() {
+        quote!(::std::option::Option::None)
+    } else {
+        quote!(::std::option::Option::Some(::std::string::String::from(#text)))
+    }
+}
+
+/// Generate the code that adds a single JSON-RPC method to a server.
+fn register_method(method: &RpcMethod) -> syn::Result<TokenStream2> {
+    let name = &method.name;
+    let ident = &method.signature.ident;
+    let arguments = arguments(&method.signature)?;
+    let api = local("api");
+    let server = local("server");
+    let params = local("params");
+    let parse = parse_arguments(&params, &arguments);
+    let idents = arguments.iter().map(|(ident, _)| ident);
+    let describe = describe_method(method, &arguments);
+    let aliases = method.aliases.iter().map(|(alias, deprecated)| {
+        let deprecation = if *deprecated {
+            quote!(::std::option::Option::Some(
+                ::std::default::Default::default()
+            ))
+        } else {
+            quote!(::std::option::Option::None)
+        };
+
+        quote! {
+            ::witty_jsonrpc::server::Server::add_alias(#server, #alias, #name, #deprecation);
+        }
+    });
+
+    Ok(quote_spanned! {method.signature.span()=>
+        #describe
+        {
+            let #api = ::std::sync::Arc::clone(&#api);
+            ::witty_jsonrpc::server::Server::add_method(
+                #server,
+                #name,
+                move |#params: ::witty_jsonrpc::jsonrpc_core::Params| {
+                    let #api = ::std::sync::Arc::clone(&#api);
+
+                    async move {
+                        #parse
+                        let output = Self::#ident(&*#api, #(#idents),*);
+                        let result =
+                            ::witty_jsonrpc::typed::MethodOutput::into_future(output).await?;
+
+                        ::witty_jsonrpc::typed::result_to_value(result)
+                    }
+                },
+            );
+        }
+        #(#aliases)*
+    })
+}
+
+/// Generate the code that adds a subscription to a server.
+fn register_subscription(
+    subscribe: &RpcMethod,
+    unsubscribe: &RpcMethod,
+) -> syn::Result<TokenStream2> {
+    let notification = match &subscribe.kind {
+        Kind::Subscribe(notification) => notification,
+        _ => unreachable!(),
+    };
+
+    let subscribe_name = &subscribe.name;
+    let subscribe_ident = &subscribe.signature.ident;
+    let mut subscribe_arguments = arguments(&subscribe.signature)?.into_iter();
+    let subscriber = match subscribe_arguments.next() {
+        Some((subscriber, _)) => subscriber,
+        None => {
+            return Err(syn::Error::new(
+                subscribe.signature.span(),
+                "`subscribe` methods must take the `Subscriber` as their first argument",
+            ))
+        }
+    };
+    let subscribe_arguments = subscribe_arguments.collect::<Vec<_>>();
+    let api = local("api");
+    let server = local("server");
+    let subscribe_api = local("subscribe_api");
+    let unsubscribe_api = local("unsubscribe_api");
+    let parse_params = local("parse");
+    let error = local("error");
+    let params = local("params");
+    let meta = local("meta");
+    let parse = parse_arguments(&params, &subscribe_arguments);
+    let subscribe_idents = subscribe_arguments
+        .iter()
+        .map(|(ident, _)| ident)
+        .collect::<Vec<_>>();
+    let describe_subscribe = describe_method(subscribe, &subscribe_arguments);
+
+    let unsubscribe_name = &unsubscribe.name;
+    let unsubscribe_ident = &unsubscribe.signature.ident;
+    let unsubscribe_arguments = arguments(&unsubscribe.signature)?;
+    let id = match &unsubscribe_arguments[..] {
+        [(id, _)] => id.clone(),
+        _ => {
+            return Err(syn::Error::new(
+                unsubscribe.signature.span(),
+                "`unsubscribe` methods must take the `SubscriptionId` as their only argument",
+            ))
+        }
+    };
+
+    let describe_unsubscribe = describe_method(unsubscribe, &unsubscribe_arguments);
+
+    Ok(quote_spanned! {subscribe.signature.span()=>
+        #describe_subscribe
+        #describe_unsubscribe
+        {
+            let #subscribe_api = ::std::sync::Arc::clone(&#api);
+            let #unsubscribe_api = ::std::sync::Arc::clone(&#api);
+            ::witty_jsonrpc::server::Server::add_subscription(
+                #server,
+                #notification,
+                (
+                    #subscribe_name,
+                    move |#params: ::witty_jsonrpc::jsonrpc_core::Params,
+                          #meta: <WittyHandler as ::witty_jsonrpc::handler::Handler>::Metadata,
+                          #subscriber: ::witty_jsonrpc::jsonrpc_pubsub::Subscriber| {
+                        // The metadata is not passed on to the exposed methods
+                        let _ = #meta;
+                        let #parse_params = move || -> ::witty_jsonrpc::jsonrpc_core::Result<_> {
+                            #parse
+
+                            Ok((#(#subscribe_idents,)*))
+                        };
+
+                        match #parse_params() {
+                            Ok((#(#subscribe_idents,)*)) => {
+                                Self::#subscribe_ident(
+                                    &*#subscribe_api,
+                                    #subscriber,
+                                    #(#subscribe_idents),*
+                                );
+                            }
+                            Err(#error) => {
+                                #subscriber.reject(#error).ok();
+                            }
+                        }
+                    },
+                ),
+                (
+                    #unsubscribe_name,
+                    move |#id: ::witty_jsonrpc::jsonrpc_pubsub::SubscriptionId,
+                          #meta: ::std::option::Option<
+                              <WittyHandler as ::witty_jsonrpc::handler::Handler>::Metadata,
+                          >| {
+                        let _ = #meta;
+                        let output = Self::#unsubscribe_ident(&*#unsubscribe_api, #id);
+
+                        async move {
+                            let result =
+                                ::witty_jsonrpc::typed::MethodOutput::into_future(output).await?;
+
+                            ::witty_jsonrpc::typed::result_to_value(result)
+                        }
+                    },
+                ),
+            );
+        }
+    })
+}